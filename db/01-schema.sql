@@ -1,30 +1,105 @@
 CREATE SCHEMA IF NOT EXISTS fhir;
 
-CREATE TYPE fhir.GENDER AS ENUM ('FEMALE', 'MALE', 'OTHER', 'UNKNOWN');
+CREATE TABLE IF NOT EXISTS fhir.resource (
+    resource_type TEXT NOT NULL,
+    id            UUID NOT NULL,
+    data          JSONB,
+    created_at    TEXT NOT NULL DEFAULT (EXTRACT(EPOCH FROM NOW()) * 1000)::BIGINT::TEXT,
+    PRIMARY KEY (resource_type, id)
+);
+
+CREATE TABLE IF NOT EXISTS fhir.search_string (
+    resource_type TEXT NOT NULL,
+    resource_id   UUID NOT NULL,
+    param         TEXT NOT NULL,
+    value         TEXT NOT NULL,
+    period_start  TIMESTAMPTZ,
+    period_end    TIMESTAMPTZ,
+    FOREIGN KEY (resource_type, resource_id) REFERENCES fhir.resource (resource_type, id)
+        ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS fhir.search_token (
+    resource_type TEXT NOT NULL,
+    resource_id   UUID NOT NULL,
+    param         TEXT NOT NULL,
+    system        TEXT,
+    code          TEXT NOT NULL,
+    FOREIGN KEY (resource_type, resource_id) REFERENCES fhir.resource (resource_type, id)
+        ON DELETE CASCADE
+);
 
-CREATE TABLE IF NOT EXISTS fhir.patient (
-    id         UUID PRIMARY KEY NOT NULL,
-    data       JSONB,
-    birthdate  TEXT,
-    gender     fhir.GENDER,
-    created_at TEXT             NOT NULL DEFAULT (EXTRACT(EPOCH FROM NOW()) * 1000)::BIGINT::TEXT
+CREATE TABLE IF NOT EXISTS fhir.search_date (
+    resource_type TEXT NOT NULL,
+    resource_id   UUID NOT NULL,
+    param         TEXT NOT NULL,
+    -- NULL means open ended, single dates have the same start and end
+    value_start   TEXT,
+    value_end     TEXT,
+    FOREIGN KEY (resource_type, resource_id) REFERENCES fhir.resource (resource_type, id)
+        ON DELETE CASCADE
 );
 
-CREATE TABLE IF NOT EXISTS fhir.patient_name (
-    patient      UUID NOT NULL REFERENCES fhir.patient (id),
-    patient_name TEXT NOT NULL,
-    period_start TIMESTAMPTZ,
-    period_end   TIMESTAMPTZ
+CREATE TABLE IF NOT EXISTS fhir.search_reference (
+    resource_type TEXT NOT NULL,
+    resource_id   UUID NOT NULL,
+    param         TEXT NOT NULL,
+    target_type   TEXT NOT NULL,
+    target_id     TEXT NOT NULL,
+    FOREIGN KEY (resource_type, resource_id) REFERENCES fhir.resource (resource_type, id)
+        ON DELETE CASCADE
 );
 
 CREATE TABLE IF NOT EXISTS fhir.id_list (
     id UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid()
 );
 
-CREATE INDEX idx_patient_birthdate ON fhir.patient (birthdate);
-CREATE INDEX idx_patient_gender ON fhir.patient (gender);
-CREATE INDEX idx_patient_pagination ON fhir.patient (created_at, id);
-CREATE INDEX idx_patient_name_patient ON fhir.patient_name (patient);
-CREATE INDEX idx_patient_name_patient_name ON fhir.patient_name (patient_name);
-CREATE INDEX idx_patient_name_period_start ON fhir.patient_name (period_start);
-CREATE INDEX idx_patient_name_period_end ON fhir.patient_name (period_end);
+-- named API keys of clients, only the SHA-256 of the key is stored
+CREATE TABLE IF NOT EXISTS fhir.api_key (
+    id         UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid(),
+    -- the client_id, keeps the quotas and the audit trail when a key is replaced
+    name       TEXT NOT NULL,
+    key_hash   BYTEA NOT NULL UNIQUE,
+    scopes     TEXT[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- NULL means the key doesn't expire
+    expires_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ
+);
+
+-- a name has successive keys, but only one that isn't revoked
+CREATE UNIQUE INDEX IF NOT EXISTS api_key_name_idx ON fhir.api_key (name) WHERE revoked_at IS NULL;
+
+-- append-only audit trail of all requests to protected routes, see fhir.reject_audit_change
+CREATE TABLE IF NOT EXISTS fhir.audit_event (
+    id            UUID PRIMARY KEY NOT NULL,
+    recorded      TIMESTAMPTZ NOT NULL,
+    -- create, read, update, delete or search
+    interaction   TEXT NOT NULL,
+    -- name of the operation, e.g. merge
+    operation     TEXT,
+    resource_type TEXT NOT NULL,
+    resource_id   TEXT,
+    query         TEXT,
+    -- NULL if the caller couldn't be authenticated
+    actor         TEXT,
+    client_id     TEXT,
+    -- keyed pseudonym of the IP address of the caller
+    ip            TEXT,
+    patient_ids   TEXT[] NOT NULL,
+    status        INT NOT NULL,
+    -- reason given for a break-the-glass access to restricted records
+    break_glass   TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_resource_pagination ON fhir.resource (resource_type, created_at, id);
+CREATE INDEX IF NOT EXISTS idx_search_string_resource ON fhir.search_string (resource_type, resource_id, param);
+CREATE INDEX IF NOT EXISTS idx_search_string_value ON fhir.search_string (resource_type, param, value);
+CREATE INDEX IF NOT EXISTS idx_search_token_resource ON fhir.search_token (resource_type, resource_id, param);
+CREATE INDEX IF NOT EXISTS idx_search_token_code ON fhir.search_token (resource_type, param, code);
+CREATE INDEX IF NOT EXISTS idx_search_date_resource ON fhir.search_date (resource_type, resource_id, param);
+CREATE INDEX IF NOT EXISTS idx_search_date_value ON fhir.search_date (resource_type, param, value_start, value_end);
+CREATE INDEX IF NOT EXISTS idx_search_reference_resource ON fhir.search_reference (resource_type, resource_id, param);
+CREATE INDEX IF NOT EXISTS idx_search_reference_target ON fhir.search_reference (target_type, target_id);
+CREATE INDEX IF NOT EXISTS idx_audit_event_recorded ON fhir.audit_event (recorded, id);
+CREATE INDEX IF NOT EXISTS idx_audit_event_patient ON fhir.audit_event USING GIN (patient_ids);
@@ -0,0 +1,30 @@
+-- Moves the patients of fhir.patient, from before resources were stored generically, into
+-- fhir.resource. New DBs don't have fhir.patient, so this does nothing there.
+-- Upgrading an existing DB, in this order:
+--   1. 01-schema.sql
+--   2. DROP EXTENSION patient; CREATE EXTENSION resource;
+--   3. this script, it can be run again and skips patients that were already moved
+--   4. `FhirDemo reindex`, which builds the search index of the moved patients
+-- fhir.patient and fhir.patient_name are kept and can be dropped once the patients are checked.
+DO
+$$
+BEGIN
+    IF TO_REGCLASS('fhir.patient') IS NULL THEN
+        RETURN;
+    END IF;
+
+    INSERT INTO fhir.resource (resource_type, id, data, created_at)
+    SELECT 'Patient',
+           p.id,
+           JSONB_SET(COALESCE(p.data, '{}'::JSONB) || JSONB_BUILD_OBJECT('id', p.id::TEXT),
+                     '{meta}',
+                     COALESCE(p.data -> 'meta', '{}'::JSONB) ||
+                     JSONB_BUILD_OBJECT(
+                             'versionId', '1',
+                             'lastUpdated', TO_CHAR(NOW() AT TIME ZONE 'UTC',
+                                                    'YYYY-MM-DD"T"HH24:MI:SS.MS"Z"'))),
+           p.created_at
+    FROM fhir.patient p
+    ON CONFLICT (resource_type, id) DO NOTHING;
+END;
+$$;
@@ -1 +1 @@
-CREATE EXTENSION patient;
+CREATE EXTENSION resource;
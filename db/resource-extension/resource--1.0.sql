@@ -0,0 +1,373 @@
+CREATE OR REPLACE FUNCTION fhir.get_resource(p_resource_type TEXT, p_resource_id UUID)
+    RETURNS JSONB
+    LANGUAGE sql
+AS
+$$
+SELECT data
+FROM fhir.resource
+WHERE resource_type = p_resource_type
+  AND id = p_resource_id;
+$$;
+
+-- Replaces the search index of the resource, see fhir.upsert_resource for its entries.
+CREATE OR REPLACE FUNCTION fhir.replace_search_index(p_resource_type TEXT,
+                                                     p_resource_id UUID,
+                                                     search_index JSONB)
+    RETURNS VOID
+    LANGUAGE plpgsql
+AS
+$$
+BEGIN
+    DELETE FROM fhir.search_string
+    WHERE resource_type = p_resource_type AND resource_id = p_resource_id;
+    DELETE FROM fhir.search_token
+    WHERE resource_type = p_resource_type AND resource_id = p_resource_id;
+    DELETE FROM fhir.search_date
+    WHERE resource_type = p_resource_type AND resource_id = p_resource_id;
+    DELETE FROM fhir.search_reference
+    WHERE resource_type = p_resource_type AND resource_id = p_resource_id;
+
+    INSERT INTO fhir.search_string (resource_type, resource_id, param, value, period_start, period_end)
+    SELECT p_resource_type, p_resource_id, e ->> 'param', e ->> 'value',
+           (e ->> 'periodStart')::TIMESTAMPTZ, (e ->> 'periodEnd')::TIMESTAMPTZ
+    FROM JSONB_ARRAY_ELEMENTS(COALESCE(search_index, '[]'::JSONB)) e
+    WHERE e ->> 'kind' = 'string'
+      AND e ->> 'value' IS NOT NULL;
+
+    INSERT INTO fhir.search_token (resource_type, resource_id, param, system, code)
+    SELECT p_resource_type, p_resource_id, e ->> 'param', e ->> 'system', e ->> 'code'
+    FROM JSONB_ARRAY_ELEMENTS(COALESCE(search_index, '[]'::JSONB)) e
+    WHERE e ->> 'kind' = 'token'
+      AND e ->> 'code' IS NOT NULL;
+
+    INSERT INTO fhir.search_date (resource_type, resource_id, param, value_start, value_end)
+    SELECT p_resource_type, p_resource_id, e ->> 'param', e ->> 'start', e ->> 'end'
+    FROM JSONB_ARRAY_ELEMENTS(COALESCE(search_index, '[]'::JSONB)) e
+    WHERE e ->> 'kind' = 'date'
+      AND (e ->> 'start' IS NOT NULL OR e ->> 'end' IS NOT NULL);
+
+    INSERT INTO fhir.search_reference (resource_type, resource_id, param, target_type, target_id)
+    SELECT p_resource_type, p_resource_id, e ->> 'param', e ->> 'targetType', e ->> 'targetId'
+    FROM JSONB_ARRAY_ELEMENTS(COALESCE(search_index, '[]'::JSONB)) e
+    WHERE e ->> 'kind' = 'reference'
+      AND e ->> 'targetType' IS NOT NULL
+      AND e ->> 'targetId' IS NOT NULL;
+END;
+$$;
+
+-- Inserts or overwrites a resource and replaces its search index.
+-- Sets meta.versionId, counting the writes of the resource, and meta.lastUpdated.
+-- search_index is an array of index entries, each tagged with its kind:
+--   {"kind": "string", "param": ..., "value": ..., "periodStart": ..., "periodEnd": ...}
+--   {"kind": "token", "param": ..., "system": ..., "code": ...}
+--   {"kind": "date", "param": ..., "start": ..., "end": ...}
+--   {"kind": "reference", "param": ..., "targetType": ..., "targetId": ...}
+CREATE OR REPLACE FUNCTION fhir.upsert_resource(p_resource_type TEXT,
+                                                resource_data JSONB,
+                                                search_index JSONB)
+    RETURNS UUID
+    LANGUAGE plpgsql
+AS
+$$
+DECLARE
+    v_id      UUID;
+    v_version INTEGER;
+BEGIN
+    v_id = (resource_data ->> 'id')::UUID;
+
+    IF v_id IS NULL THEN
+        SELECT fhir.get_uuid()
+        INTO v_id;
+
+        SELECT JSONB_SET(resource_data, '{id}', TO_JSONB(v_id))
+        INTO resource_data;
+    END IF;
+
+    SELECT (data -> 'meta' ->> 'versionId')::INTEGER
+    INTO v_version
+    FROM fhir.resource
+    WHERE resource_type = p_resource_type
+      AND id = v_id
+        FOR UPDATE;
+
+    resource_data = JSONB_SET(resource_data, '{meta}',
+                              COALESCE(resource_data -> 'meta', '{}'::JSONB) ||
+                              JSONB_BUILD_OBJECT(
+                                      'versionId', (COALESCE(v_version, 0) + 1)::TEXT,
+                                      'lastUpdated', TO_CHAR(NOW() AT TIME ZONE 'UTC',
+                                                             'YYYY-MM-DD"T"HH24:MI:SS.MS"Z"')));
+
+    INSERT INTO fhir.resource (resource_type, id, data)
+    VALUES (p_resource_type, v_id, resource_data)
+    ON CONFLICT (resource_type, id) DO UPDATE SET data = resource_data;
+
+    PERFORM fhir.replace_search_index(p_resource_type, v_id, search_index);
+
+    RETURN v_id;
+END;
+$$;
+
+-- Deletes a resource together with its search index.
+-- Returns whether the resource existed.
+CREATE OR REPLACE FUNCTION fhir.delete_resource(p_resource_type TEXT, p_resource_id UUID)
+    RETURNS BOOLEAN
+    LANGUAGE sql
+AS
+$$
+WITH d AS (DELETE FROM fhir.resource
+           WHERE resource_type = p_resource_type
+             AND id = p_resource_id
+           RETURNING id)
+SELECT EXISTS (SELECT 1 FROM d);
+$$;
+
+-- Returns the resources (as 'Type/id') that reference the resource, apart from itself and
+-- provenance of it.
+CREATE OR REPLACE FUNCTION fhir.referenced_by(p_resource_type TEXT, p_resource_id UUID)
+    RETURNS TEXT[]
+    LANGUAGE sql
+    STABLE
+AS
+$$
+SELECT COALESCE(ARRAY_AGG(DISTINCT r.resource_type || '/' || r.resource_id), '{}')
+FROM fhir.search_reference r
+WHERE r.target_type = p_resource_type
+  AND r.target_id = p_resource_id::TEXT
+  AND NOT (r.resource_type = p_resource_type AND r.resource_id = p_resource_id)
+  -- provenance records the history of its targets and outlives them
+  AND NOT (r.resource_type = 'Provenance' AND r.param = 'target');
+$$;
+
+-- Returns the references (as 'Type/id') for which no resource exists.
+CREATE OR REPLACE FUNCTION fhir.missing_references(p_references TEXT[])
+    RETURNS TEXT[]
+    LANGUAGE sql
+    STABLE
+AS
+$$
+SELECT COALESCE(ARRAY_AGG(ref), '{}')
+FROM UNNEST(p_references) ref
+WHERE NOT EXISTS (SELECT 1
+                  FROM fhir.resource r
+                  WHERE r.resource_type = SPLIT_PART(ref, '/', 1)
+                    AND r.id::TEXT = SPLIT_PART(ref, '/', 2));
+$$;
+
+-- Locks the referenced resources (as 'Type/id') that exist, so that they can't be deleted until
+-- the transaction ends, and returns the references for which no resource exists.
+CREATE OR REPLACE FUNCTION fhir.lock_references(p_references TEXT[])
+    RETURNS TEXT[]
+    LANGUAGE plpgsql
+AS
+$$
+BEGIN
+    PERFORM 1
+    FROM fhir.resource r
+             JOIN UNNEST(p_references) ref
+                  ON r.resource_type = SPLIT_PART(ref, '/', 1)
+                      AND r.id::TEXT = SPLIT_PART(ref, '/', 2)
+        FOR SHARE OF r;
+
+    RETURN fhir.missing_references(p_references);
+END;
+$$;
+
+CREATE OR REPLACE FUNCTION fhir.get_uuid()
+    RETURNS UUID
+    LANGUAGE sql
+AS
+$$
+INSERT INTO fhir.id_list DEFAULT
+VALUES
+RETURNING (id);
+$$;
+
+-- Compares two partial dates (YYYY, YYYY-MM, YYYY-MM-DD or a full dateTime) at the
+-- precision of the less precise one, at most day precision.
+-- Returns -1, 0 or 1 like a comparator.
+CREATE OR REPLACE FUNCTION fhir.compare_partial_date(a TEXT, b TEXT)
+    RETURNS INTEGER
+    LANGUAGE sql
+    IMMUTABLE
+AS
+$$
+SELECT CASE
+           WHEN LEFT(a, LEAST(LENGTH(a), LENGTH(b), 10)) < LEFT(b, LEAST(LENGTH(a), LENGTH(b), 10)) THEN -1
+           WHEN LEFT(a, LEAST(LENGTH(a), LENGTH(b), 10)) > LEFT(b, LEAST(LENGTH(a), LENGTH(b), 10)) THEN 1
+           ELSE 0
+           END;
+$$;
+
+-- Checks a single search criterion against the index of one resource.
+-- criterion has the same kinds as the search index:
+--   {"kind": "string", "param": ..., "value": ...} matches substrings of values valid right now
+--   {"kind": "token", "param": ..., "system": ..., "code": ...} system is optional
+--   {"kind": "date", "param": ..., "from": ..., "until": ...} matches overlapping ranges,
+--                                                             both bounds are inclusive and optional
+--   {"kind": "reference", "param": ..., "targetType": ..., "targetId": ...} targetType is optional
+CREATE OR REPLACE FUNCTION fhir.matches_criterion(p_resource_type TEXT,
+                                                  p_resource_id UUID,
+                                                  criterion JSONB)
+    RETURNS BOOLEAN
+    LANGUAGE sql
+    STABLE
+AS
+$$
+SELECT CASE criterion ->> 'kind'
+           WHEN 'string' THEN EXISTS (SELECT 1
+                                      FROM fhir.search_string s
+                                      WHERE s.resource_type = p_resource_type
+                                        AND s.resource_id = p_resource_id
+                                        AND s.param = criterion ->> 'param'
+                                        AND s.value LIKE '%' || (criterion ->> 'value') || '%'
+                                        -- ignore values that are not in use right now
+                                        AND (s.period_start IS NULL OR s.period_start <= NOW())
+                                        AND (s.period_end IS NULL OR s.period_end > NOW()))
+           WHEN 'token' THEN EXISTS (SELECT 1
+                                     FROM fhir.search_token t
+                                     WHERE t.resource_type = p_resource_type
+                                       AND t.resource_id = p_resource_id
+                                       AND t.param = criterion ->> 'param'
+                                       AND t.code = criterion ->> 'code'
+                                       AND (criterion ->> 'system' IS NULL
+                                           OR t.system = criterion ->> 'system'))
+           WHEN 'date' THEN EXISTS (SELECT 1
+                                    FROM fhir.search_date d
+                                    WHERE d.resource_type = p_resource_type
+                                      AND d.resource_id = p_resource_id
+                                      AND d.param = criterion ->> 'param'
+                                      -- the indexed range overlaps the searched range
+                                      AND (criterion ->> 'from' IS NULL
+                                          OR d.value_end IS NULL
+                                          OR fhir.compare_partial_date(d.value_end, criterion ->> 'from') >= 0)
+                                      AND (criterion ->> 'until' IS NULL
+                                          OR d.value_start IS NULL
+                                          OR fhir.compare_partial_date(d.value_start, criterion ->> 'until') <= 0))
+           WHEN 'reference' THEN EXISTS (SELECT 1
+                                         FROM fhir.search_reference r
+                                         WHERE r.resource_type = p_resource_type
+                                           AND r.resource_id = p_resource_id
+                                           AND r.param = criterion ->> 'param'
+                                           AND (criterion ->> 'targetType' IS NULL
+                                               OR r.target_type = criterion ->> 'targetType')
+                                           AND r.target_id = criterion ->> 'targetId')
+           WHEN 'id' THEN p_resource_id::TEXT = criterion ->> 'id'
+           ELSE FALSE
+           END;
+$$;
+
+-- Whether the resource has one of the security labels, or references a patient that has one.
+-- p_labels is an array of {"system": ..., "code": ...}.
+CREATE OR REPLACE FUNCTION fhir.is_restricted(p_resource_type TEXT, p_resource_id UUID, p_labels JSONB)
+    RETURNS BOOLEAN
+    LANGUAGE sql
+    STABLE
+AS
+$$
+SELECT EXISTS (SELECT 1
+               FROM fhir.search_token t
+                        JOIN JSONB_ARRAY_ELEMENTS(p_labels) l
+                             ON t.system IS NOT DISTINCT FROM l ->> 'system'
+                                 AND t.code = l ->> 'code'
+               WHERE t.param = '_security'
+                 AND ((t.resource_type = p_resource_type AND t.resource_id = p_resource_id)
+                   -- links between patients don't restrict the other patient
+                   OR (p_resource_type <> 'Patient'
+                       AND t.resource_type = 'Patient'
+                       AND t.resource_id::TEXT IN (SELECT r.target_id
+                                                   FROM fhir.search_reference r
+                                                   WHERE r.resource_type = p_resource_type
+                                                     AND r.resource_id = p_resource_id
+                                                     AND r.target_type = 'Patient'))));
+$$;
+
+CREATE TYPE fhir.SEARCH_OPERATOR AS ENUM ('AND', 'OR');
+
+-- Searches resources of one type.
+-- search_data: {"criteria": [...], "operator": "AND"|"OR", "compartment": ..., "count": ..., "iterationKey": ...,
+--               "lastId": ...}
+-- Without criteria, all resources of the type match. If given, the compartment criterion has to match as well,
+-- independent of the operator.
+-- Returns an array of {"id": ..., "resource": ..., "iterationKey": ...}, ordered by creation time and ID.
+CREATE OR REPLACE FUNCTION fhir.search_resources(p_resource_type TEXT, search_data JSONB)
+    RETURNS JSONB
+    LANGUAGE plpgsql
+AS
+$$
+DECLARE
+    v_criteria      JSONB;
+    v_operator      fhir.SEARCH_OPERATOR;
+    v_compartment   JSONB;
+    v_hidden        JSONB;
+    v_iteration_key TEXT;
+    v_last_id       UUID;
+    v_count         INTEGER;
+    result          JSONB;
+BEGIN
+    SELECT COALESCE(search_data -> 'criteria', '[]'::JSONB),
+           search_data ->> 'iterationKey',
+           (search_data ->> 'lastId')::UUID,
+           COALESCE((search_data ->> 'count')::INTEGER, 30),
+           COALESCE((search_data ->> 'operator')::fhir.SEARCH_OPERATOR, 'AND'::fhir.SEARCH_OPERATOR),
+           search_data -> 'compartment',
+           search_data -> 'hidden'
+    INTO v_criteria, v_iteration_key, v_last_id, v_count, v_operator, v_compartment, v_hidden;
+
+    IF v_count > 100 THEN
+        v_count = 100;
+    END IF;
+
+    WITH d AS (SELECT r.data, r.created_at, r.id
+               FROM fhir.resource r
+               WHERE r.resource_type = p_resource_type
+                 AND (JSONB_ARRAY_LENGTH(v_criteria) = 0
+                   OR (v_operator = 'AND'::fhir.SEARCH_OPERATOR
+                       AND NOT EXISTS (SELECT 1
+                                       FROM JSONB_ARRAY_ELEMENTS(v_criteria) c
+                                       WHERE NOT fhir.matches_criterion(r.resource_type, r.id, c)))
+                   OR (v_operator = 'OR'::fhir.SEARCH_OPERATOR
+                       AND EXISTS (SELECT 1
+                                   FROM JSONB_ARRAY_ELEMENTS(v_criteria) c
+                                   WHERE fhir.matches_criterion(r.resource_type, r.id, c))))
+                 AND (v_compartment IS NULL OR fhir.matches_criterion(r.resource_type, r.id, v_compartment))
+                 AND (v_hidden IS NULL OR NOT fhir.is_restricted(r.resource_type, r.id, v_hidden))
+                 -- pagination
+                 AND (v_iteration_key IS NULL
+                   OR v_iteration_key < r.created_at
+                   OR (v_iteration_key = r.created_at
+                       AND (v_last_id IS NULL
+                           OR v_last_id < r.id)))
+               ORDER BY r.created_at, r.id
+               LIMIT v_count)
+    SELECT COALESCE(JSONB_AGG(JSONB_BUILD_OBJECT('id', d.id, 'resource', d.data,
+                                                 'iterationKey', d.created_at)
+                              ORDER BY d.created_at, d.id), '[]'::JSONB)
+    INTO result
+    FROM d;
+
+    RETURN result;
+END;
+$$;
+
+-- The audit trail must never be changed, only appended to.
+CREATE OR REPLACE FUNCTION fhir.reject_audit_change()
+    RETURNS TRIGGER
+    LANGUAGE plpgsql
+AS
+$$
+BEGIN
+    RAISE EXCEPTION 'fhir.audit_event is append-only';
+END;
+$$;
+
+CREATE OR REPLACE TRIGGER audit_event_append_only
+    BEFORE UPDATE OR DELETE
+    ON fhir.audit_event
+    FOR EACH ROW
+EXECUTE FUNCTION fhir.reject_audit_change();
+
+CREATE OR REPLACE TRIGGER audit_event_no_truncate
+    BEFORE TRUNCATE
+    ON fhir.audit_event
+    FOR EACH STATEMENT
+EXECUTE FUNCTION fhir.reject_audit_change();
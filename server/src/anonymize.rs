@@ -0,0 +1,384 @@
+pub mod anonymize {
+    use crate::consent::consent::SecurityLabel;
+    use crate::db::db::Db;
+    use crate::merge::merge::replaced_by;
+    use crate::model::model::{Patient, SearchOperator};
+    use crate::resource::resource::{ResourceSearch, SearchCriterion, StoredResource};
+    use futures::stream::{self, Stream};
+    use ring::hmac;
+    use ring::rand::{SecureRandom, SystemRandom};
+    use serde_json::{json, Value};
+    use std::env;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use tracing::{error, warn};
+
+    /// Drops the direct identifiers of a patient, generalizes the quasi-identifiers and replaces
+    /// the IDs with pseudonyms, so that records of the same patient can still be linked. IDs in
+    /// references get the same pseudonyms, `meta` and extensions are left out.
+    const DEFAULT_PROFILE: &str = "id=pseudonym,identifier.value=pseudonym,name=drop,\
+                                   telecom=drop,photo=drop,text=drop,contact=drop,\
+                                   address.line=drop,address.text=drop,\
+                                   address.postalCode=truncate:3,birthDate=year,\
+                                   deceased.deceasedDateTime=year,meta=drop,contained=drop,\
+                                   extension=drop,modifierExtension=drop,\
+                                   link.other.reference=reference,link.other.display=drop,\
+                                   generalPractitioner.reference=reference,\
+                                   generalPractitioner.display=drop,\
+                                   managingOrganization.reference=reference,\
+                                   managingOrganization.display=drop";
+    /// Security label of exported patients.
+    const PSEUDONYMIZED_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/v3-ObservationValue";
+    const PSEUDONYMIZED_CODE: &str = "PSEUDED";
+    /// Patients read from the DB at a time.
+    const PAGE_SIZE: u32 = 100;
+    /// Bytes of the HMAC in a pseudonym.
+    const PSEUDONYM_LEN: usize = 16;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum AnonymizeAction {
+        /// The element is left out.
+        Drop,
+        /// Dates are cut to their year.
+        Year,
+        /// Strings are cut to their first characters.
+        Truncate(usize),
+        /// Strings are replaced with their keyed pseudonym.
+        Pseudonym,
+        /// The ID in references is replaced with its pseudonym, e.g. `Patient/<pseudonym>`.
+        Reference,
+    }
+
+    /// What happens to an element of patients, e.g. `address.postalCode=truncate:3`.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct AnonymizeRule {
+        /// Names of the elements from the patient down, arrays are descended into.
+        path: Vec<String>,
+        action: AnonymizeAction,
+    }
+
+    impl FromStr for AnonymizeRule {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let (path, action) = s.trim()
+                                  .split_once('=')
+                                  .ok_or(format!("Invalid anonymization rule: {}", s))?;
+            let action = match action.trim().split_once(':') {
+                None if action.trim() == "drop" => AnonymizeAction::Drop,
+                None if action.trim() == "year" => AnonymizeAction::Year,
+                None if action.trim() == "pseudonym" => AnonymizeAction::Pseudonym,
+                None if action.trim() == "reference" => AnonymizeAction::Reference,
+                Some(("truncate", len)) => match len.parse() {
+                    Ok(len) => AnonymizeAction::Truncate(len),
+                    Err(_) => return Err(format!("Invalid length in anonymization rule: {}", s)),
+                },
+                _ => return Err(format!("Unknown anonymization action: {}", s)),
+            };
+            let path: Vec<String> = path.trim().split('.').map(str::to_string).collect();
+            if path.iter().any(|element| element.is_empty()) {
+                return Err(format!("Invalid path in anonymization rule: {}", s));
+            }
+            return Ok(Self { path, action });
+        }
+    }
+
+    fn parse_profile(profile: &str) -> Result<Vec<AnonymizeRule>, String> {
+        return profile.split(',')
+                      .filter(|r| !r.trim().is_empty())
+                      .map(AnonymizeRule::from_str)
+                      .collect();
+    }
+
+    /// Applies the anonymization profile to patients for the `$anonymize` export.
+    #[derive(Clone)]
+    pub struct Anonymizer {
+        rules: Arc<Vec<AnonymizeRule>>,
+        /// Key of the pseudonyms.
+        key: hmac::Key,
+    }
+
+    impl Anonymizer {
+        pub fn new(rules: Vec<AnonymizeRule>, key: &[u8]) -> Self {
+            return Self { rules: Arc::new(rules), key: hmac::Key::new(hmac::HMAC_SHA256, key) };
+        }
+
+        /// The profile `FHIR_ANONYMIZE_PROFILE`, comma separated rules, by default
+        /// [DEFAULT_PROFILE]. Pseudonyms are keyed with `FHIR_PSEUDONYM_KEY`. Without one, a random
+        /// key is used, so the pseudonyms of a patient change with every restart.
+        pub fn from_env() -> Self {
+            let profile = env::var_os("FHIR_ANONYMIZE_PROFILE")
+                .map(|val| val.into_string().unwrap())
+                .unwrap_or(DEFAULT_PROFILE.to_string());
+            let rules = match parse_profile(&profile) {
+                Ok(rules) => rules,
+                Err(e) => {
+                    error!(?e, "Invalid FHIR_ANONYMIZE_PROFILE");
+                    panic!("Invalid FHIR_ANONYMIZE_PROFILE");
+                }
+            };
+            return match env::var_os("FHIR_PSEUDONYM_KEY") {
+                Some(key) => Anonymizer::new(rules, key.into_string().unwrap().as_bytes()),
+                None => {
+                    warn!("FHIR_PSEUDONYM_KEY is not set, pseudonyms change with every restart");
+                    let mut key = [0u8; 32];
+                    SystemRandom::new().fill(&mut key).unwrap();
+                    Anonymizer::new(rules, &key)
+                }
+            };
+        }
+
+        /// The same value always has the same pseudonym under a key, but without the key it
+        /// can't be told which value that is.
+        pub fn pseudonym(&self, value: &str) -> String {
+            let tag = hmac::sign(&self.key, value.as_bytes());
+            return tag.as_ref()[..PSEUDONYM_LEN].iter().map(|b| format!("{:02x}", b)).collect();
+        }
+
+        /// The reference with the pseudonym of its ID. The version is left out, it isn't exported
+        /// either.
+        fn pseudonym_reference(&self, reference: &str) -> String {
+            let reference = reference.split_once("/_history/").map_or(reference, |(r, _)| r);
+            return match reference.rsplit_once('/') {
+                Some((target, id)) => format!("{}/{}", target, self.pseudonym(id)),
+                None => self.pseudonym(reference),
+            };
+        }
+
+        /// The patient as it is exported, labeled as pseudonymized.
+        pub fn anonymize(&self, patient: &Patient) -> Value {
+            let mut value = serde_json::to_value(patient).unwrap();
+            for rule in self.rules.iter() {
+                self.apply(&mut value, &rule.path, rule.action);
+            }
+            let Value::Object(object) = &mut value else {
+                return value;
+            };
+            let meta = object.entry("meta").or_insert(json!({}));
+            if let Some(meta) = meta.as_object_mut() {
+                let security = meta.entry("security").or_insert(json!([]));
+                if let Some(security) = security.as_array_mut() {
+                    security.push(json!({
+                        "system": PSEUDONYMIZED_SYSTEM,
+                        "code": PSEUDONYMIZED_CODE,
+                    }));
+                }
+            }
+            object.insert("resourceType".to_string(), json!(Patient::RESOURCE_TYPE));
+            return value;
+        }
+
+        fn apply(&self, value: &mut Value, path: &[String], action: AnonymizeAction) {
+            let Some((element, rest)) = path.split_first() else {
+                return;
+            };
+            match value {
+                Value::Array(items) => {
+                    items.iter_mut().for_each(|item| self.apply(item, path, action));
+                }
+                Value::Object(object) if rest.is_empty() => match action {
+                    AnonymizeAction::Drop => {
+                        object.remove(element);
+                    }
+                    _ => {
+                        if let Some(child) = object.get_mut(element) {
+                            self.replace(child, action);
+                        }
+                    }
+                },
+                Value::Object(object) => {
+                    if let Some(child) = object.get_mut(element) {
+                        self.apply(child, rest, action);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        fn replace(&self, value: &mut Value, action: AnonymizeAction) {
+            match value {
+                Value::String(s) => match action {
+                    AnonymizeAction::Drop => {}
+                    AnonymizeAction::Year => *s = s.chars().take(4).collect(),
+                    AnonymizeAction::Truncate(len) => *s = s.chars().take(len).collect(),
+                    AnonymizeAction::Pseudonym => *s = self.pseudonym(s),
+                    AnonymizeAction::Reference => *s = self.pseudonym_reference(s),
+                },
+                Value::Array(items) => items.iter_mut().for_each(|item| self.replace(item, action)),
+                _ => {}
+            }
+        }
+
+        /// The anonymized patients as NDJSON, read page by page while the response is sent.
+        /// Patients that were merged into others are left out, their records live on in the
+        /// patient that replaces them.
+        pub fn export(&self,
+                      db: Arc<Db>,
+                      compartment: Option<SearchCriterion>,
+                      hidden: Vec<SecurityLabel>,
+        ) -> impl Stream<Item = Result<String, String>> + Send + 'static {
+            let anonymizer = self.clone();
+            let first = ResourceSearch {
+                criteria: Vec::new(),
+                operator: SearchOperator::And,
+                compartment,
+                hidden,
+                count: PAGE_SIZE,
+                iteration_key: None,
+                last_id: None,
+            };
+            return stream::try_unfold(Some(first), move |search| {
+                let db = db.clone();
+                let anonymizer = anonymizer.clone();
+                async move {
+                    let Some(mut search) = search else {
+                        return Ok(None);
+                    };
+                    let hits = db.search_resources::<Patient>(&search)
+                                 .await
+                                 .map_err(|e| {
+                                     error!(?e, "Unknown error when exporting patients");
+                                     e.to_string()
+                                 })?;
+                    let lines: String = hits.iter()
+                                            .filter(|hit| replaced_by(&hit.resource).is_none())
+                                            .map(|hit| anonymizer.anonymize(&hit.resource))
+                                            .map(|patient| format!("{}\n", patient))
+                                            .collect();
+                    let next = match hits.last() {
+                        Some(last) if hits.len() as u32 == PAGE_SIZE => {
+                            search.iteration_key = Some(last.iteration_key.clone());
+                            search.last_id = Some(last.id.clone());
+                            Some(search)
+                        }
+                        _ => None,
+                    };
+                    return Ok(Some((lines, next)));
+                }
+            });
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use speculoos::assert_that;
+        use speculoos::prelude::ResultAssertions;
+
+        fn patient() -> Patient {
+            return serde_json::from_value(json!({
+                "id": "f3e1c2a0-7a8b-4c1e-9d7e-0c5b2a1f4e6d",
+                "identifier": [{"system": "urn:mrn", "value": "12345"}],
+                "name": [{"family": "Meier", "given": ["Anna"]}],
+                "telecom": [{"value": "+41 44 123 45 67"}],
+                "birthDate": "1984-03-12",
+                "address": {"line": ["Bahnhofstrasse 1"], "city": "Zuerich", "postalCode": "8001"},
+            })).unwrap();
+        }
+
+        #[test]
+        fn test_parse_rules() {
+            assert_that!(AnonymizeRule::from_str("address.postalCode=truncate:3"))
+                .is_ok()
+                .is_equal_to(AnonymizeRule {
+                    path: vec!["address".to_string(), "postalCode".to_string()],
+                    action: AnonymizeAction::Truncate(3),
+                });
+            assert_that!(AnonymizeRule::from_str("birthDate=year"))
+                .is_ok()
+                .is_equal_to(AnonymizeRule {
+                    path: vec!["birthDate".to_string()],
+                    action: AnonymizeAction::Year,
+                });
+            assert_that!(parse_profile(DEFAULT_PROFILE)).is_ok();
+            for invalid in ["name", "name=hide", "address..line=drop", "birthDate=truncate:x"] {
+                assert_that!(AnonymizeRule::from_str(invalid)).is_err();
+            }
+        }
+
+        #[test]
+        fn test_merged_patient() {
+            let anonymizer = Anonymizer::new(parse_profile(DEFAULT_PROFILE).unwrap(), b"key");
+            let source = "0b7c5a4e-3f2d-4e1b-8a9c-6d5e4f3a2b1c";
+            let practitioner = "5d2c1b0a-9e8f-4a7b-b6c5-d4e3f2a1b0c9";
+            let mut merged = patient();
+            merged.meta = serde_json::from_value(json!({
+                "versionId": "3",
+                "lastUpdated": "2024-05-01T10:00:00Z",
+                "security": [{"system": "http://terminology.hl7.org/CodeSystem/v3-Confidentiality",
+                              "code": "R"}],
+            })).unwrap();
+            let linked: Patient = serde_json::from_value(json!({
+                "link": [{"other": {"reference": format!("Patient/{}", source),
+                                    "display": "Anna Meier"},
+                          "type": "REPLACES"}],
+                "generalPractitioner": [{"reference": format!("Practitioner/{}/_history/2",
+                                                              practitioner)}],
+                "managingOrganization": {"reference": "Organization/hospital"},
+                "deceased": {"deceasedBoolean": null, "deceasedDateTime": "2024-04-30T08:15:00Z"},
+                "extension": [{"url": "http://example.com/birthPlace", "valueString": "Bern"}],
+            })).unwrap();
+            merged.link = linked.link;
+            merged.general_practitioner = linked.general_practitioner;
+            merged.managing_organization = linked.managing_organization;
+            merged.deceased = linked.deceased;
+            merged.extension = linked.extension;
+            let value = anonymizer.anonymize(&merged);
+
+            assert_that!(value["link"][0]["other"]["reference"])
+                .is_equal_to(json!(format!("Patient/{}", anonymizer.pseudonym(source))));
+            assert_that!(value["link"][0]["other"]["display"]).is_equal_to(Value::Null);
+            assert_that!(value["link"][0]["type"]).is_equal_to(json!("REPLACES"));
+            assert_that!(value["generalPractitioner"][0]["reference"])
+                .is_equal_to(json!(format!("Practitioner/{}", anonymizer.pseudonym(practitioner))));
+            assert_that!(value["managingOrganization"]["reference"])
+                .is_equal_to(json!(format!("Organization/{}", anonymizer.pseudonym("hospital"))));
+            assert_that!(value["deceased"]["deceasedDateTime"]).is_equal_to(json!("2024"));
+            assert_that!(value["extension"]).is_equal_to(Value::Null);
+            // only the label of the export is left of meta
+            assert_that!(value["meta"]).is_equal_to(json!({"security": [{
+                "system": PSEUDONYMIZED_SYSTEM,
+                "code": PSEUDONYMIZED_CODE,
+            }]}));
+            let exported = value.to_string();
+            for plain in [source, practitioner, "hospital", "Anna", "Bern", "2024-05-01"] {
+                assert_that!(exported.contains(plain)).is_equal_to(false);
+            }
+        }
+
+        #[test]
+        fn test_pseudonyms() {
+            let anonymizer = Anonymizer::new(Vec::new(), b"key");
+            assert_that!(anonymizer.pseudonym("12345")).is_equal_to(anonymizer.pseudonym("12345"));
+            assert_that!(anonymizer.pseudonym("12345").len()).is_equal_to(2 * PSEUDONYM_LEN);
+            assert_that!(anonymizer.pseudonym("12345"))
+                .is_not_equal_to(anonymizer.pseudonym("12346"));
+            assert_that!(Anonymizer::new(Vec::new(), b"other").pseudonym("12345"))
+                .is_not_equal_to(anonymizer.pseudonym("12345"));
+        }
+
+        #[test]
+        fn test_anonymize() {
+            let anonymizer = Anonymizer::new(parse_profile(DEFAULT_PROFILE).unwrap(), b"key");
+            let value = anonymizer.anonymize(&patient());
+
+            assert_that!(value["resourceType"]).is_equal_to(json!("Patient"));
+            let id = "f3e1c2a0-7a8b-4c1e-9d7e-0c5b2a1f4e6d";
+            assert_that!(value["id"]).is_equal_to(json!(anonymizer.pseudonym(id)));
+            assert_that!(value["identifier"][0]["system"]).is_equal_to(json!("urn:mrn"));
+            assert_that!(value["identifier"][0]["value"])
+                .is_equal_to(json!(anonymizer.pseudonym("12345")));
+            assert_that!(value["name"]).is_equal_to(Value::Null);
+            assert_that!(value["telecom"]).is_equal_to(Value::Null);
+            assert_that!(value["birthDate"]).is_equal_to(json!("1984"));
+            assert_that!(value["address"]["line"]).is_equal_to(Value::Null);
+            assert_that!(value["address"]["city"]).is_equal_to(json!("Zuerich"));
+            assert_that!(value["address"]["postalCode"]).is_equal_to(json!("800"));
+            assert_that!(value["meta"]["security"]).is_equal_to(json!([{
+                "system": PSEUDONYMIZED_SYSTEM,
+                "code": PSEUDONYMIZED_CODE,
+            }]));
+            assert_that!(anonymizer.anonymize(&patient())).is_equal_to(value);
+        }
+    }
+}
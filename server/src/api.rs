@@ -1,28 +1,136 @@
 pub mod api {
-    use crate::auth::auth::Auth;
-    use crate::cache::cache::Cache;
-    use crate::db::db::Db;
-    use crate::model::model::{Patient, PatientSearch, PatientStub};
+    use crate::anonymize::anonymize::Anonymizer;
+    use crate::api_key::api_key::ApiKeys;
+    use crate::audit::audit::{self, Audit, AuditedPatients};
+    use crate::auth::auth::{Auth, Identity};
+    use crate::consent::consent::{Clearance, Consent};
+    use crate::cors::cors::Cors;
+    use crate::redaction::redaction::Redaction;
+    use crate::cache::cache::{Cache, CacheState};
+    use crate::db::db::{Changed, Db, Referenced};
+    use crate::discovery::discovery::{Discovery, SmartConfiguration};
+    use crate::integrity::integrity::{ReferenceIntegrity, Unresolvable};
+    use crate::limits::limits::Limits;
+    use crate::matching::matching::{match_bundle, MatchGrade, Matcher};
+    use crate::merge::merge::{check_mergeable, merge_patients, merge_provenance};
+    use crate::rate_limit::rate_limit::RateLimit;
+    use crate::provenance::provenance::{self, for_version, write_provenance, X_PROVENANCE};
+    use crate::model::model::{
+        AuditEventSearch,
+        Bundle,
+        Encounter,
+        EncounterSearch,
+        Observation,
+        ObservationSearch,
+        Patient,
+        PatientMatchParams,
+        PatientMerge,
+        PatientSearch,
+        PatientStub,
+        Provenance,
+        ProvenanceSearch,
+    };
+    use crate::resource::resource::{
+        patient_ids,
+        resource_id,
+        ResourceSearch,
+        SearchHit,
+        StoredResource,
+    };
     use crate::setid::SetId;
-    use axum::extract::{ConnectInfo, Path, Query, State};
+    use crate::smart::smart::{Access, Interaction};
+    use axum::extract::{ConnectInfo, DefaultBodyLimit, Path, Query, State};
     use axum::http::StatusCode;
     use axum::middleware::{from_fn, from_fn_with_state, Next};
-    use axum::routing::{get, put};
+    use axum::routing::{delete, get, post, put};
     use axum::{Extension, Json, Router};
+    use chrono::{DateTime, SecondsFormat};
     use axum_core::body::Body;
     use axum_core::extract::Request;
     use axum_core::response::Response;
-    use http::Method;
+    use http::header::{CONTENT_TYPE, WARNING};
+    use http::{HeaderMap, HeaderValue, Method, Uri};
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use serde_json::Value;
     use std::net::SocketAddr;
     use std::str::FromStr;
     use std::sync::Arc;
-    use tower_http::cors::{Any, CorsLayer};
     use tracing::{error, info, info_span, Instrument};
     use uuid::Uuid;
 
-    const UPSERT_PATIENT_PATH: &'static str = "/fhir/patient";
-    const SEARCH_PATIENTS_PATH: &'static str = "/fhir/patient";
-    pub const GET_PATIENT_PATH: &'static str = "/fhir/patient/{patient_id}";
+    const UPSERT_PATIENT_PATH: &str = "/fhir/patient";
+    pub const SEARCH_PATIENTS_PATH: &str = "/fhir/patient";
+    pub const GET_PATIENT_PATH: &str = "/fhir/patient/{patient_id}";
+    const DELETE_PATIENT_PATH: &str = "/fhir/patient/{patient_id}";
+    const MERGE_PATIENT_PATH: &str = "/fhir/patient/$merge";
+    pub const MATCH_PATIENT_PATH: &str = "/fhir/patient/$match";
+    const ANONYMIZE_PATIENTS_PATH: &str = "/fhir/patient/$anonymize";
+    const UPSERT_OBSERVATION_PATH: &str = "/fhir/Observation";
+    const SEARCH_OBSERVATIONS_PATH: &str = "/fhir/Observation";
+    const GET_OBSERVATION_PATH: &str = "/fhir/Observation/{observation_id}";
+    const UPSERT_ENCOUNTER_PATH: &str = "/fhir/Encounter";
+    const SEARCH_ENCOUNTERS_PATH: &str = "/fhir/Encounter";
+    const GET_ENCOUNTER_PATH: &str = "/fhir/Encounter/{encounter_id}";
+    const DELETE_ENCOUNTER_PATH: &str = "/fhir/Encounter/{encounter_id}";
+    const SEARCH_PROVENANCES_PATH: &str = "/fhir/Provenance";
+    const GET_PROVENANCE_PATH: &str = "/fhir/Provenance/{provenance_id}";
+    const SEARCH_AUDIT_EVENTS_PATH: &str = "/fhir/AuditEvent";
+    const GET_AUDIT_EVENT_PATH: &str = "/fhir/AuditEvent/{audit_event_id}";
+    const NDJSON_CONTENT_TYPE: &str = "application/fhir+ndjson";
+    const HEALTH_PATH: &str = "/health";
+    const METRICS_PATH: &str = "/metrics";
+    const METADATA_PATH: &str = "/fhir/metadata";
+    /// SMART clients look for the discovery document relative to the FHIR base URL, others at the
+    /// root of the server.
+    const SMART_CONFIGURATION_PATH: &str = "/.well-known/smart-configuration";
+    const FHIR_SMART_CONFIGURATION_PATH: &str = "/fhir/.well-known/smart-configuration";
+
+    /// Resource type and interaction of each route, which the caller's scopes have to allow.
+    /// None for routes that aren't protected.
+    pub fn route_interaction(method: &Method, path: &str) -> Option<(&'static str, Interaction)> {
+        let interaction = match (method, path) {
+            (&Method::GET, SEARCH_PATIENTS_PATH) => (Patient::RESOURCE_TYPE, Interaction::Search),
+            (&Method::GET, GET_PATIENT_PATH) => (Patient::RESOURCE_TYPE, Interaction::Read),
+            // upserts are FHIR updates, which may create the resource
+            (&Method::PUT, UPSERT_PATIENT_PATH) => (Patient::RESOURCE_TYPE, Interaction::Update),
+            (&Method::DELETE, DELETE_PATIENT_PATH) => (Patient::RESOURCE_TYPE, Interaction::Delete),
+            (&Method::POST, MERGE_PATIENT_PATH) => (Patient::RESOURCE_TYPE, Interaction::Update),
+            // $match only reads, even though it is a POST
+            (&Method::POST, MATCH_PATIENT_PATH) => (Patient::RESOURCE_TYPE, Interaction::Search),
+            (&Method::GET, ANONYMIZE_PATIENTS_PATH) => {
+                (Patient::RESOURCE_TYPE, Interaction::Search)
+            }
+            (&Method::GET, SEARCH_OBSERVATIONS_PATH) => {
+                (Observation::RESOURCE_TYPE, Interaction::Search)
+            }
+            (&Method::GET, GET_OBSERVATION_PATH) => (Observation::RESOURCE_TYPE, Interaction::Read),
+            (&Method::PUT, UPSERT_OBSERVATION_PATH) => {
+                (Observation::RESOURCE_TYPE, Interaction::Update)
+            }
+            (&Method::GET, SEARCH_ENCOUNTERS_PATH) => (Encounter::RESOURCE_TYPE, Interaction::Search),
+            (&Method::GET, GET_ENCOUNTER_PATH) => (Encounter::RESOURCE_TYPE, Interaction::Read),
+            (&Method::PUT, UPSERT_ENCOUNTER_PATH) => (Encounter::RESOURCE_TYPE, Interaction::Update),
+            (&Method::DELETE, DELETE_ENCOUNTER_PATH) => {
+                (Encounter::RESOURCE_TYPE, Interaction::Delete)
+            }
+            (&Method::GET, SEARCH_PROVENANCES_PATH) => {
+                (Provenance::RESOURCE_TYPE, Interaction::Search)
+            }
+            (&Method::GET, GET_PROVENANCE_PATH) => (Provenance::RESOURCE_TYPE, Interaction::Read),
+            (&Method::GET, SEARCH_AUDIT_EVENTS_PATH) => (audit::RESOURCE_TYPE, Interaction::Search),
+            (&Method::GET, GET_AUDIT_EVENT_PATH) => (audit::RESOURCE_TYPE, Interaction::Read),
+            _ => return None,
+        };
+        return Some(interaction);
+    }
+
+    #[derive(Serialize, Debug)]
+    struct Health {
+        status: &'static str,
+        db: &'static str,
+        cache: &'static str,
+    }
 
     pub struct Api {
         pub app: Router<()>,
@@ -30,76 +138,593 @@ pub mod api {
 
     impl Api {
         pub fn new(db: Arc<Db>, cache: Cache) -> Self {
-            let auth = Auth::new();
-            let cors = CorsLayer::new()
-                .allow_origin(Any)
-                .allow_methods([Method::GET, Method::POST, Method::PUT, Method::OPTIONS])
-                .allow_headers(Any);
+            let auth = Auth::from_env(ApiKeys::new(db.clone()));
+            return Api::with_auth(db, cache, auth);
+        }
+
+        /// The API authenticating with `auth`, everything else is configured from env.
+        pub fn with_auth(db: Arc<Db>, cache: Cache, auth: Auth) -> Self {
+            let audit = Audit::from_env(db.clone());
+            let consent = Consent::from_env();
+            let redaction = Redaction::from_env();
+            let rate_limit = RateLimit::from_env();
+            let limits = Limits::from_env();
+            let integrity = ReferenceIntegrity::from_env();
+            let matcher = Matcher::from_env();
+            let anonymizer = Anonymizer::from_env();
+            let discovery = Discovery::from_env();
+            let cors = Cors::from_env();
 
             let app = Router::new()
-                .layer(from_fn(tracing_middleware))
-                .layer(from_fn_with_state(auth, Auth::auth_middleware))
                 .route(SEARCH_PATIENTS_PATH, get(Api::search_patient))
-                .route(GET_PATIENT_PATH, get(Api::get_patient))
-                .route_layer(from_fn_with_state(cache, Api::get_patient_cache_layer))
+                .route(GET_PATIENT_PATH, get(Api::get_resource::<Patient>))
+                .route_layer(from_fn_with_state(cache.clone(), Api::cache_layer))
                 .route(UPSERT_PATIENT_PATH, put(Api::upsert_patient))
-                .layer(cors)
-                .layer(Extension(db));
+                .route(DELETE_PATIENT_PATH, delete(Api::delete_resource::<Patient>))
+                .route(MERGE_PATIENT_PATH, post(Api::merge_patients))
+                .route(MATCH_PATIENT_PATH, post(Api::match_patients))
+                .route(ANONYMIZE_PATIENTS_PATH, get(Api::anonymize_patients))
+                .route(SEARCH_OBSERVATIONS_PATH,
+                       get(Api::search_resources::<Observation, ObservationSearch>))
+                .route(GET_OBSERVATION_PATH, get(Api::get_resource::<Observation>))
+                .route(UPSERT_OBSERVATION_PATH, put(Api::upsert_resource::<Observation>))
+                .route(SEARCH_ENCOUNTERS_PATH,
+                       get(Api::search_resources::<Encounter, EncounterSearch>))
+                .route(GET_ENCOUNTER_PATH, get(Api::get_resource::<Encounter>))
+                .route(UPSERT_ENCOUNTER_PATH, put(Api::upsert_resource::<Encounter>))
+                .route(DELETE_ENCOUNTER_PATH, delete(Api::delete_resource::<Encounter>))
+                .route(SEARCH_PROVENANCES_PATH,
+                       get(Api::search_resources::<Provenance, ProvenanceSearch>))
+                .route(GET_PROVENANCE_PATH, get(Api::get_resource::<Provenance>))
+                .route(SEARCH_AUDIT_EVENTS_PATH, get(Api::search_audit_events))
+                .route(GET_AUDIT_EVENT_PATH, get(Api::get_audit_event))
+                // Layers only wrap the routes added before them, the last one added runs first.
+                // Authorization has to run before the cache, so that cached responses are never
+                // served to callers who may not see them. Redaction runs after the cache, which
+                // only holds unredacted responses.
+                // The limits replace the default body limit of the extractors.
+                .layer(DefaultBodyLimit::disable())
+                .layer(from_fn_with_state(limits, Limits::limits_middleware))
+                .layer(from_fn_with_state(redaction, Redaction::redaction_middleware))
+                .layer(from_fn_with_state(consent, Consent::consent_middleware))
+                .layer(from_fn_with_state(rate_limit.clone(), RateLimit::token_middleware))
+                .layer(from_fn_with_state(auth, Auth::auth_middleware))
+                // limits by IP address before authentication, so that guessing tokens counts, too
+                .layer(from_fn_with_state(rate_limit, RateLimit::ip_middleware))
+                // audits rejected requests, too
+                .layer(from_fn_with_state(audit, Audit::audit_middleware))
+                // monitoring and discovery don't need a token
+                .route(HEALTH_PATH, get(Api::health))
+                .route(METRICS_PATH, get(Api::metrics))
+                .route(METADATA_PATH, get(Api::capability_statement))
+                .route(SMART_CONFIGURATION_PATH, get(Api::smart_configuration))
+                .route(FHIR_SMART_CONFIGURATION_PATH, get(Api::smart_configuration))
+                .layer(from_fn(tracing_middleware))
+                .layer(cors.layer())
+                .layer(Extension(db))
+                .layer(Extension(cache))
+                .layer(Extension(integrity))
+                .layer(Extension(matcher))
+                .layer(Extension(anonymizer))
+                .layer(Extension(discovery));
             Self { app }
         }
 
-        async fn get_patient_cache_layer(
+        async fn cache_layer(
             State(cache): State<Cache>,
             request: Request<Body>,
             next: Next,
         ) -> Response<Body> {
-            return cache.get_patient_caching_layer(request, next).await;
+            return cache.caching_layer(request, next).await;
         }
 
-        async fn upsert_patient(Extension(db): Extension<Arc<Db>>,
-                                Json(patient): Json<Patient>,
+        /// `503` if the DB is unavailable. The cache is optional, so it being down only shows
+        /// up as `degraded`.
+        async fn health(Extension(db): Extension<Arc<Db>>,
+                        Extension(cache): Extension<Cache>,
+        ) -> (StatusCode, Json<Health>) {
+            let db_up = db.is_available().await;
+            let cache_state = cache.state();
+            let (status, code) = if !db_up {
+                (StatusCode::SERVICE_UNAVAILABLE, "down")
+            } else if cache_state == CacheState::Up || cache_state == CacheState::Disabled {
+                (StatusCode::OK, "up")
+            } else {
+                (StatusCode::OK, "degraded")
+            };
+            return (status, Json(Health {
+                status: code,
+                db: if db_up { "up" } else { "down" },
+                cache: cache_state.code(),
+            }));
+        }
+
+        /// Metrics in the Prometheus text format.
+        async fn metrics(Extension(cache): Extension<Cache>) -> String {
+            return cache.metrics();
+        }
+
+        async fn capability_statement(Extension(discovery): Extension<Discovery>) -> Json<Value> {
+            return Json(discovery.capability_statement.as_ref().clone());
+        }
+
+        /// `404` if no authorization server is configured.
+        async fn smart_configuration(Extension(discovery): Extension<Discovery>,
+        ) -> Result<Json<SmartConfiguration>, StatusCode> {
+            return discovery.smart.map(Json).ok_or(StatusCode::NOT_FOUND);
+        }
+
+        /// Upserts the resource and drops its cached representation and searches,
+        /// so that the next read returns the new version.
+        /// With patient scopes, both the new and the stored version have to be in the
+        /// compartment of the patient.
+        async fn upsert_resource<R: StoredResource + SetId + Clone>(
+            Extension(db): Extension<Arc<Db>>,
+            Extension(cache): Extension<Cache>,
+            Extension(integrity): Extension<ReferenceIntegrity>,
+            Extension(access): Extension<Access>,
+            Extension(clearance): Extension<Clearance>,
+            Extension(audited): Extension<AuditedPatients>,
+            Json(resource): Json<R>,
         ) -> Result<String, (StatusCode, String)> {
-            let mut pc = patient.clone();
-            pc.set_id(db.as_ref())
+            return Api::write(&db, &cache, integrity, &access, &clearance, &audited, resource, None)
+                .await;
+        }
+
+        /// Writes the resource, together with its provenance if there is one.
+        #[allow(clippy::too_many_arguments)]
+        async fn write<R: StoredResource + SetId + Clone>(db: &Db,
+                                                          cache: &Cache,
+                                                          integrity: ReferenceIntegrity,
+                                                          access: &Access,
+                                                          clearance: &Clearance,
+                                                          audited: &AuditedPatients,
+                                                          resource: R,
+                                                          provenance: Option<Provenance>,
+        ) -> Result<String, (StatusCode, String)> {
+            let id = resource_id(&resource);
+            if !access.allows(id.as_deref(), &resource) {
+                return Err((StatusCode::FORBIDDEN, "Outside of the patient compartment".to_string()));
+            }
+            if let Some(id) = &id {
+                Api::check_stored_access::<R>(db, access, clearance, parse_uuid(id)?).await?;
+            }
+            let mut rc = resource.clone();
+            rc.set_id(db)
               .await
               .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-            return db.upsert_patient(&mut pc)
-                     .await
-                     .map(|uuid| uuid.to_string())
-                     .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+            let upserted = match provenance {
+                Some(mut provenance) => {
+                    provenance.set_id(db)
+                              .await
+                              .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                    db.upsert_with_provenance(&rc, integrity, |id, version| {
+                        return for_version(provenance, R::RESOURCE_TYPE, id, version);
+                    }).await
+                }
+                None => db.upsert_resource(&rc, integrity).await,
+            };
+            let uuid = upserted.map_err(|e| match e.downcast_ref::<Unresolvable>() {
+                Some(unresolvable) => (StatusCode::UNPROCESSABLE_ENTITY, unresolvable.to_string()),
+                None => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            })?;
+            cache.invalidate(R::RESOURCE_TYPE, &[uuid]).await;
+            audited.add(patient_ids(Some(&uuid.to_string()), &rc));
+            return Ok(uuid.to_string());
+        }
+
+        /// Upserts the patient. Every write is recorded as Provenance, based on the one in the
+        /// `X-Provenance` header if the client sent one.
+        /// If enabled, likely duplicates of the patient are reported in `Warning` headers, the
+        /// patient is stored anyway.
+        #[allow(clippy::too_many_arguments)] // one per extractor
+        async fn upsert_patient(Extension(db): Extension<Arc<Db>>,
+                                Extension(cache): Extension<Cache>,
+                                Extension(integrity): Extension<ReferenceIntegrity>,
+                                Extension(matcher): Extension<Matcher>,
+                                Extension(access): Extension<Access>,
+                                Extension(identity): Extension<Identity>,
+                                Extension(clearance): Extension<Clearance>,
+                                Extension(audited): Extension<AuditedPatients>,
+                                request_headers: HeaderMap,
+                                Json(patient): Json<Patient>,
+        ) -> Result<(HeaderMap, String), (StatusCode, String)> {
+            let provenance = write_provenance(request_headers.get(X_PROVENANCE), &identity)
+                .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            let mut headers = HeaderMap::new();
+            // duplicates are other patients, which patient scopes don't allow to see
+            if matcher.warn_on_write && access == Access::All {
+                let hidden = clearance.hidden().to_vec();
+                match matcher.find(db.as_ref(), &patient, None, hidden, 3).await {
+                    Ok(matches) => {
+                        for m in matches.iter().filter(|m| m.grade >= MatchGrade::Probable) {
+                            let warning = format!(
+                                "299 - \"Possible duplicate of Patient/{} (score {:.2})\"",
+                                m.patient.id.as_deref().unwrap_or_default(),
+                                m.score);
+                            if let Ok(value) = HeaderValue::from_str(&warning) {
+                                headers.append(WARNING, value);
+                            }
+                        }
+                    }
+                    Err(e) => error!(?e, "Could not check patient for duplicates"),
+                }
+            }
+            let id = Api::write(&db,
+                                &cache,
+                                integrity,
+                                &access,
+                                &clearance,
+                                &audited,
+                                patient,
+                                Some(provenance)).await?;
+            return Ok((headers, id));
+        }
+
+        /// Scores existing patients against the candidate, see [Matcher].
+        async fn match_patients(Extension(db): Extension<Arc<Db>>,
+                                Extension(matcher): Extension<Matcher>,
+                                Extension(access): Extension<Access>,
+                                Extension(clearance): Extension<Clearance>,
+                                Extension(audited): Extension<AuditedPatients>,
+                                Query(params): Query<PatientMatchParams>,
+                                Json(candidate): Json<Patient>,
+        ) -> Result<Json<Bundle<Patient>>, (StatusCode, String)> {
+            let mut matches = matcher.find(db.as_ref(),
+                                           &candidate,
+                                           access.compartment::<Patient>(),
+                                           clearance.hidden().to_vec(),
+                                           params.count.min(100) as usize)
+                                     .await
+                                     .map_err(|e| {
+                                         error!(?e, "Unknown error when matching patients");
+                                         (StatusCode::INTERNAL_SERVER_ERROR,
+                                          "internal error".to_string())
+                                     })?;
+            if params.only_certain_matches {
+                matches.retain(|m| m.grade == MatchGrade::Certain);
+            }
+            audited.add(matches.iter().filter_map(|m| m.patient.id.clone()));
+            return Ok(Json(match_bundle(matches)));
+        }
+
+        /// Exports the patients the caller may see as NDJSON, anonymized with the profile of the
+        /// [Anonymizer]. The export is streamed, so errors after the first page cut it short.
+        async fn anonymize_patients(Extension(db): Extension<Arc<Db>>,
+                                    Extension(anonymizer): Extension<Anonymizer>,
+                                    Extension(access): Extension<Access>,
+                                    Extension(clearance): Extension<Clearance>,
+        ) -> Response<Body> {
+            let lines = anonymizer.export(db,
+                                          access.compartment::<Patient>(),
+                                          clearance.hidden().to_vec());
+            return Response::builder().header(CONTENT_TYPE, NDJSON_CONTENT_TYPE)
+                                      .body(Body::from_stream(lines))
+                                      .unwrap();
         }
 
         async fn search_patient(Extension(db): Extension<Arc<Db>>,
+                                Extension(access): Extension<Access>,
+                                Extension(clearance): Extension<Clearance>,
+                                Extension(audited): Extension<AuditedPatients>,
                                 Query(params): Query<PatientSearch>,
         ) -> Result<Json<Vec<PatientStub>>, (StatusCode, String)> {
-            return db.search_patient(params)
-                     .await
-                     .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+            let mut search: ResourceSearch = params.into();
+            search.compartment = access.compartment::<Patient>();
+            search.hidden = clearance.hidden().to_vec();
+            let stubs = db.search_patient(search)
+                          .await
+                          .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            audited.add(stubs.iter().map(|stub| stub.id.clone()));
+            return Ok(stubs);
         }
 
-        async fn get_patient(Extension(db): Extension<Arc<Db>>,
-                             Path(patient_id): Path<String>,
-        ) -> Result<Json<Patient>, (StatusCode, String)> {
-            let uuid = match Uuid::from_str(&patient_id) {
+        async fn search_resources<R: StoredResource, S: DeserializeOwned + Into<ResourceSearch>>(
+            Extension(db): Extension<Arc<Db>>,
+            Extension(access): Extension<Access>,
+            Extension(clearance): Extension<Clearance>,
+            Extension(audited): Extension<AuditedPatients>,
+            uri: Uri,
+            Query(params): Query<S>,
+        ) -> Result<Json<Bundle<R>>, (StatusCode, String)> {
+            let mut search: ResourceSearch = params.into();
+            search.compartment = access.compartment::<R>();
+            search.hidden = clearance.hidden().to_vec();
+            let hits = db.search_resources::<R>(&search)
+                         .await
+                         .map_err(|e| {
+                             error!(?e, "Unknown error when querying DB");
+                             (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string())
+                         })?;
+            let next = next_link(&uri, &hits, search.count);
+            for hit in &hits {
+                audited.add(patient_ids(Some(&hit.id), &hit.resource));
+            }
+            let resources = hits.into_iter().map(|hit| hit.resource).collect();
+            return Ok(Json(Bundle::searchset(resources, next)));
+        }
+
+        /// Resources outside of the compartment of a patient scoped caller, and restricted ones
+        /// the caller may not see, are reported as unknown, so that their existence isn't revealed.
+        async fn get_resource<R: StoredResource>(Extension(db): Extension<Arc<Db>>,
+                                                 Extension(access): Extension<Access>,
+                                                 Extension(clearance): Extension<Clearance>,
+                                                 Extension(audited): Extension<AuditedPatients>,
+                                                 Path(resource_id): Path<String>,
+        ) -> Result<Json<R>, (StatusCode, String)> {
+            let uuid = match Uuid::from_str(&resource_id) {
                 Ok(uuid) => uuid,
                 Err(error) => {
                     error!(?error, "Could not parse UUID");
                     return Err((StatusCode::BAD_REQUEST, "UUID format".to_string()));
                 }
             };
-            return db.get_patient(uuid)
+            return db.get_visible_resource(uuid, clearance.hidden())
                      .await
                      .map_err(|e| {
                          if e.downcast_ref::<crate::db::db::NotFound>().is_some() {
-                             info!(?e, "Trying to get non-existent ID {}", patient_id);
+                             info!(?e, "Trying to get non-existent ID {}", resource_id);
                              return (StatusCode::NOT_FOUND, "Unknown UUID".to_string());
                          }
                          error!(?e, "Unknown error when querying DB");
                          return (StatusCode::INTERNAL_SERVER_ERROR,
                                  "internal error".to_string());
                      })
-                     .map(Json);
+                     .and_then(|resource| {
+                         if !access.allows(Some(&resource_id), &resource) {
+                             info!("ID {} is outside of the patient compartment", resource_id);
+                             return Err((StatusCode::NOT_FOUND, "Unknown UUID".to_string()));
+                         }
+                         audited.add(patient_ids(Some(&resource_id), &resource));
+                         return Ok(Json(resource));
+                     });
+        }
+
+        /// Merges the source patient into the target and returns the updated target.
+        /// Needs access to all patients, since it changes two. Restricted patients the caller
+        /// may not see are reported as unknown.
+        #[allow(clippy::too_many_arguments)] // one per extractor
+        async fn merge_patients(Extension(db): Extension<Arc<Db>>,
+                                Extension(cache): Extension<Cache>,
+                                Extension(integrity): Extension<ReferenceIntegrity>,
+                                Extension(access): Extension<Access>,
+                                Extension(clearance): Extension<Clearance>,
+                                Extension(identity): Extension<Identity>,
+                                Extension(audited): Extension<AuditedPatients>,
+                                Json(merge): Json<PatientMerge>,
+        ) -> Result<Json<Patient>, (StatusCode, String)> {
+            if access != Access::All {
+                return Err((StatusCode::FORBIDDEN, "Merging needs user or system scopes".to_string()));
+            }
+            let source_id = parse_uuid(&merge.source_patient)?;
+            let target_id = parse_uuid(&merge.target_patient)?;
+            if source_id == target_id {
+                return Err((StatusCode::BAD_REQUEST,
+                            "Cannot merge a patient into itself".to_string()));
+            }
+            let load = async |id: Uuid| {
+                return db.get_visible_resource::<Patient>(id, clearance.hidden())
+                         .await
+                         .map_err(|e| {
+                             if e.downcast_ref::<crate::db::db::NotFound>().is_some() {
+                                 info!(?e, "Trying to merge non-existent ID {}", id);
+                                 return (StatusCode::NOT_FOUND, format!("Unknown UUID {}", id));
+                             }
+                             error!(?e, "Unknown error when querying DB");
+                             return (StatusCode::INTERNAL_SERVER_ERROR,
+                                     "internal error".to_string());
+                         });
+            };
+            let mut source = load(source_id).await?;
+            let mut target = load(target_id).await?;
+            check_mergeable(&source, &target).map_err(|e| (StatusCode::CONFLICT, e))?;
+
+            merge_patients(&mut source, &mut target);
+            let mut provenance = merge_provenance(&source_id.to_string(),
+                                                  &target_id.to_string(),
+                                                  provenance::agents(&identity, "author"));
+            let stored = async {
+                source.set_id(db.as_ref()).await?;
+                target.set_id(db.as_ref()).await?;
+                provenance.set_id(db.as_ref()).await?;
+                return db.store_merge(&source, &target, &provenance, integrity).await;
+            };
+            if let Err(e) = stored.await {
+                if let Some(changed) = e.downcast_ref::<Changed>() {
+                    info!(?e, "Patient changed during merge of {} into {}", source_id, target_id);
+                    return Err((StatusCode::CONFLICT,
+                                format!("Patient {} was changed during the merge", changed.id)));
+                }
+                if let Some(unresolvable) = e.downcast_ref::<Unresolvable>() {
+                    return Err((StatusCode::UNPROCESSABLE_ENTITY, unresolvable.to_string()));
+                }
+                error!(?e, "Could not store merge of {} into {}", source_id, target_id);
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()));
+            }
+            cache.invalidate(Patient::RESOURCE_TYPE, &[source_id, target_id]).await;
+            audited.add([source_id.to_string(), target_id.to_string()]);
+            return Ok(Json(target));
+        }
+
+        /// Restricted resources the caller may not see are reported as unknown, like reads.
+        async fn delete_resource<R: StoredResource>(Extension(db): Extension<Arc<Db>>,
+                                                    Extension(cache): Extension<Cache>,
+                                                    Extension(access): Extension<Access>,
+                                                    Extension(clearance): Extension<Clearance>,
+                                                    Extension(audited): Extension<AuditedPatients>,
+                                                    Path(resource_id): Path<String>,
+        ) -> Result<StatusCode, (StatusCode, String)> {
+            let uuid = match Uuid::from_str(&resource_id) {
+                Ok(uuid) => uuid,
+                Err(error) => {
+                    error!(?error, "Could not parse UUID");
+                    return Err((StatusCode::BAD_REQUEST, "UUID format".to_string()));
+                }
+            };
+            // loaded for the patients it belongs to
+            match db.get_visible_resource::<R>(uuid, clearance.hidden()).await {
+                Ok(stored) if access.allows(Some(&resource_id), &stored) => {
+                    audited.add(patient_ids(Some(&resource_id), &stored));
+                }
+                Ok(_) => {
+                    return Err((StatusCode::FORBIDDEN,
+                                "Outside of the patient compartment".to_string()));
+                }
+                Err(e) if e.downcast_ref::<crate::db::db::NotFound>().is_some() => {
+                    info!(?e, "Trying to delete non-existent ID {}", resource_id);
+                    return Err((StatusCode::NOT_FOUND, "Unknown UUID".to_string()));
+                }
+                Err(e) => {
+                    error!(?e, "Unknown error when querying DB");
+                    return Err((StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()));
+                }
+            }
+            db.delete_resource::<R>(uuid)
+              .await
+              .map_err(|e| {
+                  if e.downcast_ref::<crate::db::db::NotFound>().is_some() {
+                      info!(?e, "Trying to delete non-existent ID {}", resource_id);
+                      return (StatusCode::NOT_FOUND, "Unknown UUID".to_string());
+                  }
+                  if let Some(referenced) = e.downcast_ref::<Referenced>() {
+                      info!(?e, "Trying to delete referenced ID {}", resource_id);
+                      return (StatusCode::CONFLICT,
+                              format!("Still referenced by {}", referenced.by.join(", ")));
+                  }
+                  error!(?e, "Unknown error when querying DB");
+                  return (StatusCode::INTERNAL_SERVER_ERROR,
+                          "internal error".to_string());
+              })?;
+            cache.invalidate(R::RESOURCE_TYPE, &[uuid]).await;
+            return Ok(StatusCode::NO_CONTENT);
+        }
+
+        /// Searches the audit trail. Patient scoped callers only see the entries of their patient.
+        async fn search_audit_events(Extension(db): Extension<Arc<Db>>,
+                                     Extension(access): Extension<Access>,
+                                     Extension(audited): Extension<AuditedPatients>,
+                                     uri: Uri,
+                                     Query(mut search): Query<AuditEventSearch>,
+        ) -> Result<Json<Bundle<Value>>, (StatusCode, String)> {
+            if let Access::Patient(patient_id) = &access {
+                if search.patient.as_ref().is_some_and(|p| p != patient_id) {
+                    return Ok(Json(Bundle::searchset(Vec::new(), None)));
+                }
+                search.patient = Some(patient_id.clone());
+            }
+            let after = match (&search.iteration_key, &search.last_id) {
+                (Some(key), Some(id)) => {
+                    let recorded = DateTime::parse_from_rfc3339(key).map_err(|_| {
+                        (StatusCode::BAD_REQUEST, "iterationKey format".to_string())
+                    })?;
+                    Some((recorded.to_utc(), parse_uuid(id)?))
+                }
+                _ => None,
+            };
+            let entries = db.search_audit_events(&search, after)
+                            .await
+                            .map_err(|e| {
+                                error!(?e, "Unknown error when querying DB");
+                                (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string())
+                            })?;
+            let hits: Vec<SearchHit<Value>> = entries.iter()
+                                                     .map(|entry| SearchHit {
+                                                         id: entry.id.to_string(),
+                                                         resource: entry.to_resource(),
+                                                         iteration_key: entry.recorded.to_rfc3339_opts(SecondsFormat::Micros, true),
+                                                     })
+                                                     .collect();
+            for entry in &entries {
+                audited.add(entry.patients.clone());
+            }
+            let next = next_link(&uri, &hits, search.count);
+            let resources = hits.into_iter().map(|hit| hit.resource).collect();
+            return Ok(Json(Bundle::searchset(resources, next)));
+        }
+
+        async fn get_audit_event(Extension(db): Extension<Arc<Db>>,
+                                 Extension(access): Extension<Access>,
+                                 Extension(audited): Extension<AuditedPatients>,
+                                 Path(audit_event_id): Path<String>,
+        ) -> Result<Json<Value>, (StatusCode, String)> {
+            let id = parse_uuid(&audit_event_id)?;
+            let entry = db.get_audit_event(id).await.map_err(|e| {
+                if e.downcast_ref::<crate::db::db::NotFound>().is_some() {
+                    return (StatusCode::NOT_FOUND, "Unknown UUID".to_string());
+                }
+                error!(?e, "Unknown error when querying DB");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string());
+            })?;
+            if let Access::Patient(patient_id) = &access
+                && !entry.patients.contains(patient_id) {
+                return Err((StatusCode::NOT_FOUND, "Unknown UUID".to_string()));
+            }
+            audited.add(entry.patients.clone());
+            return Ok(Json(entry.to_resource()));
+        }
+
+        /// Checks that the stored resource with the ID may be changed, if there is one.
+        /// Restricted resources the caller may not see can't be overwritten, which could drop
+        /// their labels.
+        async fn check_stored_access<R: StoredResource>(db: &Db,
+                                                        access: &Access,
+                                                        clearance: &Clearance,
+                                                        id: Uuid,
+        ) -> Result<(), (StatusCode, String)> {
+            match db.is_hidden::<R>(id, clearance.hidden()).await {
+                Ok(false) => {}
+                Ok(true) => {
+                    info!("ID {} is restricted, the caller may not change it", id);
+                    return Err((StatusCode::FORBIDDEN, "Restricted record".to_string()));
+                }
+                Err(e) => {
+                    error!(?e, "Unknown error when querying DB");
+                    return Err((StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()));
+                }
+            }
+            if *access == Access::All {
+                return Ok(());
+            }
+            return match db.get_resource::<R>(id).await {
+                Ok(stored) if access.allows(Some(&id.to_string()), &stored) => Ok(()),
+                Ok(_) => Err((StatusCode::FORBIDDEN,
+                              "Outside of the patient compartment".to_string())),
+                Err(e) if e.downcast_ref::<crate::db::db::NotFound>().is_some() => Ok(()),
+                Err(e) => {
+                    error!(?e, "Unknown error when querying DB");
+                    Err((StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()))
+                }
+            };
+        }
+    }
+
+    /// Parses a resource ID from a request.
+    fn parse_uuid(id: &str) -> Result<Uuid, (StatusCode, String)> {
+        return Uuid::from_str(id).map_err(|error| {
+            error!(?error, "Could not parse UUID");
+            (StatusCode::BAD_REQUEST, "UUID format".to_string())
+        });
+    }
+
+    /// URL of the page following the hits, keeping all other search parameters.
+    /// None if the hits did not fill the page, so there cannot be any more.
+    fn next_link<R>(uri: &Uri, hits: &[SearchHit<R>], count: u32) -> Option<String> {
+        let last = hits.last()?;
+        // the DB never returns more than 100 hits per page
+        if (hits.len() as u32) < count.min(100) {
+            return None;
         }
+        let mut query: Vec<String> = uri.query()
+                                        .unwrap_or_default()
+                                        .split('&')
+                                        .filter(|p| !p.is_empty()
+                                            && !p.starts_with("iterationKey=")
+                                            && !p.starts_with("lastId="))
+                                        .map(str::to_string)
+                                        .collect();
+        query.push(format!("iterationKey={}", last.iteration_key));
+        query.push(format!("lastId={}", last.id));
+        return Some(format!("{}?{}", uri.path(), query.join("&")));
     }
 
     async fn tracing_middleware(ConnectInfo(remote): ConnectInfo<SocketAddr>,
@@ -129,4 +754,538 @@ pub mod api {
         let span = info_span!("request", %request_id, %method, %uri, %ip);
         return next.run(req).instrument(span).await;
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::api_key::api_key;
+        use crate::db::db::tests::setup;
+        use axum::body::to_bytes;
+        use serde_json::json;
+        use speculoos::assert_that;
+        use std::time::Duration;
+        use testcontainers::core::{IntoContainerPort, WaitFor};
+        use testcontainers::runners::AsyncRunner;
+        use testcontainers::{ContainerAsync, GenericImage};
+        use tower::ServiceExt;
+
+        /// Set up Redis with testcontainers.
+        async fn setup_cache() -> (Cache, ContainerAsync<GenericImage>) {
+            let image = GenericImage::new("redis", "8.2-alpine")
+                .with_exposed_port(6379.tcp())
+                .with_wait_for(WaitFor::message_on_stdout("Ready to accept connections"))
+                .start()
+                .await
+                .expect("Failed to start redis");
+            let url = format!("redis://{}:{}",
+                              image.get_host().await.unwrap(),
+                              image.get_host_port_ipv4(6379).await.unwrap());
+            return (Cache::new(&url).await, image);
+        }
+
+        /// The API with the static tokens "read" and "write".
+        fn api(db: Arc<Db>, cache: Cache) -> Router {
+            let auth = Auth::new(Some("read".to_string()),
+                                 Some("write".to_string()),
+                                 ApiKeys::new(db.clone()));
+            return Api::with_auth(db, cache, auth).app;
+        }
+
+        async fn call(app: &Router,
+                      method: Method,
+                      uri: &str,
+                      token: Option<&str>,
+                      body: Body,
+        ) -> Response<Body> {
+            let mut request = Request::builder().method(method)
+                                                .uri(uri)
+                                                .header("Content-Type", "application/json");
+            if let Some(token) = token {
+                request = request.header("Authorization", token);
+            }
+            let mut request = request.body(body).unwrap();
+            request.extensions_mut()
+                   .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 8080))));
+            return app.clone().oneshot(request).await.unwrap();
+        }
+
+        async fn send(app: &Router, method: Method, uri: &str, body: Body) -> (StatusCode, String) {
+            let response = call(app, method, uri, Some("write"), body).await;
+            let status = response.status();
+            let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            return (status, String::from_utf8(bytes.to_vec()).unwrap());
+        }
+
+        fn patient(id: Option<&str>, name: &str) -> Body {
+            let mut patient = serde_json::json!({"name": [{"text": name}]});
+            if let Some(id) = id {
+                patient["id"] = serde_json::json!(id);
+            }
+            return Body::from(patient.to_string());
+        }
+
+        #[tokio::test]
+        async fn test_put_invalidates_cached_patient() {
+            let test_db = setup().await;
+            let (cache, _redis) = setup_cache().await;
+            let app = api(Arc::new(test_db.db), cache);
+
+            let (status, id) = send(&app, Method::PUT, "/fhir/patient", patient(None, "Old")).await;
+            assert_that!(status).is_equal_to(StatusCode::OK);
+            let path = format!("/fhir/patient/{}", id);
+
+            // the first read fills the cache
+            let (_, body) = send(&app, Method::GET, &path, Body::empty()).await;
+            assert_that!(body.contains("Old")).is_equal_to(true);
+
+            send(&app, Method::PUT, "/fhir/patient", patient(Some(&id), "New")).await;
+
+            let (status, body) = send(&app, Method::GET, &path, Body::empty()).await;
+            assert_that!(status).is_equal_to(StatusCode::OK);
+            assert_that!(body.contains("New")).is_equal_to(true);
+
+            send(&app, Method::DELETE, &path, Body::empty()).await;
+
+            let (status, _) = send(&app, Method::GET, &path, Body::empty()).await;
+            assert_that!(status).is_equal_to(StatusCode::NOT_FOUND);
+        }
+
+        #[tokio::test]
+        async fn test_cache_is_behind_authorization() {
+            let test_db = setup().await;
+            let (cache, _redis) = setup_cache().await;
+            let app = api(Arc::new(test_db.db), cache);
+
+            let (_, id) = send(&app, Method::PUT, "/fhir/patient", patient(None, "Anna")).await;
+            let path = format!("/fhir/patient/{}", id);
+
+            let first = call(&app, Method::GET, &path, Some("read"), Body::empty()).await;
+            let cached = call(&app, Method::GET, &path, Some("read"), Body::empty()).await;
+
+            assert_that!(first.status()).is_equal_to(StatusCode::OK);
+            assert_that!(cached.status()).is_equal_to(StatusCode::OK);
+            assert_that!(cached.headers().get("content-type"))
+                .is_equal_to(first.headers().get("content-type"));
+            assert_that!(cached.headers().get("content-type").is_some()).is_equal_to(true);
+
+            let anonymous = call(&app, Method::GET, &path, None, Body::empty()).await;
+            let wrong_token = call(&app, Method::GET, &path, Some("wrong"), Body::empty()).await;
+
+            assert_that!(anonymous.status()).is_equal_to(StatusCode::UNAUTHORIZED);
+            assert_that!(wrong_token.status()).is_equal_to(StatusCode::UNAUTHORIZED);
+        }
+
+        #[tokio::test]
+        async fn test_unavailable_cache_is_bypassed() {
+            let test_db = setup().await;
+            // nothing listens there, and every read has to try it
+            let cache = Cache::new("redis://127.0.0.1:1").await.with_local_tier(0, Duration::ZERO);
+            let app = api(Arc::new(test_db.db), cache);
+
+            let (status, id) = send(&app, Method::PUT, "/fhir/patient", patient(None, "Anna")).await;
+            assert_that!(status).is_equal_to(StatusCode::OK);
+            let path = format!("/fhir/patient/{}", id);
+            for _ in 0..5 {
+                let (status, body) = send(&app, Method::GET, &path, Body::empty()).await;
+                assert_that!(status).is_equal_to(StatusCode::OK);
+                assert_that!(body.contains("Anna")).is_equal_to(true);
+            }
+
+            let (status, health) = send(&app, Method::GET, "/health", Body::empty()).await;
+            assert_that!(status).is_equal_to(StatusCode::OK);
+            assert_that!(health)
+                .is_equal_to(r#"{"status":"degraded","db":"up","cache":"down"}"#.to_string());
+
+            let (_, metrics) = send(&app, Method::GET, "/metrics", Body::empty()).await;
+            assert_that!(metrics.contains(r#"fhir_cache_state{state="down"} 1"#)).is_equal_to(true);
+        }
+
+        #[tokio::test]
+        async fn test_patient_write_invalidates_cached_searches() {
+            let test_db = setup().await;
+            let (cache, _redis) = setup_cache().await;
+            let app = api(Arc::new(test_db.db), cache);
+
+            let (_, id) = send(&app, Method::PUT, "/fhir/patient", patient(None, "Anna Meier")).await;
+            let (status, body) = send(&app, Method::GET, "/fhir/patient?name=Anna", Body::empty())
+                .await;
+            assert_that!(status).is_equal_to(StatusCode::OK);
+            assert_that!(body.matches("iterationKey").count()).is_equal_to(1);
+
+            send(&app, Method::PUT, "/fhir/patient", patient(None, "Anna Schulz")).await;
+            let (_, body) = send(&app, Method::GET, "/fhir/patient?name=Anna", Body::empty()).await;
+            assert_that!(body.matches("iterationKey").count()).is_equal_to(2);
+
+            send(&app, Method::DELETE, &format!("/fhir/patient/{}", id), Body::empty()).await;
+            let (_, body) = send(&app, Method::GET, "/fhir/patient?name=Anna", Body::empty()).await;
+            assert_that!(body.matches("iterationKey").count()).is_equal_to(1);
+
+            let (_, metrics) = send(&app, Method::GET, "/metrics", Body::empty()).await;
+            assert_that!(metrics.contains(r#"fhir_cache_misses_total{tier="local"} 3"#))
+                .is_equal_to(true);
+        }
+
+        #[tokio::test]
+        async fn test_encrypted_types_stay_out_of_redis() {
+            let test_db = setup().await;
+            let (cache, _redis) = setup_cache().await;
+            let cache = cache.with_local_tier(1000, Duration::from_secs(5))
+                             .with_local_only(vec![Patient::RESOURCE_TYPE.to_string()]);
+            let app = api(Arc::new(test_db.db), cache);
+
+            let (_, id) = send(&app, Method::PUT, "/fhir/patient", patient(None, "Anna")).await;
+            for uri in [format!("/fhir/patient/{}", id), "/fhir/patient?name=Anna".to_string()] {
+                send(&app, Method::GET, &uri, Body::empty()).await;
+                let (status, body) = send(&app, Method::GET, &uri, Body::empty()).await;
+                assert_that!(status).is_equal_to(StatusCode::OK);
+                assert_that!(body.contains(&id)).is_equal_to(true);
+            }
+
+            let (_, metrics) = send(&app, Method::GET, "/metrics", Body::empty()).await;
+            assert_that!(metrics.contains(r#"fhir_cache_hits_total{tier="local"} 2"#))
+                .is_equal_to(true);
+            assert_that!(metrics.contains(r#"fhir_cache_hits_total{tier="redis"} 0"#))
+                .is_equal_to(true);
+            assert_that!(metrics.contains(r#"fhir_cache_misses_total{tier="redis"} 0"#))
+                .is_equal_to(true);
+        }
+
+        #[tokio::test]
+        async fn test_local_tier_without_redis() {
+            let test_db = setup().await;
+            let cache = Cache::new("redis://127.0.0.1:1").await;
+            let app = api(Arc::new(test_db.db), cache);
+
+            let (_, id) = send(&app, Method::PUT, "/fhir/patient", patient(None, "Old")).await;
+            let path = format!("/fhir/patient/{}", id);
+            send(&app, Method::GET, &path, Body::empty()).await;
+            let (_, body) = send(&app, Method::GET, &path, Body::empty()).await;
+            assert_that!(body.contains("Old")).is_equal_to(true);
+
+            let (_, metrics) = send(&app, Method::GET, "/metrics", Body::empty()).await;
+            assert_that!(metrics.contains(r#"fhir_cache_hits_total{tier="local"} 1"#))
+                .is_equal_to(true);
+            assert_that!(metrics.contains(r#"fhir_cache_hits_total{tier="redis"} 0"#))
+                .is_equal_to(true);
+
+            // writes on this instance invalidate the local tier without Redis
+            send(&app, Method::PUT, "/fhir/patient", patient(Some(&id), "New")).await;
+            let (_, body) = send(&app, Method::GET, &path, Body::empty()).await;
+            assert_that!(body.contains("New")).is_equal_to(true);
+        }
+
+        #[tokio::test]
+        async fn test_disabled_cache() {
+            let test_db = setup().await;
+            let app = api(Arc::new(test_db.db), Cache::disabled());
+
+            let (_, id) = send(&app, Method::PUT, "/fhir/patient", patient(None, "Anna")).await;
+            let (status, _) = send(&app, Method::GET, &format!("/fhir/patient/{}", id), Body::empty())
+                .await;
+            assert_that!(status).is_equal_to(StatusCode::OK);
+
+            let health = call(&app, Method::GET, "/health", None, Body::empty()).await;
+            assert_that!(health.status()).is_equal_to(StatusCode::OK);
+            let body = to_bytes(health.into_body(), usize::MAX).await.unwrap();
+            assert_that!(String::from_utf8(body.to_vec()).unwrap())
+                .is_equal_to(r#"{"status":"up","db":"up","cache":"disabled"}"#.to_string());
+        }
+
+        #[tokio::test]
+        async fn test_api_keys() {
+            let test_db = setup().await;
+            let db = Arc::new(test_db.db);
+            let keys = ApiKeys::new(db.clone());
+            let scopes = ["system/Patient.rs".to_string()];
+            let key = format!("Bearer {}", keys.create("partner", &scopes, None).await.unwrap());
+            let app = api(db, Cache::disabled());
+
+            let (_, id) = send(&app, Method::PUT, "/fhir/patient", patient(None, "Anna")).await;
+            let path = format!("/fhir/patient/{}", id);
+            let read = call(&app, Method::GET, &path, Some(&key), Body::empty()).await;
+            assert_that!(read.status()).is_equal_to(StatusCode::OK);
+            let write = call(&app, Method::PUT, "/fhir/patient", Some(&key), patient(None, "Bob")).await;
+            assert_that!(write.status()).is_equal_to(StatusCode::FORBIDDEN);
+
+            keys.revoke("partner").await.unwrap();
+            let revoked = call(&app, Method::GET, &path, Some(&key), Body::empty()).await;
+            assert_that!(revoked.status()).is_equal_to(StatusCode::UNAUTHORIZED);
+        }
+
+        #[tokio::test]
+        async fn test_audit_trail() {
+            let test_db = setup().await;
+            let cache = Cache::new("redis://127.0.0.1:1").await;
+            let app = api(Arc::new(test_db.db), cache);
+
+            let (_, id) = send(&app, Method::PUT, "/fhir/patient", patient(None, "Anna")).await;
+            let path = format!("/fhir/patient/{}", id);
+            call(&app, Method::GET, &path, Some("read"), Body::empty()).await;
+            // served by the cache, audited all the same
+            call(&app, Method::GET, &path, Some("read"), Body::empty()).await;
+            call(&app, Method::GET, &path, None, Body::empty()).await;
+
+            let (status, body) = send(&app,
+                                      Method::GET,
+                                      &format!("/fhir/AuditEvent?patient={}", id),
+                                      Body::empty()).await;
+            assert_that!(status).is_equal_to(StatusCode::OK);
+            let bundle: Value = serde_json::from_str(&body).unwrap();
+            let events: Vec<&Value> = bundle["entry"].as_array()
+                                                     .unwrap()
+                                                     .iter()
+                                                     .map(|e| &e["resource"])
+                                                     .collect();
+            let actions: Vec<&Value> = events.iter().map(|e| &e["action"]).collect();
+            assert_that!(actions).is_equal_to(vec![&json!("U"), &json!("R"), &json!("R")]);
+            assert_that!(events[1]["agent"][0]["who"]["display"]).is_equal_to(json!("read-token"));
+
+            let (_, body) = send(&app, Method::GET, "/fhir/AuditEvent?count=100", Body::empty()).await;
+            let bundle: Value = serde_json::from_str(&body).unwrap();
+            let rejected = bundle["entry"].as_array()
+                                          .unwrap()
+                                          .iter()
+                                          .filter(|e| e["resource"]["outcome"] == json!("4"))
+                                          .count();
+            assert_that!(rejected).is_equal_to(1);
+        }
+
+        #[tokio::test]
+        async fn test_patient_provenance() {
+            let test_db = setup().await;
+            let app = api(Arc::new(test_db.db), Cache::disabled());
+
+            let put = async |body: Body, x_provenance: &str| {
+                let mut request = Request::builder().method(Method::PUT)
+                                                    .uri("/fhir/patient")
+                                                    .header("Content-Type", "application/json")
+                                                    .header("Authorization", "write")
+                                                    .header("X-Provenance", x_provenance)
+                                                    .body(body)
+                                                    .unwrap();
+                request.extensions_mut()
+                       .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 8080))));
+                return app.clone().oneshot(request).await.unwrap().status();
+            };
+
+            let (_, id) = send(&app, Method::PUT, "/fhir/patient", patient(None, "Anna")).await;
+            let x_provenance = json!({
+                "resourceType": "Provenance",
+                "reason": [{"text": "Name changed after marriage"}],
+            });
+            let status = put(patient(Some(&id), "Anna Schulz"), &x_provenance.to_string()).await;
+            assert_that!(status).is_equal_to(StatusCode::OK);
+            let status = put(patient(Some(&id), "Anna Meier"), r#"{"resourceType": "Patient"}"#).await;
+            assert_that!(status).is_equal_to(StatusCode::BAD_REQUEST);
+
+            let (_, body) = send(&app,
+                                 Method::GET,
+                                 &format!("/fhir/Provenance?target=Patient/{}", id),
+                                 Body::empty()).await;
+            let bundle: Value = serde_json::from_str(&body).unwrap();
+            let provenances: Vec<&Value> = bundle["entry"].as_array()
+                                                          .unwrap()
+                                                          .iter()
+                                                          .map(|e| &e["resource"])
+                                                          .collect();
+            assert_that!(provenances.len()).is_equal_to(2);
+            let update = provenances.iter()
+                                    .find(|p| p["activity"]["coding"][0]["code"] == json!("UPDATE"))
+                                    .unwrap();
+            assert_that!(update["target"][0]["reference"])
+                .is_equal_to(json!(format!("Patient/{}/_history/2", id)));
+            assert_that!(update["reason"][0]["text"]).is_equal_to(json!("Name changed after marriage"));
+            assert_that!(update["agent"][0]["who"]["display"]).is_equal_to(json!("write-token"));
+
+            // provenance doesn't keep the patient from being deleted
+            let (status, _) = send(&app, Method::DELETE, &format!("/fhir/patient/{}", id), Body::empty())
+                .await;
+            assert_that!(status).is_equal_to(StatusCode::NO_CONTENT);
+        }
+
+        #[tokio::test]
+        async fn test_restricted_patient() {
+            let test_db = setup().await;
+            let cache = Cache::new("redis://127.0.0.1:1").await;
+            // API keys can't be created with other than SMART scopes
+            let key = "fhirkey_emergency";
+            let scopes = ["system/*.cruds".to_string(), "break-glass".to_string()];
+            test_db.db.insert_api_key("emergency", &api_key::hash(key), &scopes, None).await.unwrap();
+            let app = api(Arc::new(test_db.db), cache);
+
+            let get_as = async |token: &str, uri: &str, break_glass: &str| {
+                let mut request = Request::builder().method(Method::GET)
+                                                    .uri(uri)
+                                                    .header("Authorization", token)
+                                                    .header("X-Break-Glass", break_glass)
+                                                    .body(Body::empty())
+                                                    .unwrap();
+                request.extensions_mut()
+                       .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 8080))));
+                let response = app.clone().oneshot(request).await.unwrap();
+                let status = response.status();
+                let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                return (status, String::from_utf8(bytes.to_vec()).unwrap());
+            };
+            let get = async |uri: &str, break_glass: &str| {
+                return get_as(&format!("Bearer {}", key), uri, break_glass).await;
+            };
+
+            let vip = json!({
+                "meta": {"security": [{
+                    "system": "http://terminology.hl7.org/CodeSystem/v3-Confidentiality",
+                    "code": "R",
+                }]},
+                "name": [{"text": "Anna VIP"}],
+            });
+            let (status, id) = send(&app, Method::PUT, "/fhir/patient", Body::from(vip.to_string()))
+                .await;
+            assert_that!(status).is_equal_to(StatusCode::OK);
+            let path = format!("/fhir/patient/{}", id);
+
+            let (status, _) = send(&app, Method::GET, &path, Body::empty()).await;
+            assert_that!(status).is_equal_to(StatusCode::NOT_FOUND);
+            let (_, body) = send(&app, Method::GET, "/fhir/patient?name=Anna", Body::empty()).await;
+            assert_that!(body.contains(&id)).is_equal_to(false);
+
+            // the cache doesn't hand out what was read by breaking the glass
+            let (status, body) = get(&path, "Emergency admission").await;
+            assert_that!(status).is_equal_to(StatusCode::OK);
+            assert_that!(body.contains("Anna VIP")).is_equal_to(true);
+            let (status, _) = send(&app, Method::GET, &path, Body::empty()).await;
+            assert_that!(status).is_equal_to(StatusCode::NOT_FOUND);
+            let (_, body) = get("/fhir/patient?name=Anna", "Emergency admission").await;
+            assert_that!(body.contains(&id)).is_equal_to(true);
+            let (status, _) = get(&path, " ").await;
+            assert_that!(status).is_equal_to(StatusCode::BAD_REQUEST);
+            // the write token has neither the role nor the scope for breaking the glass
+            let (status, _) = get_as("write", &path, "Emergency admission").await;
+            assert_that!(status).is_equal_to(StatusCode::FORBIDDEN);
+
+            let (_, body) = send(&app,
+                                 Method::GET,
+                                 &format!("/fhir/AuditEvent?patient={}", id),
+                                 Body::empty()).await;
+            let bundle: Value = serde_json::from_str(&body).unwrap();
+            let reasons: Vec<&Value> = bundle["entry"].as_array()
+                                                      .unwrap()
+                                                      .iter()
+                                                      .map(|e| &e["resource"]["purposeOfEvent"])
+                                                      .filter(|p| !p.is_null())
+                                                      .collect();
+            assert_that!(reasons.len()).is_equal_to(2);
+            assert_that!(reasons[0][0]["coding"][0]["code"]).is_equal_to(json!("BTG"));
+            assert_that!(reasons[0][0]["text"]).is_equal_to(json!("Emergency admission"));
+        }
+
+        #[tokio::test]
+        async fn test_restricted_patient_changes() {
+            let test_db = setup().await;
+            let db = Arc::new(test_db.db);
+            let app = api(db.clone(), Cache::disabled());
+
+            let vip = json!({
+                "meta": {"security": [{
+                    "system": "http://terminology.hl7.org/CodeSystem/v3-Confidentiality",
+                    "code": "R",
+                }]},
+                "name": [{"text": "Anna VIP"}],
+            });
+            let (_, vip_id) = send(&app, Method::PUT, "/fhir/patient", Body::from(vip.to_string()))
+                .await;
+            let (_, other_id) = send(&app, Method::PUT, "/fhir/patient", patient(None, "Beat"))
+                .await;
+
+            // the write token has no role for restricted records
+            let (status, _) = send(&app,
+                                   Method::DELETE,
+                                   &format!("/fhir/patient/{}", vip_id),
+                                   Body::empty()).await;
+            assert_that!(status).is_equal_to(StatusCode::NOT_FOUND);
+            let (status, _) = send(&app,
+                                   Method::PUT,
+                                   "/fhir/patient",
+                                   patient(Some(&vip_id), "Anna Public")).await;
+            assert_that!(status).is_equal_to(StatusCode::FORBIDDEN);
+            for (source, target) in [(&vip_id, &other_id), (&other_id, &vip_id)] {
+                let merge = json!({"sourcePatient": source, "targetPatient": target});
+                let (status, body) = send(&app,
+                                          Method::POST,
+                                          "/fhir/patient/$merge",
+                                          Body::from(merge.to_string())).await;
+                assert_that!(status).is_equal_to(StatusCode::NOT_FOUND);
+                assert_that!(body.contains("Anna")).is_equal_to(false);
+            }
+
+            let stored: Patient = db.get_resource(Uuid::from_str(&vip_id).unwrap()).await.unwrap();
+            assert_that!(serde_json::to_value(&stored).unwrap()["name"][0]["text"])
+                .is_equal_to(json!("Anna VIP"));
+            assert_that!(stored.meta.unwrap().version_id).is_equal_to(Some("1".to_string()));
+        }
+
+        #[tokio::test]
+        async fn test_anonymize_patients() {
+            let test_db = setup().await;
+            let app = api(Arc::new(test_db.db), Cache::disabled());
+
+            for (name, mrn) in [("Anna Meier", "12345"), ("Beat Huber", "67890")] {
+                let patient = json!({
+                    "identifier": [{"system": "urn:mrn", "value": mrn}],
+                    "name": [{"text": name}],
+                    "birthDate": "1984-03-12",
+                    "address": {"city": "Zuerich", "postalCode": "8001"},
+                });
+                let (status, _) = send(&app,
+                                       Method::PUT,
+                                       "/fhir/patient",
+                                       Body::from(patient.to_string())).await;
+                assert_that!(status).is_equal_to(StatusCode::OK);
+            }
+
+            let response = call(&app,
+                                Method::GET,
+                                "/fhir/patient/$anonymize",
+                                Some("write"),
+                                Body::empty()).await;
+            assert_that!(response.status()).is_equal_to(StatusCode::OK);
+            assert_that!(response.headers()[CONTENT_TYPE].to_str().unwrap())
+                .is_equal_to(NDJSON_CONTENT_TYPE);
+            let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let export = String::from_utf8(bytes.to_vec()).unwrap();
+            let patients: Vec<Value> = export.lines()
+                                             .map(|line| serde_json::from_str(line).unwrap())
+                                             .collect();
+            assert_that!(patients.len()).is_equal_to(2);
+            assert_that!(export.contains("Meier") || export.contains("12345")).is_equal_to(false);
+            for patient in &patients {
+                assert_that!(patient["name"]).is_equal_to(Value::Null);
+                assert_that!(patient["birthDate"]).is_equal_to(json!("1984"));
+                assert_that!(patient["address"]["postalCode"]).is_equal_to(json!("800"));
+            }
+
+            // the same patients get the same pseudonyms in every export
+            let (_, again) = send(&app, Method::GET, "/fhir/patient/$anonymize", Body::empty()).await;
+            assert_that!(again).is_equal_to(export);
+        }
+
+        #[tokio::test]
+        async fn test_discovery_without_token() {
+            let test_db = setup().await;
+            let app = api(Arc::new(test_db.db), Cache::disabled());
+
+            let metadata = call(&app, Method::GET, "/fhir/metadata", None, Body::empty()).await;
+            assert_that!(metadata.status()).is_equal_to(StatusCode::OK);
+            let body = to_bytes(metadata.into_body(), usize::MAX).await.unwrap();
+            assert_that!(String::from_utf8(body.to_vec()).unwrap().contains("CapabilityStatement"))
+                .is_equal_to(true);
+
+            // no authorization server is configured in the tests
+            let smart = call(&app,
+                             Method::GET,
+                             "/.well-known/smart-configuration",
+                             None,
+                             Body::empty()).await;
+            assert_that!(smart.status()).is_equal_to(StatusCode::NOT_FOUND);
+        }
+    }
 }
@@ -0,0 +1,254 @@
+pub mod api_key {
+    use crate::auth::auth::Identity;
+    use crate::db::db::Db;
+    use crate::smart::smart::Scope;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use chrono::{DateTime, Duration, Utc};
+    use ring::digest::{digest, SHA256};
+    use ring::rand::{SecureRandom, SystemRandom};
+    use std::error::Error;
+    use std::sync::Arc;
+
+    /// Prefix of every API key, tells them apart from other bearer tokens.
+    pub const PREFIX: &str = "fhirkey_";
+    /// Random bytes of a key, after the prefix.
+    const KEY_BYTES: usize = 32;
+
+    const USAGE: &str = "Usage:
+  api-key create <name> <scope>... [--expires-in-days <days>]
+  api-key revoke <name>
+  api-key list";
+
+    /// An API key of a named client, without the key itself.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct ApiKey {
+        pub name: String,
+        /// SMART scopes granted to the client.
+        pub scopes: Vec<String>,
+        pub created_at: DateTime<Utc>,
+        pub expires_at: Option<DateTime<Utc>>,
+        pub revoked_at: Option<DateTime<Utc>>,
+    }
+
+    impl ApiKey {
+        pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+            return self.revoked_at.is_none() && self.expires_at.is_none_or(|e| e > now);
+        }
+
+        fn identity(&self) -> Identity {
+            return Identity {
+                subject: format!("api-key:{}", self.name),
+                client_id: Some(self.name.clone()),
+                scopes: self.scopes.clone(),
+                patient: None,
+                roles: Vec::new(),
+            };
+        }
+    }
+
+    /// What is stored of a key. Keys are random, so a plain SHA-256 can't be reversed and allows
+    /// looking them up.
+    pub fn hash(key: &str) -> Vec<u8> {
+        return digest(&SHA256, key.as_bytes()).as_ref().to_vec();
+    }
+
+    /// Registry of the API keys in Postgres.
+    #[derive(Clone)]
+    pub struct ApiKeys {
+        db: Arc<Db>,
+    }
+
+    impl ApiKeys {
+        pub fn new(db: Arc<Db>) -> Self {
+            return Self { db };
+        }
+
+        /// Creates a key for the client and returns it. Only its hash is stored, so it can't be
+        /// shown again. Every scope has to be a SMART resource scope.
+        pub async fn create(&self,
+                            name: &str,
+                            scopes: &[String],
+                            expires_at: Option<DateTime<Utc>>,
+        ) -> Result<String, Box<dyn Error>> {
+            if name.is_empty() {
+                return Err("The name must not be empty".into());
+            }
+            if scopes.is_empty() {
+                return Err("At least one scope is required".into());
+            }
+            for scope in scopes {
+                scope.parse::<Scope>().map_err(|e| format!("Invalid scope {}: {}", scope, e))?;
+            }
+            let mut bytes = [0u8; KEY_BYTES];
+            SystemRandom::new().fill(&mut bytes).map_err(|_| "No randomness available")?;
+            let key = format!("{}{}", PREFIX, URL_SAFE_NO_PAD.encode(bytes));
+            self.db.insert_api_key(name, &hash(&key), scopes, expires_at).await?;
+            return Ok(key);
+        }
+
+        /// Revokes the key of the client, false if there is no active one.
+        pub async fn revoke(&self, name: &str) -> Result<bool, Box<dyn Error>> {
+            return self.db.revoke_api_key(name).await;
+        }
+
+        pub async fn list(&self) -> Result<Vec<ApiKey>, Box<dyn Error>> {
+            return self.db.api_keys().await;
+        }
+
+        /// The client presenting the key, None if the key is unknown, expired or revoked.
+        pub async fn identify(&self, key: &str) -> Result<Option<Identity>, Box<dyn Error>> {
+            let api_key = self.db.find_api_key(&hash(key)).await?;
+            return Ok(api_key.filter(|k| k.is_active(Utc::now())).map(|k| k.identity()));
+        }
+    }
+
+    /// Runs the management command in `args`, e.g. `create partner system/*.rs`, and returns
+    /// its output.
+    pub async fn run_cli(keys: &ApiKeys, args: &[String]) -> Result<String, Box<dyn Error>> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        return match args.as_slice() {
+            ["create", name, rest @ ..] => {
+                let options = CreateOptions::parse(rest)?;
+                let key = keys.create(name, &options.scopes, options.expires_at).await?;
+                Ok(format!("Created API key {}, it is only shown once:\n{}", name, key))
+            }
+            ["revoke", name] => match keys.revoke(name).await? {
+                true => Ok(format!("Revoked API key {}", name)),
+                false => Err(format!("There is no active API key {}", name).into()),
+            },
+            ["list"] => {
+                let now = Utc::now();
+                let lines: Vec<String> = keys.list()
+                                             .await?
+                                             .iter()
+                                             .map(|k| format!("{}\t{}\t{}\t{}",
+                                                              k.name,
+                                                              k.scopes.join(" "),
+                                                              state(k, now),
+                                                              k.created_at.to_rfc3339()))
+                                             .collect();
+                Ok(lines.join("\n"))
+            }
+            _ => Err(USAGE.into()),
+        };
+    }
+
+    struct CreateOptions {
+        scopes: Vec<String>,
+        expires_at: Option<DateTime<Utc>>,
+    }
+
+    impl CreateOptions {
+        fn parse(args: &[&str]) -> Result<Self, Box<dyn Error>> {
+            let mut options = CreateOptions { scopes: Vec::new(), expires_at: None };
+            let mut args = args.iter();
+            while let Some(arg) = args.next() {
+                if *arg == "--expires-in-days" {
+                    let days: i64 = args.next()
+                                        .and_then(|d| d.parse().ok())
+                                        .filter(|d| *d > 0)
+                                        .ok_or("--expires-in-days needs a positive number of days")?;
+                    options.expires_at = Some(Utc::now() + Duration::days(days));
+                } else {
+                    options.scopes.push(arg.to_string());
+                }
+            }
+            return Ok(options);
+        }
+    }
+
+    fn state(key: &ApiKey, now: DateTime<Utc>) -> String {
+        return match (key.revoked_at, key.expires_at) {
+            (Some(revoked_at), _) => format!("revoked {}", revoked_at.to_rfc3339()),
+            (None, Some(expires_at)) if expires_at <= now => {
+                format!("expired {}", expires_at.to_rfc3339())
+            }
+            (None, Some(expires_at)) => format!("expires {}", expires_at.to_rfc3339()),
+            (None, None) => "active".to_string(),
+        };
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::db::db::tests::setup;
+        use speculoos::assert_that;
+        use speculoos::prelude::{ContainingResultAssertions, ResultAssertions};
+
+        fn args(args: &[&str]) -> Vec<String> {
+            return args.iter().map(|a| a.to_string()).collect();
+        }
+
+        #[tokio::test]
+        async fn test_api_keys() {
+            let test_db = setup().await;
+            let keys = ApiKeys::new(Arc::new(test_db.db));
+
+            let key = keys.create("partner", &args(&["system/Patient.rs"]), None).await.unwrap();
+            assert_that!(key.starts_with(PREFIX)).is_equal_to(true);
+            let identity = keys.identify(&key).await.unwrap().unwrap();
+            assert_that!(identity.subject).is_equal_to("api-key:partner".to_string());
+            assert_that!(identity.scopes).is_equal_to(args(&["system/Patient.rs"]));
+            assert_that!(keys.identify(&format!("{}x", key)).await.unwrap()).is_equal_to(None);
+
+            // the same name can't get a second active key
+            assert_that!(keys.create("partner", &args(&["system/*.rs"]), None).await.map_err(|e| e.to_string()))
+                .is_err_containing("There already is an active API key partner".to_string());
+            assert_that!(keys.create("other", &args(&["fhir.read"]), None).await.map_err(|e| e.to_string()))
+                .is_err_containing("Invalid scope fhir.read: Not a resource scope".to_string());
+
+            assert_that!(keys.revoke("partner").await.unwrap()).is_equal_to(true);
+            assert_that!(keys.revoke("partner").await.unwrap()).is_equal_to(false);
+            assert_that!(keys.identify(&key).await.unwrap()).is_equal_to(None);
+
+            // but a new one after the old one was revoked
+            let reissued = keys.create("partner", &args(&["system/*.rs"]), None).await.unwrap();
+            assert_that!(keys.identify(&reissued).await.unwrap().map(|i| i.client_id))
+                .is_equal_to(Some(Some("partner".to_string())));
+            assert_that!(keys.identify(&key).await.unwrap()).is_equal_to(None);
+            let partner: Vec<bool> = keys.list()
+                                         .await
+                                         .unwrap()
+                                         .iter()
+                                         .filter(|k| k.name == "partner")
+                                         .map(|k| k.revoked_at.is_some())
+                                         .collect();
+            assert_that!(partner).is_equal_to(vec![true, false]);
+
+            let expired = keys.create("expired",
+                                      &args(&["user/*.cruds"]),
+                                      Some(Utc::now() - Duration::seconds(1))).await.unwrap();
+            assert_that!(keys.identify(&expired).await.unwrap()).is_equal_to(None);
+        }
+
+        #[tokio::test]
+        async fn test_cli() {
+            let test_db = setup().await;
+            let keys = ApiKeys::new(Arc::new(test_db.db));
+
+            let created = run_cli(&keys, &args(&["create", "lab", "system/Observation.cruds",
+                                                 "--expires-in-days", "30"])).await.unwrap();
+            let key = created.lines().last().unwrap();
+            assert_that!(keys.identify(key).await.unwrap().map(|i| i.client_id))
+                .is_equal_to(Some(Some("lab".to_string())));
+
+            let listed = run_cli(&keys, &args(&["list"])).await.unwrap();
+            assert_that!(listed.starts_with("lab\tsystem/Observation.cruds\texpires ")).is_equal_to(true);
+            assert_that!(listed.contains(key)).is_equal_to(false);
+
+            assert_that!(run_cli(&keys, &args(&["revoke", "lab"])).await)
+                .is_ok()
+                .is_equal_to("Revoked API key lab".to_string());
+            assert_that!(run_cli(&keys, &args(&["list"])).await.unwrap().contains("\trevoked "))
+                .is_equal_to(true);
+            assert_that!(run_cli(&keys, &args(&["create", "lab", "system/Observation.rs"])).await)
+                .is_ok();
+            assert_that!(run_cli(&keys, &args(&["list"])).await.unwrap().lines().count())
+                .is_equal_to(2);
+            assert_that!(run_cli(&keys, &args(&["create", "x", "--expires-in-days", "0"])).await)
+                .is_err();
+            assert_that!(run_cli(&keys, &args(&["rotate"])).await).is_err();
+        }
+    }
+}
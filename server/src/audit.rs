@@ -0,0 +1,347 @@
+pub mod audit {
+    use crate::api::api::route_interaction;
+    use crate::auth::auth::Identity;
+    use crate::consent::consent::X_BREAK_GLASS;
+    use crate::db::db::Db;
+    use crate::smart::smart::Interaction;
+    use axum::extract::{ConnectInfo, MatchedPath, State};
+    use axum::middleware::Next;
+    use axum_core::body::Body;
+    use axum_core::extract::Request;
+    use axum_core::response::Response;
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use chrono::{DateTime, SecondsFormat, Utc};
+    use http::StatusCode;
+    use ring::hmac;
+    use ring::rand::{SecureRandom, SystemRandom};
+    use serde_json::{json, Value};
+    use std::env;
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::{Arc, Mutex};
+    use tracing::{error, warn};
+    use uuid::Uuid;
+
+    pub const RESOURCE_TYPE: &str = "AuditEvent";
+
+    const EVENT_TYPE_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/audit-event-type";
+    const INTERACTION_SYSTEM: &str = "http://hl7.org/fhir/restful-interaction";
+    const ENTITY_TYPE_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/audit-entity-type";
+    const OBJECT_ROLE_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/object-role";
+    const ACT_REASON_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/v3-ActReason";
+
+    /// Patients a request touched, filled in by the handlers (or the cache) for the audit trail.
+    #[derive(Clone, Default, Debug)]
+    pub struct AuditedPatients(Arc<Mutex<Vec<String>>>);
+
+    impl AuditedPatients {
+        pub fn add(&self, ids: impl IntoIterator<Item = String>) {
+            let mut patients = self.0.lock().unwrap();
+            for id in ids {
+                if !patients.contains(&id) {
+                    patients.push(id);
+                }
+            }
+        }
+
+        pub fn get(&self) -> Vec<String> {
+            return self.0.lock().unwrap().clone();
+        }
+    }
+
+    /// One row of the audit trail, `fhir.audit_event`.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct AuditEntry {
+        pub id: Uuid,
+        pub recorded: DateTime<Utc>,
+        pub interaction: Interaction,
+        /// Set for operations like `$merge`, the interaction is what the operation amounts to.
+        pub operation: Option<String>,
+        pub resource_type: String,
+        pub resource_id: Option<String>,
+        pub query: Option<String>,
+        /// Subject of the caller, None if it couldn't be authenticated.
+        pub actor: Option<String>,
+        pub client_id: Option<String>,
+        /// Pseudonym of the IP address of the caller, see [IpPseudonyms::pseudonymize].
+        pub ip: Option<String>,
+        pub patients: Vec<String>,
+        pub status: u16,
+        /// Reason of a break-the-glass access.
+        pub break_glass: Option<String>,
+    }
+
+    impl AuditEntry {
+        /// The entry as FHIR R4 AuditEvent.
+        pub fn to_resource(&self) -> Value {
+            let (action, subtype) = match (&self.operation, self.interaction) {
+                (Some(_), Interaction::Search) => ("E", "operation"),
+                (Some(_), _) => ("U", "operation"),
+                (None, Interaction::Create) => ("C", "create"),
+                (None, Interaction::Read) => ("R", "read"),
+                (None, Interaction::Update) => ("U", "update"),
+                (None, Interaction::Delete) => ("D", "delete"),
+                (None, Interaction::Search) => ("E", "search-type"),
+            };
+            let outcome = match self.status {
+                0..400 => "0",
+                400..500 => "4",
+                _ => "8",
+            };
+            let mut agent = json!({
+                "who": {"display": self.actor.as_deref().unwrap_or("anonymous")},
+                "requestor": true,
+            });
+            if let Some(client_id) = &self.client_id {
+                agent["altId"] = json!(client_id);
+            }
+            if let Some(ip) = &self.ip {
+                agent["network"] = json!({"address": ip});
+            }
+
+            let mut entity: Vec<Value> = self.patients
+                                             .iter()
+                                             .map(|id| json!({
+                                                 "what": {"reference": format!("Patient/{}", id)},
+                                                 "type": {
+                                                     "system": ENTITY_TYPE_SYSTEM,
+                                                     "code": "1",
+                                                 },
+                                                 "role": {
+                                                     "system": OBJECT_ROLE_SYSTEM,
+                                                     "code": "1",
+                                                 },
+                                             }))
+                                             .collect();
+            let target = match &self.resource_id {
+                Some(id) => json!({"reference": format!("{}/{}", self.resource_type, id)}),
+                None => json!({"display": self.resource_type}),
+            };
+            let mut request = json!({"what": target});
+            if let Some(operation) = &self.operation {
+                request["name"] = json!(operation);
+            }
+            if let Some(query) = &self.query {
+                request["query"] = json!(STANDARD.encode(query));
+            }
+            entity.push(request);
+
+            let mut event = json!({
+                "resourceType": "AuditEvent",
+                "id": self.id.to_string(),
+                "type": {
+                    "system": EVENT_TYPE_SYSTEM,
+                    "code": "rest",
+                    "display": "RESTful Operation",
+                },
+                "subtype": [{"system": INTERACTION_SYSTEM, "code": subtype}],
+                "action": action,
+                "recorded": self.recorded.to_rfc3339_opts(SecondsFormat::Micros, true),
+                "outcome": outcome,
+                "agent": [agent],
+                "source": {"observer": {"display": env!("CARGO_PKG_NAME")}},
+                "entity": entity,
+            });
+            if let Some(reason) = &self.break_glass {
+                event["purposeOfEvent"] = json!([{
+                    "coding": [{
+                        "system": ACT_REASON_SYSTEM,
+                        "code": "BTG",
+                        "display": "break the glass",
+                    }],
+                    "text": reason,
+                }]);
+            }
+            return event;
+        }
+    }
+
+    /// Keyed pseudonyms of the IP addresses of callers.
+    #[derive(Clone)]
+    pub struct IpPseudonyms {
+        key: hmac::Key,
+    }
+
+    impl IpPseudonyms {
+        pub fn new(key: &[u8]) -> Self {
+            return Self { key: hmac::Key::new(hmac::HMAC_SHA256, key) };
+        }
+
+        /// Pseudonymizes with the key `FHIR_AUDIT_IP_KEY`. Without one, a random key is used, so
+        /// the pseudonyms of an address change with every restart.
+        pub fn from_env() -> Self {
+            return match env::var_os("FHIR_AUDIT_IP_KEY") {
+                Some(key) => IpPseudonyms::new(key.into_string().unwrap().as_bytes()),
+                None => {
+                    warn!("FHIR_AUDIT_IP_KEY is not set, IP pseudonyms change with every restart");
+                    let mut key = [0u8; 32];
+                    SystemRandom::new().fill(&mut key).unwrap();
+                    IpPseudonyms::new(&key)
+                }
+            };
+        }
+
+        /// The same address always has the same pseudonym, but without the key it can't be
+        /// told which address that is.
+        pub fn pseudonymize(&self, ip: IpAddr) -> String {
+            let tag = hmac::sign(&self.key, ip.to_string().as_bytes());
+            return tag.as_ref()[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        }
+    }
+
+    /// Writes every request to a protected route to the append-only audit trail in Postgres,
+    /// including rejected ones.
+    #[derive(Clone)]
+    pub struct Audit {
+        db: Arc<Db>,
+        ips: IpPseudonyms,
+    }
+
+    impl Audit {
+        pub fn new(db: Arc<Db>, ips: IpPseudonyms) -> Self {
+            return Self { db, ips };
+        }
+
+        /// See [IpPseudonyms::from_env].
+        pub fn from_env(db: Arc<Db>) -> Self {
+            return Audit::new(db, IpPseudonyms::from_env());
+        }
+
+        /// Records the request after it was answered. Has to run before authentication, so that
+        /// rejected requests are recorded, too. Reads whose record can't be written are answered
+        /// with `503` instead, changes are already done then and only logged.
+        pub async fn audit_middleware(State(audit): State<Audit>,
+                                      mut req: Request<Body>,
+                                      next: Next) -> Response<Body> {
+            let Some(path) = req.extensions().get::<MatchedPath>().cloned() else {
+                return next.run(req).await;
+            };
+            let Some((resource_type, interaction)) = route_interaction(req.method(), path.as_str())
+            else {
+                return next.run(req).await;
+            };
+            let patients = AuditedPatients::default();
+            req.extensions_mut().insert(patients.clone());
+            let resource_id = path.as_str().ends_with('}').then(|| {
+                req.uri().path().rsplit('/').next().unwrap().to_string()
+            });
+            let operation = path.as_str()
+                                .rsplit('/')
+                                .next()
+                                .and_then(|last| last.strip_prefix('$'))
+                                .map(str::to_string);
+            let query = req.uri().query().map(str::to_string);
+            let break_glass = req.headers()
+                                 .get(X_BREAK_GLASS)
+                                 .and_then(|h| h.to_str().ok())
+                                 .map(str::trim)
+                                 .filter(|reason| !reason.is_empty())
+                                 .map(str::to_string);
+            let ip = req.extensions()
+                        .get::<ConnectInfo<SocketAddr>>()
+                        .map(|ConnectInfo(remote)| audit.ips.pseudonymize(remote.ip()));
+
+            let response = next.run(req).await;
+
+            let identity = response.extensions().get::<Identity>();
+            let entry = AuditEntry {
+                id: Uuid::new_v4(),
+                recorded: Utc::now(),
+                interaction,
+                operation,
+                resource_type: resource_type.to_string(),
+                resource_id,
+                query,
+                actor: identity.map(|i| i.subject.clone()),
+                client_id: identity.and_then(|i| i.client_id.clone()),
+                ip,
+                patients: patients.get(),
+                status: response.status().as_u16(),
+                break_glass,
+            };
+            if let Err(e) = audit.db.insert_audit_event(&entry).await {
+                error!(?e, ?entry, "Could not write audit trail");
+                let read = matches!(interaction, Interaction::Read | Interaction::Search);
+                if read && response.status().is_success() {
+                    return Response::builder().status(StatusCode::SERVICE_UNAVAILABLE)
+                                              .body(Body::empty())
+                                              .unwrap();
+                }
+            }
+            return response;
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use speculoos::assert_that;
+
+        fn entry(interaction: Interaction, status: u16) -> AuditEntry {
+            return AuditEntry {
+                id: Uuid::nil(),
+                recorded: DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z").unwrap().to_utc(),
+                interaction,
+                operation: None,
+                resource_type: "Observation".to_string(),
+                resource_id: Some("o1".to_string()),
+                query: None,
+                actor: Some("alice".to_string()),
+                client_id: Some("gui".to_string()),
+                ip: Some("0123456789abcdef".to_string()),
+                patients: vec!["p1".to_string()],
+                status,
+                break_glass: None,
+            };
+        }
+
+        #[test]
+        fn test_audit_event() {
+            let event = entry(Interaction::Read, 200).to_resource();
+            assert_that!(event["action"]).is_equal_to(json!("R"));
+            assert_that!(event["outcome"]).is_equal_to(json!("0"));
+            assert_that!(event["recorded"]).is_equal_to(json!("2025-01-02T03:04:05.000000Z"));
+            assert_that!(event["agent"][0]["altId"]).is_equal_to(json!("gui"));
+            assert_that!(event["entity"][0]["what"]["reference"]).is_equal_to(json!("Patient/p1"));
+            assert_that!(event["entity"][1]["what"]["reference"])
+                .is_equal_to(json!("Observation/o1"));
+
+            let mut search = entry(Interaction::Search, 403);
+            search.query = Some("subject=Patient/p2".to_string());
+            search.actor = None;
+            let event = search.to_resource();
+            assert_that!(event["action"]).is_equal_to(json!("E"));
+            assert_that!(event["outcome"]).is_equal_to(json!("4"));
+            assert_that!(event["agent"][0]["who"]["display"]).is_equal_to(json!("anonymous"));
+            assert_that!(event["entity"][1]["query"])
+                .is_equal_to(json!("c3ViamVjdD1QYXRpZW50L3Ay"));
+
+            let mut merge = entry(Interaction::Update, 500);
+            merge.operation = Some("merge".to_string());
+            let event = merge.to_resource();
+            assert_that!(event["subtype"][0]["code"]).is_equal_to(json!("operation"));
+            assert_that!(event["outcome"]).is_equal_to(json!("8"));
+
+            let mut break_glass = entry(Interaction::Read, 200);
+            break_glass.break_glass = Some("Emergency admission".to_string());
+            let event = break_glass.to_resource();
+            assert_that!(event["purposeOfEvent"][0]["coding"][0]["code"])
+                .is_equal_to(json!("BTG"));
+            assert_that!(event["purposeOfEvent"][0]["text"])
+                .is_equal_to(json!("Emergency admission"));
+            let event = entry(Interaction::Read, 200).to_resource();
+            assert_that!(event["purposeOfEvent"]).is_equal_to(Value::Null);
+        }
+
+        #[test]
+        fn test_pseudonyms() {
+            let ips = IpPseudonyms::new(b"key");
+            let ip: IpAddr = "192.168.1.17".parse().unwrap();
+
+            assert_that!(ips.pseudonymize(ip)).is_equal_to(ips.pseudonymize(ip));
+            assert_that!(ips.pseudonymize(ip).contains("192")).is_equal_to(false);
+            assert_that!(ips.pseudonymize(ip))
+                .is_not_equal_to(IpPseudonyms::new(b"other").pseudonymize(ip));
+        }
+    }
+}
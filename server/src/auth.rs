@@ -1,62 +1,381 @@
 pub mod auth {
-    use axum::extract::State;
+    use crate::api::api::route_interaction;
+    use crate::api_key::api_key::{ApiKeys, PREFIX};
+    use crate::introspection::introspection::{IntrospectionError, Introspector};
+    use crate::jwt::jwt::JwtValidator;
+    use crate::smart::smart::Access;
+    use crate::tls::tls::ClientCertificate;
+    use axum::extract::{MatchedPath, State};
     use axum::middleware::Next;
     use axum_core::body::Body;
     use axum_core::extract::Request;
     use axum_core::response::Response;
+    use http::header::WWW_AUTHENTICATE;
     use http::{HeaderValue, StatusCode};
+    use ring::digest::{digest, SHA256};
     use std::env;
+    use subtle::ConstantTimeEq;
+    use tracing::{error, info};
+
+    /// Scopes of the static read token.
+    const READ_TOKEN_SCOPES: [&str; 1] = ["system/*.rs"];
+    /// Scopes of the static write token.
+    const WRITE_TOKEN_SCOPES: [&str; 1] = ["system/*.cruds"];
+
+    /// Who is calling, as decided by [Auth::auth_middleware].
+    /// Added to the request extensions of every authorized request.
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct Identity {
+        pub subject: String,
+        /// The OAuth2 client the caller uses, if known.
+        pub client_id: Option<String>,
+        pub scopes: Vec<String>,
+        /// ID of the patient in context, limits what patient scopes grant.
+        pub patient: Option<String>,
+        /// Roles of the caller at the authorization server, see [crate::consent::consent::Consent].
+        pub roles: Vec<String>,
+    }
 
     #[derive(Clone)]
     pub struct Auth {
-        read_token: String,
-        write_token: String,
+        /// Static tokens, compared with the whole `Authorization` header.
+        read_token: Option<String>,
+        write_token: Option<String>,
+        jwt: Option<JwtValidator>,
+        /// Named keys of clients, bearer tokens starting with [PREFIX].
+        api_keys: Option<ApiKeys>,
+        /// For all other bearer tokens.
+        introspector: Option<Introspector>,
     }
 
     impl Auth {
-        pub fn new() -> Self {
-            let read_token = match env::var_os("FHIR_READ_TOKEN") {
-                Some(val) => val.into_string().unwrap(),
-                None => "read".to_string(),
+        /// Accepts the static tokens, if any, and the keys of the registry.
+        pub fn new(read_token: Option<String>,
+                   write_token: Option<String>,
+                   api_keys: ApiKeys,
+        ) -> Self {
+            return Self {
+                read_token,
+                write_token,
+                jwt: None,
+                api_keys: Some(api_keys),
+                introspector: None,
             };
-            let write_token = match env::var_os("FHIR_WRITE_TOKEN") {
-                None => "write".to_string(),
-                Some(val) => val.into_string().unwrap(),
+        }
+
+        /// Accepts JWT bearer tokens if `FHIR_JWKS_FILE` is set, see [JwtValidator::from_env],
+        /// other bearer tokens if `FHIR_INTROSPECTION_URL` is set, see [Introspector::from_env],
+        /// the keys of the registry, and the static tokens `FHIR_READ_TOKEN` and
+        /// `FHIR_WRITE_TOKEN`. There are no default static tokens, unset ones aren't accepted.
+        pub fn from_env(api_keys: ApiKeys) -> Self {
+            let static_token = |name: &str| env::var_os(name).map(|v| v.into_string().unwrap());
+            let read_token = static_token("FHIR_READ_TOKEN");
+            let write_token = static_token("FHIR_WRITE_TOKEN");
+            return Self {
+                jwt: JwtValidator::from_env(),
+                introspector: Introspector::from_env(),
+                ..Auth::new(read_token, write_token, api_keys)
             };
-            return Self { read_token, write_token };
         }
 
+        /// The caller presenting the `Authorization` header. `401` if it isn't valid, `503` if
+        /// that can't be decided because the DB or the introspection endpoint is unavailable.
+        async fn identify(&self, authorization: &str) -> Result<Identity, StatusCode> {
+            let bearer = authorization.strip_prefix("Bearer ");
+            if let Some(jwt) = &self.jwt
+                && let Some(token) = bearer
+                && token.matches('.').count() == 2 {
+                return jwt.validate(token).map_err(|e| {
+                    info!(%e, "Rejected bearer token");
+                    return StatusCode::UNAUTHORIZED;
+                });
+            }
+            if matches(&self.write_token, authorization) {
+                return Ok(Identity {
+                    subject: "write-token".to_string(),
+                    client_id: None,
+                    scopes: WRITE_TOKEN_SCOPES.map(str::to_string).to_vec(),
+                    patient: None,
+                    roles: Vec::new(),
+                });
+            }
+            if matches(&self.read_token, authorization) {
+                return Ok(Identity {
+                    subject: "read-token".to_string(),
+                    client_id: None,
+                    scopes: READ_TOKEN_SCOPES.map(str::to_string).to_vec(),
+                    patient: None,
+                    roles: Vec::new(),
+                });
+            }
+            if let Some(api_keys) = &self.api_keys
+                && let Some(key) = bearer.filter(|t| t.starts_with(PREFIX)) {
+                return match api_keys.identify(key).await {
+                    Ok(Some(identity)) => Ok(identity),
+                    Ok(None) => {
+                        info!("Rejected unknown, expired or revoked API key");
+                        Err(StatusCode::UNAUTHORIZED)
+                    }
+                    Err(e) => {
+                        error!(?e, "Could not look up API key");
+                        Err(StatusCode::SERVICE_UNAVAILABLE)
+                    }
+                };
+            }
+            if let Some(introspector) = &self.introspector
+                && let Some(token) = bearer {
+                return introspector.introspect(token).await.map_err(|e| {
+                    info!(%e, "Rejected bearer token");
+                    return match e {
+                        IntrospectionError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+                        _ => StatusCode::UNAUTHORIZED,
+                    };
+                });
+            }
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+
+        /// Authenticates the caller by its token, or else its [ClientCertificate], and checks that
+        /// its SMART scopes allow the interaction of the route. Adds the [Identity] and its
+        /// [Access] to the request.
         pub async fn auth_middleware(
             State(auth): State<Auth>,
-            req: Request<Body>,
+            mut req: Request<Body>,
             next: Next,
         ) -> Response<Body> {
-            return if let Some(Ok(token)) = req.headers()
-                                               .get("Authorization")
-                                               .map(HeaderValue::to_str) {
-                if req.method() == http::Method::POST || req.method() == http::Method::PUT {
-                    if token == auth.write_token {
-                        next.run(req).await
-                    } else {
-                        Response::builder().status(StatusCode::UNAUTHORIZED)
-                                           .body(Body::empty())
-                                           .unwrap()
-                    }
-                } else {
-                    // read API
-                    if token == auth.read_token || token == auth.write_token {
-                        next.run(req).await
-                    } else {
-                        Response::builder().status(StatusCode::UNAUTHORIZED)
-                                           .body(Body::empty())
-                                           .unwrap()
-                    }
+            let authorization = req.headers().get("Authorization").map(HeaderValue::to_str);
+            // system clients authenticated by their TLS certificate need no token
+            let certificate = req.extensions().get::<ClientCertificate>().cloned();
+            let identity = match (authorization, certificate) {
+                (Some(Ok(authorization)), _) => auth.identify(authorization).await,
+                (None, Some(ClientCertificate(identity))) => Ok(identity),
+                _ => return challenge(StatusCode::UNAUTHORIZED, None),
+            };
+            let identity = match identity {
+                Ok(identity) => identity,
+                Err(StatusCode::UNAUTHORIZED) => {
+                    return challenge(StatusCode::UNAUTHORIZED, Some("invalid_token"));
+                }
+                Err(status) => return Response::builder().status(status).body(Body::empty()).unwrap(),
+            };
+            // unmatched requests are answered by the fallback
+            if let Some(path) = req.extensions().get::<MatchedPath>() {
+                let access = route_interaction(req.method(), path.as_str())
+                    .and_then(|(resource_type, interaction)| {
+                        Access::granted(&identity.scopes,
+                                        identity.patient.as_deref(),
+                                        resource_type,
+                                        interaction)
+                    });
+                let Some(access) = access else {
+                    info!(subject = identity.subject, path = path.as_str(), "Insufficient scope");
+                    let mut response = challenge(StatusCode::FORBIDDEN, Some("insufficient_scope"));
+                    response.extensions_mut().insert(identity);
+                    return response;
+                };
+                req.extensions_mut().insert(access);
+            }
+            req.extensions_mut().insert(identity.clone());
+            let mut response = next.run(req).await;
+            // for the audit trail, which runs before authentication
+            response.extensions_mut().insert(identity);
+            return response;
+        }
+    }
+
+    /// Compares in constant time, so that the time taken reveals nothing about the token. Hashing
+    /// first hides the length of the token, too.
+    fn matches(token: &Option<String>, authorization: &str) -> bool {
+        let Some(token) = token else {
+            return false;
+        };
+        let expected = digest(&SHA256, token.as_bytes());
+        let presented = digest(&SHA256, authorization.as_bytes());
+        return expected.as_ref().ct_eq(presented.as_ref()).into();
+    }
+
+    /// Rejection with a `WWW-Authenticate` header as in RFC 6750.
+    fn challenge(status: StatusCode, error: Option<&str>) -> Response<Body> {
+        let challenge = match error {
+            Some(error) => format!("Bearer error=\"{}\"", error),
+            None => "Bearer".to_string(),
+        };
+        return Response::builder().status(status)
+                                  .header(WWW_AUTHENTICATE, challenge)
+                                  .body(Body::empty())
+                                  .unwrap();
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::introspection::introspection::tests::stub_endpoint;
+        use crate::jwt::jwt::tests::{claims, TestIssuer, AUDIENCE, ISSUER};
+        use axum::body::to_bytes;
+        use axum::middleware::from_fn_with_state;
+        use axum::routing::get;
+        use axum::{Extension, Router};
+        use http::Method;
+        use serde_json::json;
+        use speculoos::assert_that;
+        use tower::ServiceExt;
+
+        fn auth(issuer: &TestIssuer) -> Auth {
+            return Auth {
+                read_token: None,
+                write_token: None,
+                jwt: Some(JwtValidator::new(ISSUER, AUDIENCE, &issuer.jwks()).unwrap()),
+                api_keys: None,
+                introspector: None,
+            };
+        }
+
+        /// Status and the granted access of the request.
+        async fn call(auth: &Auth,
+                      method: Method,
+                      uri: &str,
+                      authorization: Option<&str>) -> (StatusCode, String) {
+            let granted = |Extension(access): Extension<Access>| async move { access.code() };
+            let app = Router::new()
+                .route("/fhir/patient", get(granted).put(granted))
+                .route("/fhir/patient/{patient_id}", get(granted).delete(granted))
+                .layer(from_fn_with_state(auth.clone(), Auth::auth_middleware));
+            let mut request = Request::builder().method(method).uri(uri);
+            if let Some(authorization) = authorization {
+                request = request.header("Authorization", authorization);
+            }
+            let response = app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+            let status = response.status();
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            return (status, String::from_utf8(body.to_vec()).unwrap());
+        }
+
+        #[tokio::test]
+        async fn test_bearer_tokens() {
+            let issuer = TestIssuer::new("key-1");
+            let auth = auth(&issuer);
+            let reader = format!("Bearer {}", issuer.token("alice", "openid user/Patient.rs"));
+            let writer = format!("Bearer {}", issuer.token("bob", "openid user/Patient.write"));
+            let forged = format!("Bearer {}", TestIssuer::new("key-1").token("bob", "user/*.*"));
+
+            assert_that!(call(&auth, Method::GET, "/fhir/patient/p1", Some(&reader)).await)
+                .is_equal_to((StatusCode::OK, "all".to_string()));
+            assert_that!(call(&auth, Method::PUT, "/fhir/patient", Some(&reader)).await.0)
+                .is_equal_to(StatusCode::FORBIDDEN);
+            assert_that!(call(&auth, Method::PUT, "/fhir/patient", Some(&writer)).await.0)
+                .is_equal_to(StatusCode::OK);
+            assert_that!(call(&auth, Method::GET, "/fhir/patient", Some(&writer)).await.0)
+                .is_equal_to(StatusCode::FORBIDDEN);
+            assert_that!(call(&auth, Method::GET, "/fhir/patient", Some(&forged)).await.0)
+                .is_equal_to(StatusCode::UNAUTHORIZED);
+            assert_that!(call(&auth, Method::GET, "/fhir/patient", None).await.0)
+                .is_equal_to(StatusCode::UNAUTHORIZED);
+            // static tokens are only accepted if they are configured
+            assert_that!(call(&auth, Method::GET, "/fhir/patient", Some("read")).await.0)
+                .is_equal_to(StatusCode::UNAUTHORIZED);
+        }
+
+        #[tokio::test]
+        async fn test_patient_scopes() {
+            let issuer = TestIssuer::new("key-1");
+            let auth = auth(&issuer);
+            let mut claims = claims("alice", "launch/patient patient/Patient.read");
+            let without_context = format!("Bearer {}", issuer.sign_claims(&claims));
+            claims["patient"] = json!("p1");
+            let with_context = format!("Bearer {}", issuer.sign_claims(&claims));
+
+            assert_that!(call(&auth, Method::GET, "/fhir/patient", Some(&with_context)).await)
+                .is_equal_to((StatusCode::OK, "patient:p1".to_string()));
+            assert_that!(call(&auth, Method::DELETE, "/fhir/patient/p1", Some(&with_context)).await.0)
+                .is_equal_to(StatusCode::FORBIDDEN);
+            assert_that!(call(&auth, Method::GET, "/fhir/patient", Some(&without_context)).await.0)
+                .is_equal_to(StatusCode::FORBIDDEN);
+        }
+
+        #[tokio::test]
+        async fn test_identities() {
+            let issuer = TestIssuer::new("key-1");
+            let auth = Auth {
+                read_token: Some("read".to_string()),
+                write_token: Some("write".to_string()),
+                ..auth(&issuer)
+            };
+
+            let identity = auth.identify(&format!("Bearer {}", issuer.token("alice", "a b"))).await;
+            assert_that!(identity.ok().map(|i| (i.subject, i.client_id, i.scopes))).is_equal_to(Some((
+                "alice".to_string(),
+                Some("gui".to_string()),
+                vec!["a".to_string(), "b".to_string()],
+            )));
+            assert_that!(auth.identify("read").await.map(|i| i.scopes))
+                .is_equal_to(Ok(vec!["system/*.rs".to_string()]));
+            assert_that!(auth.identify("write").await.map(|i| i.scopes))
+                .is_equal_to(Ok(vec!["system/*.cruds".to_string()]));
+            assert_that!(auth.identify("Bearer read").await).is_equal_to(Err(StatusCode::UNAUTHORIZED));
+            assert_that!(auth.identify("rea").await).is_equal_to(Err(StatusCode::UNAUTHORIZED));
+            assert_that!(auth.identify("readx").await).is_equal_to(Err(StatusCode::UNAUTHORIZED));
+        }
+
+        #[tokio::test]
+        async fn test_client_certificates() {
+            let issuer = TestIssuer::new("key-1");
+            let app = Router::new()
+                .route("/fhir/patient", get(|Extension(identity): Extension<Identity>| async move {
+                    identity.subject
+                }))
+                .layer(from_fn_with_state(auth(&issuer), Auth::auth_middleware));
+            let call = async |authorization: Option<String>| {
+                let mut request = Request::get("/fhir/patient").body(Body::empty()).unwrap();
+                request.extensions_mut().insert(ClientCertificate(Identity {
+                    subject: "lab-system".to_string(),
+                    client_id: Some("lab-system".to_string()),
+                    scopes: vec!["system/Patient.rs".to_string()],
+                    patient: None,
+                    roles: Vec::new(),
+                }));
+                if let Some(authorization) = authorization {
+                    request.headers_mut().insert("Authorization", authorization.parse().unwrap());
                 }
-            } else {
-                Response::builder().status(StatusCode::UNAUTHORIZED)
-                                   .body(Body::empty())
-                                   .unwrap()
+                let response = app.clone().oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                return (status, String::from_utf8(body.to_vec()).unwrap());
+            };
+
+            assert_that!(call(None).await)
+                .is_equal_to((StatusCode::OK, "lab-system".to_string()));
+            // a token takes precedence over the certificate of the connection
+            let token = format!("Bearer {}", issuer.token("alice", "system/Patient.rs"));
+            assert_that!(call(Some(token)).await)
+                .is_equal_to((StatusCode::OK, "alice".to_string()));
+            assert_that!(call(Some("Bearer invalid".to_string())).await.0)
+                .is_equal_to(StatusCode::UNAUTHORIZED);
+        }
+
+        #[tokio::test]
+        async fn test_introspected_tokens() {
+            let (url, _) = stub_endpoint().await;
+            let auth = Auth {
+                read_token: Some("read".to_string()),
+                write_token: None,
+                jwt: None,
+                api_keys: None,
+                introspector: Some(Introspector::new(&url, Some("fhir"), Some("s3cret=")).unwrap()),
+            };
+
+            assert_that!(call(&auth, Method::GET, "/fhir/patient", Some("Bearer good")).await)
+                .is_equal_to((StatusCode::OK, "patient:p1".to_string()));
+            assert_that!(call(&auth, Method::GET, "/fhir/patient", Some("Bearer bad")).await.0)
+                .is_equal_to(StatusCode::UNAUTHORIZED);
+            assert_that!(call(&auth, Method::GET, "/fhir/patient", Some("read")).await)
+                .is_equal_to((StatusCode::OK, "all".to_string()));
+
+            let unavailable = Auth {
+                introspector: Some(Introspector::new("http://127.0.0.1:9/", None, None).unwrap()),
+                ..auth
             };
+            assert_that!(call(&unavailable, Method::GET, "/fhir/patient", Some("Bearer good")).await.0)
+                .is_equal_to(StatusCode::SERVICE_UNAVAILABLE);
         }
     }
 }
@@ -0,0 +1,19 @@
+pub mod config {
+    use std::env;
+    use std::str::FromStr;
+    use tracing::error;
+
+    /// The value of the environment variable, or the default if it isn't set.
+    pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+        return match env::var_os(name) {
+            None => default,
+            Some(val) => match val.into_string().unwrap().parse() {
+                Ok(val) => val,
+                Err(_) => {
+                    error!("Invalid {}", name);
+                    panic!("Invalid {}", name);
+                }
+            },
+        };
+    }
+}
@@ -0,0 +1,250 @@
+pub mod consent {
+    use crate::auth::auth::Identity;
+    use axum::extract::State;
+    use axum::middleware::Next;
+    use axum_core::body::Body;
+    use axum_core::extract::Request;
+    use axum_core::response::{IntoResponse, Response};
+    use http::{HeaderValue, StatusCode};
+    use serde::Serialize;
+    use std::env;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use tracing::{error, info, warn};
+
+    /// Header of a break-the-glass access, with the reason for it.
+    pub const X_BREAK_GLASS: &str = "x-break-glass";
+
+    const CONFIDENTIALITY_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/v3-Confidentiality";
+    /// Restricted and very restricted, the confidentiality codes of VIPs, employees and opt-outs.
+    const RESTRICTED_CODES: [&str; 2] = ["R", "V"];
+    const RESTRICTED_ROLE: &str = "restricted-records";
+    const BREAK_GLASS_ROLE: &str = "break-glass";
+
+    /// A coding in `Meta.security` that marks a record.
+    #[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+    pub struct SecurityLabel {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub system: Option<String>,
+        pub code: String,
+    }
+
+    impl FromStr for SecurityLabel {
+        type Err = String;
+
+        /// `system|code`, or just the code for labels without a system.
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let (system, code) = match s.trim().rsplit_once('|') {
+                Some((system, code)) => (Some(system.to_string()), code),
+                None => (None, s.trim()),
+            };
+            if code.is_empty() || system.as_ref().is_some_and(|s| s.is_empty()) {
+                return Err(format!("Invalid security label: {}", s));
+            }
+            return Ok(Self { system, code: code.to_string() });
+        }
+    }
+
+    /// What of restricted records a request may see, added to the request extensions by
+    /// [Consent::consent_middleware].
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum Clearance {
+        /// Resources with one of the labels are left out, as are the resources of patients with
+        /// one of them.
+        Restricted(Arc<Vec<SecurityLabel>>),
+        /// The caller has the role for restricted records.
+        Role,
+        /// Break-the-glass access, with the reason the caller gave.
+        BreakGlass(String),
+    }
+
+    impl Clearance {
+        /// The labels of the records left out, empty if the request may see all.
+        pub fn hidden(&self) -> &[SecurityLabel] {
+            return match self {
+                Clearance::Restricted(labels) => labels,
+                _ => &[],
+            };
+        }
+
+        pub fn code(&self) -> &'static str {
+            return match self {
+                Clearance::Restricted(_) => "restricted",
+                Clearance::Role => "role",
+                Clearance::BreakGlass(_) => "break-glass",
+            };
+        }
+    }
+
+    /// Which records are restricted and who may see them.
+    #[derive(Clone, Debug)]
+    pub struct Consent {
+        labels: Arc<Vec<SecurityLabel>>,
+        role: String,
+        /// Role or scope of the callers who may break the glass.
+        break_glass: String,
+    }
+
+    impl Consent {
+        pub fn new(labels: Vec<SecurityLabel>, role: &str, break_glass: &str) -> Self {
+            return Self {
+                labels: Arc::new(labels),
+                role: role.to_string(),
+                break_glass: break_glass.to_string(),
+            };
+        }
+
+        /// Restricted records are labeled with one of `FHIR_RESTRICTED_LABELS`, comma separated
+        /// `system|code`, by default the confidentiality codes `R` and `V`. Callers with the role
+        /// `FHIR_RESTRICTED_ROLE`, by default `restricted-records`, may see them. Callers with the
+        /// role or scope `FHIR_BREAK_GLASS_ROLE`, by default `break-glass`, may break the glass.
+        pub fn from_env() -> Self {
+            let labels = match env::var_os("FHIR_RESTRICTED_LABELS") {
+                None => RESTRICTED_CODES.iter()
+                                        .map(|code| SecurityLabel {
+                                            system: Some(CONFIDENTIALITY_SYSTEM.to_string()),
+                                            code: code.to_string(),
+                                        })
+                                        .collect(),
+                Some(val) => match val.into_string()
+                                      .unwrap()
+                                      .split(',')
+                                      .filter(|l| !l.trim().is_empty())
+                                      .map(SecurityLabel::from_str)
+                                      .collect::<Result<Vec<_>, _>>() {
+                    Ok(labels) => labels,
+                    Err(e) => {
+                        error!(?e, "Invalid FHIR_RESTRICTED_LABELS");
+                        panic!("Invalid FHIR_RESTRICTED_LABELS");
+                    }
+                },
+            };
+            let var = |name: &str, default: &str| {
+                return env::var_os(name).map(|val| val.into_string().unwrap())
+                                        .unwrap_or(default.to_string());
+            };
+            return Consent::new(labels,
+                                &var("FHIR_RESTRICTED_ROLE", RESTRICTED_ROLE),
+                                &var("FHIR_BREAK_GLASS_ROLE", BREAK_GLASS_ROLE));
+        }
+
+        /// The clearance of the caller. Breaking the glass needs the break-glass role or scope,
+        /// and a reason.
+        pub fn clearance(&self,
+                         identity: &Identity,
+                         break_glass: Option<&HeaderValue>,
+        ) -> Result<Clearance, (StatusCode, String)> {
+            if let Some(reason) = break_glass {
+                if !identity.roles.contains(&self.break_glass)
+                    && !identity.scopes.contains(&self.break_glass) {
+                    return Err((StatusCode::FORBIDDEN,
+                                "The caller may not break the glass".to_string()));
+                }
+                let reason = reason.to_str()
+                                   .ok()
+                                   .map(str::trim)
+                                   .filter(|r| !r.is_empty())
+                                   .ok_or((StatusCode::BAD_REQUEST,
+                                           "X-Break-Glass needs a reason".to_string()))?;
+                return Ok(Clearance::BreakGlass(reason.to_string()));
+            }
+            if identity.roles.contains(&self.role) {
+                return Ok(Clearance::Role);
+            }
+            return Ok(Clearance::Restricted(self.labels.clone()));
+        }
+
+        /// Adds the [Clearance] of the caller to the request. Runs after authentication.
+        /// Break-the-glass accesses are logged, the audit trail records their reason.
+        pub async fn consent_middleware(State(consent): State<Consent>,
+                                        mut req: Request<Body>,
+                                        next: Next) -> Response<Body> {
+            let Some(identity) = req.extensions().get::<Identity>() else {
+                return next.run(req).await;
+            };
+            let clearance = match consent.clearance(identity, req.headers().get(X_BREAK_GLASS)) {
+                Ok(clearance) => clearance,
+                Err(e) => {
+                    info!(subject = identity.subject,
+                          reason = e.1,
+                          "Rejected break-the-glass access");
+                    return e.into_response();
+                }
+            };
+            if let Clearance::BreakGlass(reason) = &clearance {
+                warn!(subject = identity.subject,
+                      reason,
+                      path = req.uri().path(),
+                      "Break-the-glass access");
+            }
+            req.extensions_mut().insert(clearance);
+            return next.run(req).await;
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use speculoos::assert_that;
+        use speculoos::prelude::ResultAssertions;
+
+        fn identity(roles: &[&str]) -> Identity {
+            return Identity {
+                subject: "alice".to_string(),
+                client_id: None,
+                scopes: Vec::new(),
+                patient: None,
+                roles: roles.iter().map(|r| r.to_string()).collect(),
+            };
+        }
+
+        #[test]
+        fn test_security_labels() {
+            assert_that!(SecurityLabel::from_str("urn:labels|VIP")).is_ok().is_equal_to(SecurityLabel {
+                system: Some("urn:labels".to_string()),
+                code: "VIP".to_string(),
+            });
+            assert_that!(SecurityLabel::from_str("EMP")).is_ok().is_equal_to(SecurityLabel {
+                system: None,
+                code: "EMP".to_string(),
+            });
+            assert_that!(SecurityLabel::from_str("urn:labels|")).is_err();
+            assert_that!(SecurityLabel::from_str("|R")).is_err();
+        }
+
+        #[test]
+        fn test_clearance() {
+            let consent = Consent::new(vec![SecurityLabel::from_str("urn:labels|VIP").unwrap()],
+                                       "vip-team",
+                                       "emergency");
+
+            let restricted = consent.clearance(&identity(&[]), None).unwrap();
+            assert_that!(restricted.hidden().to_vec())
+                .is_equal_to(vec![SecurityLabel::from_str("urn:labels|VIP").unwrap()]);
+
+            let role = consent.clearance(&identity(&["vip-team"]), None).unwrap();
+            assert_that!(role).is_equal_to(Clearance::Role);
+            assert_that!(role.hidden().is_empty()).is_equal_to(true);
+
+            let reason = HeaderValue::from_static("Emergency admission");
+            assert_that!(consent.clearance(&identity(&["emergency"]), Some(&reason)))
+                .is_ok()
+                .is_equal_to(Clearance::BreakGlass("Emergency admission".to_string()));
+            let mut system = identity(&[]);
+            system.scopes.push("emergency".to_string());
+            assert_that!(consent.clearance(&system, Some(&reason))).is_ok();
+            let blank = HeaderValue::from_static(" ");
+            let blank_reason = consent.clearance(&identity(&["emergency"]), Some(&blank));
+            assert_that!(blank_reason.map_err(|e| e.0))
+                .is_err()
+                .is_equal_to(StatusCode::BAD_REQUEST);
+
+            // everybody else may not break the glass, not even with the role for restricted records
+            for roles in [&[][..], &["vip-team"][..]] {
+                assert_that!(consent.clearance(&identity(roles), Some(&reason)).map_err(|e| e.0))
+                    .is_err()
+                    .is_equal_to(StatusCode::FORBIDDEN);
+            }
+        }
+    }
+}
@@ -0,0 +1,220 @@
+pub mod cors {
+    use crate::consent::consent::X_BREAK_GLASS;
+    use crate::provenance::provenance::X_PROVENANCE;
+    use http::header::{
+        ACCEPT,
+        AUTHORIZATION,
+        CONTENT_TYPE,
+        ETAG,
+        IF_MATCH,
+        IF_NONE_MATCH,
+        LOCATION,
+        RETRY_AFTER,
+        WARNING,
+    };
+    use http::{HeaderName, HeaderValue, Method, Uri};
+    use std::env;
+    use std::str::FromStr;
+    use std::time::Duration;
+    use tower_http::cors::{AllowOrigin, CorsLayer};
+    use tracing::{error, info};
+
+    /// Default time browsers may cache the answer to a preflight request.
+    const MAX_AGE: Duration = Duration::from_secs(600);
+
+    /// Which websites may call the API from a browser, and with what.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Cors {
+        /// Empty if no website may, None if all may.
+        origins: Option<Vec<HeaderValue>>,
+        methods: Vec<Method>,
+        headers: Vec<HeaderName>,
+        /// Whether browsers send cookies along.
+        credentials: bool,
+        max_age: Duration,
+    }
+
+    impl Default for Cors {
+        /// No other website may call the API.
+        fn default() -> Self {
+            return Self {
+                origins: Some(Vec::new()),
+                methods: vec![Method::GET, Method::POST, Method::PUT, Method::DELETE],
+                headers: vec![
+                    AUTHORIZATION,
+                    ACCEPT,
+                    CONTENT_TYPE,
+                    IF_MATCH,
+                    IF_NONE_MATCH,
+                    HeaderName::from_static(X_PROVENANCE),
+                    HeaderName::from_static(X_BREAK_GLASS),
+                ],
+                credentials: false,
+                max_age: MAX_AGE,
+            };
+        }
+    }
+
+    /// An origin like `https://app.example.org`, without a path.
+    fn parse_origin(origin: &str) -> Result<HeaderValue, String> {
+        let uri = Uri::from_str(origin.trim()).map_err(|_| format!("Invalid origin: {}", origin))?;
+        let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+        if uri.scheme().is_none() || uri.authority().is_none() || path != "/" {
+            return Err(format!("Invalid origin, expected scheme://host[:port]: {}", origin));
+        }
+        return HeaderValue::from_str(origin.trim().trim_end_matches('/'))
+            .map_err(|_| format!("Invalid origin: {}", origin));
+    }
+
+    /// Comma separated items, None if the variable isn't set.
+    fn env_list<T>(name: &str, parse: impl Fn(&str) -> Result<T, String>) -> Option<Vec<T>> {
+        let val = env::var_os(name)?.into_string().unwrap();
+        return match val.split(',').filter(|v| !v.trim().is_empty()).map(parse).collect() {
+            Ok(items) => Some(items),
+            Err(e) => {
+                error!(?e, "Invalid {}", name);
+                panic!("Invalid {}", name);
+            }
+        };
+    }
+
+    impl Cors {
+        pub fn new(origins: Option<Vec<HeaderValue>>, credentials: bool) -> Result<Self, String> {
+            if origins.is_none() && credentials {
+                return Err("Credentials can't be allowed for all origins".to_string());
+            }
+            return Ok(Self { origins, credentials, ..Cors::default() });
+        }
+
+        /// Websites in `FHIR_CORS_ORIGINS`, comma separated, or `*` for all. Without it, no
+        /// website may call the API. `FHIR_CORS_METHODS` and `FHIR_CORS_HEADERS` replace the
+        /// default methods and request headers. `FHIR_CORS_CREDENTIALS` lets browsers send
+        /// cookies, which needs explicit origins. Preflights are cached for
+        /// `FHIR_CORS_MAX_AGE_SECONDS`.
+        pub fn from_env() -> Self {
+            let origins = match env::var_os("FHIR_CORS_ORIGINS").map(|v| v.into_string().unwrap()) {
+                Some(origins) if origins.trim() == "*" => None,
+                _ => Some(env_list("FHIR_CORS_ORIGINS", parse_origin).unwrap_or_default()),
+            };
+            let credentials = match env::var_os("FHIR_CORS_CREDENTIALS") {
+                None => false,
+                Some(val) => match val.into_string().unwrap().parse() {
+                    Ok(credentials) => credentials,
+                    Err(e) => {
+                        error!(?e, "Invalid FHIR_CORS_CREDENTIALS");
+                        panic!("Invalid FHIR_CORS_CREDENTIALS");
+                    }
+                },
+            };
+            let mut cors = match Cors::new(origins, credentials) {
+                Ok(cors) => cors,
+                Err(e) => {
+                    error!(?e, "Invalid CORS configuration");
+                    panic!("Invalid CORS configuration");
+                }
+            };
+            if let Some(methods) = env_list("FHIR_CORS_METHODS", |m| {
+                Method::from_str(m.trim()).map_err(|_| format!("Invalid method: {}", m))
+            }) {
+                cors.methods = methods;
+            }
+            if let Some(headers) = env_list("FHIR_CORS_HEADERS", |h| {
+                HeaderName::from_str(h.trim()).map_err(|_| format!("Invalid header: {}", h))
+            }) {
+                cors.headers = headers;
+            }
+            if let Some(max_age) = env::var_os("FHIR_CORS_MAX_AGE_SECONDS") {
+                cors.max_age = match max_age.into_string().unwrap().parse() {
+                    Ok(seconds) => Duration::from_secs(seconds),
+                    Err(e) => {
+                        error!(?e, "Invalid FHIR_CORS_MAX_AGE_SECONDS");
+                        panic!("Invalid FHIR_CORS_MAX_AGE_SECONDS");
+                    }
+                };
+            }
+            info!(?cors.origins, "Allowed CORS origins");
+            return cors;
+        }
+
+        /// Browsers may read the version and the location of written resources, as well as the
+        /// warnings about duplicates and when to retry after a rate limit.
+        pub fn layer(&self) -> CorsLayer {
+            let origin = match &self.origins {
+                None => AllowOrigin::any(),
+                Some(origins) => AllowOrigin::list(origins.clone()),
+            };
+            return CorsLayer::new().allow_origin(origin)
+                                   .allow_methods(self.methods.clone())
+                                   .allow_headers(self.headers.clone())
+                                   .allow_credentials(self.credentials)
+                                   .expose_headers([ETAG, LOCATION, WARNING, RETRY_AFTER])
+                                   .max_age(self.max_age);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use axum::body::Body;
+        use axum::routing::get;
+        use axum::Router;
+        use axum_core::extract::Request;
+        use http::header::{
+            ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            ACCESS_CONTROL_ALLOW_ORIGIN,
+            ACCESS_CONTROL_EXPOSE_HEADERS,
+            ACCESS_CONTROL_MAX_AGE,
+            ACCESS_CONTROL_REQUEST_METHOD,
+            ORIGIN,
+        };
+        use http::HeaderMap;
+        use speculoos::assert_that;
+        use speculoos::prelude::ResultAssertions;
+        use tower::ServiceExt;
+
+        #[test]
+        fn test_parse_origin() {
+            assert_that!(parse_origin("https://app.example.org"))
+                .is_ok()
+                .is_equal_to(HeaderValue::from_static("https://app.example.org"));
+            assert_that!(parse_origin("http://localhost:3000/"))
+                .is_ok()
+                .is_equal_to(HeaderValue::from_static("http://localhost:3000"));
+            for invalid in ["app.example.org", "https://app.example.org/fhir", "*"] {
+                assert_that!(parse_origin(invalid)).is_err();
+            }
+            assert_that!(Cors::new(None, true)).is_err();
+        }
+
+        #[tokio::test]
+        async fn test_cors_layer() {
+            let origin = HeaderValue::from_static("https://app.example.org");
+            let cors = Cors::new(Some(vec![origin.clone()]), true).unwrap();
+            let app = Router::new().route("/fhir/patient", get(async || "[]")).layer(cors.layer());
+            let send = async |method: Method, origin: &'static str| -> HeaderMap {
+                let request = Request::builder().method(method)
+                                                .uri("/fhir/patient")
+                                                .header(ORIGIN, origin)
+                                                .header(ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                                                .body(Body::empty())
+                                                .unwrap();
+                return app.clone().oneshot(request).await.unwrap().headers().clone();
+            };
+
+            let preflight = send(Method::OPTIONS, "https://app.example.org").await;
+            assert_that!(preflight.get(ACCESS_CONTROL_ALLOW_ORIGIN)).is_equal_to(Some(&origin));
+            assert_that!(preflight.get(ACCESS_CONTROL_ALLOW_CREDENTIALS))
+                .is_equal_to(Some(&HeaderValue::from_static("true")));
+            assert_that!(preflight.get(ACCESS_CONTROL_MAX_AGE))
+                .is_equal_to(Some(&HeaderValue::from_static("600")));
+
+            let get = send(Method::GET, "https://app.example.org").await;
+            let exposed = get.get(ACCESS_CONTROL_EXPOSE_HEADERS).unwrap().to_str().unwrap();
+            assert_that!(exposed.contains("etag") && exposed.contains("location"))
+                .is_equal_to(true);
+
+            let other = send(Method::GET, "https://evil.example.com").await;
+            assert_that!(other.get(ACCESS_CONTROL_ALLOW_ORIGIN)).is_equal_to(None);
+        }
+    }
+}
@@ -1,17 +1,37 @@
 pub mod db {
-    use crate::model::model::{Patient, PatientSearch, PatientStub};
+    use crate::api_key::api_key::ApiKey;
+    use crate::audit::audit::AuditEntry;
+    use crate::consent::consent::SecurityLabel;
+    use crate::encryption::encryption::{Encryption, ENVELOPE};
+    use crate::integrity::integrity::ReferenceIntegrity;
+    use crate::model::model::{AuditEventSearch, Encounter, Observation, Patient, PatientStub, Provenance};
+    use crate::resource::resource::{
+        security_index,
+        ResourceSearch,
+        SearchHit,
+        SearchIndex,
+        StoredResource,
+    };
+    use crate::smart::smart::Interaction;
     use axum::Json;
+    use chrono::{DateTime, Utc};
     use deadpool::managed::{Object, Pool};
-    use deadpool_postgres::Manager;
+    use deadpool_postgres::{GenericClient, Manager};
+    use serde::de::DeserializeOwned;
     use serde_json::Value;
     use std::error::Error;
     use std::fmt::{Display, Formatter};
-    use tokio_postgres::NoTls;
+    use tokio_postgres::error::SqlState;
+    use tokio_postgres::config::SslMode;
+    use tokio_postgres::{NoTls, Row};
+    use tokio_postgres_rustls::MakeRustlsConnect;
     use tracing::error;
     use uuid::Uuid;
 
     pub struct Db {
         pool: Pool<Manager, Object<Manager>>,
+        /// Encrypts elements of resources at rest, None if they are stored in plain.
+        encryption: Option<Encryption>,
     }
 
     #[derive(Debug)]
@@ -21,30 +41,78 @@ pub mod db {
 
     impl Display for NotFound {
         fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-            return f.write_str(format!("NotFound {{ id={} }}", self.id.to_string()).as_str());
+            return f.write_str(format!("NotFound {{ id={} }}", self.id).as_str());
         }
     }
 
     impl Error for NotFound {}
 
+    /// The resource cannot be deleted because other resources reference it.
+    #[derive(Debug)]
+    pub struct Referenced {
+        id: Uuid,
+        pub by: Vec<String>,
+    }
+
+    impl Display for Referenced {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            return f.write_str(format!("Referenced {{ id={}, by={} }}",
+                                       self.id,
+                                       self.by.join(",")).as_str());
+        }
+    }
+
+    impl Error for Referenced {}
+
+    /// The resource was changed or deleted since it was read.
+    #[derive(Debug)]
+    pub struct Changed {
+        pub id: Uuid,
+    }
+
+    impl Display for Changed {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            return f.write_str(format!("Changed {{ id={} }}", self.id).as_str());
+        }
+    }
+
+    impl Error for Changed {}
+
     impl Db {
         pub fn create_connection(dbname: &str,
                                  host: &str,
                                  password: &str,
                                  username: &str,
                                  port: u16,
+        ) -> Self {
+            return Db::create_tls_connection(dbname, host, password, username, port, None);
+        }
+
+        /// Connects over TLS if there is a connector, see [crate::tls::tls::DbTls].
+        pub fn create_tls_connection(dbname: &str,
+                                     host: &str,
+                                     password: &str,
+                                     username: &str,
+                                     port: u16,
+                                     tls: Option<MakeRustlsConnect>,
         ) -> Self {
             // Setup config
             let mut config = tokio_postgres::Config::new();
-            config.dbname(dbname.to_string())
+            config.dbname(dbname)
                   .host(host.to_string())
-                  .password(password.to_string())
+                  .password(password)
                   .user(username.to_string())
                   .port(port);
             let manager_config = deadpool_postgres::ManagerConfig {
                 recycling_method: deadpool_postgres::RecyclingMethod::Fast,
             };
-            let mgr = Manager::from_config(config, NoTls, manager_config);
+            let mgr = match tls {
+                Some(tls) => {
+                    config.ssl_mode(SslMode::Require);
+                    Manager::from_config(config, tls, manager_config)
+                }
+                None => Manager::from_config(config, NoTls, manager_config),
+            };
 
             // Setup connection pool
             let pool = match Pool::builder(mgr)
@@ -56,28 +124,154 @@ pub mod db {
                     panic!("Could not build a connection pool for DB");
                 }
             };
-            Self { pool }
+            Self { pool, encryption: None }
+        }
+
+        /// Encrypts the configured elements of all resources written from now on, and decrypts
+        /// them when reading.
+        pub fn with_encryption(mut self, encryption: Encryption) -> Self {
+            self.encryption = Some(encryption);
+            return self;
         }
 
-        /// Updates or inserts the patient into the DB.
+        pub fn encryption(&self) -> Option<&Encryption> {
+            return self.encryption.as_ref();
+        }
+
+        /// Updates or inserts the resource into the DB and replaces its search index.
         /// Assumption: Nested documents have IDs assigned where appropriate.
-        /// Sets the id of patient if it isn't set already.
-        /// Returns patient.id.
-        pub async fn upsert_patient(&self,
-                                    patient: &mut Patient,
+        /// Sets the id of the resource if it isn't set already.
+        /// Fails with [Unresolvable] if its references don't pass the integrity check.
+        /// Returns the id of the resource.
+        pub async fn upsert_resource<R: StoredResource>(&self,
+                                                        resource: &R,
+                                                        integrity: ReferenceIntegrity,
         ) -> Result<Uuid, Box<dyn Error>> {
-            let client = self.pool.get().await?;
-            let json = serde_json::to_value(patient)?;
-            let row = client.query_one("SELECT fhir.upsert_patient($1);", &[&json])
+            let mut client = self.pool.get().await?;
+            let transaction = client.transaction().await?;
+            Db::check_references(&transaction, resource, integrity).await?;
+            let id = self.upsert_with(&transaction, resource).await?;
+            transaction.commit().await?;
+            return Ok(id);
+        }
+
+        /// Upserts the resource together with its provenance in one transaction. The provenance
+        /// is completed with the ID and version of the resource written. The references of both
+        /// have to pass the integrity check, see [Db::upsert_resource].
+        /// Returns the id of the resource.
+        pub async fn upsert_with_provenance<R: StoredResource>(
+            &self,
+            resource: &R,
+            integrity: ReferenceIntegrity,
+            provenance: impl FnOnce(Uuid, &str) -> Provenance,
+        ) -> Result<Uuid, Box<dyn Error>> {
+            let mut client = self.pool.get().await?;
+            let transaction = client.transaction().await?;
+            Db::check_references(&transaction, resource, integrity).await?;
+            let id = self.upsert_with(&transaction, resource).await?;
+            let version: String = transaction.query_one(
+                "SELECT data -> 'meta' ->> 'versionId' FROM fhir.resource \
+                 WHERE resource_type = $1 AND id = $2;",
+                &[&R::RESOURCE_TYPE, &id]).await?.get(0);
+            let provenance = provenance(id, &version);
+            Db::check_references(&transaction, &provenance, integrity).await?;
+            self.upsert_with(&transaction, &provenance).await?;
+            transaction.commit().await?;
+            return Ok(id);
+        }
+
+        /// Checks the references of the resource. The referenced resources stay locked until the
+        /// transaction ends, so that they can't be deleted before the resource is stored.
+        async fn check_references<R: StoredResource>(client: &impl GenericClient,
+                                                     resource: &R,
+                                                     integrity: ReferenceIntegrity,
+        ) -> Result<(), Box<dyn Error>> {
+            let targets = integrity.targets(resource);
+            if targets.is_empty() {
+                return Ok(());
+            }
+            let missing = client.query_one("SELECT fhir.lock_references($1);", &[&targets])
+                                .await?
+                                .get(0);
+            integrity.check(R::RESOURCE_TYPE, missing)?;
+            return Ok(());
+        }
+
+        /// Stores both patients of a merge together with its provenance in one transaction.
+        /// Both have to be stored still in the version of their meta, the one that was merged,
+        /// otherwise it fails with [Changed]. References are checked like in
+        /// [Db::upsert_resource].
+        pub async fn store_merge(&self,
+                                 source: &Patient,
+                                 target: &Patient,
+                                 provenance: &Provenance,
+                                 integrity: ReferenceIntegrity,
+        ) -> Result<(), Box<dyn Error>> {
+            let mut client = self.pool.get().await?;
+            let transaction = client.transaction().await?;
+            let mut read = Vec::new();
+            for patient in [source, target] {
+                let id = Uuid::parse_str(patient.id.as_deref().unwrap_or_default())?;
+                let version = patient.meta.as_ref().and_then(|m| m.version_id.clone());
+                read.push((id, version));
+            }
+            let ids: Vec<Uuid> = read.iter().map(|(id, _)| *id).collect();
+            // locked in the order of their IDs, so that concurrent merges can't deadlock
+            let rows = transaction.query(
+                "SELECT id, data -> 'meta' ->> 'versionId' FROM fhir.resource \
+                 WHERE resource_type = $1 AND id = ANY($2) \
+                 ORDER BY id \
+                 FOR UPDATE;",
+                &[&Patient::RESOURCE_TYPE, &ids]).await?;
+            let stored: Vec<(Uuid, Option<String>)> = rows.iter()
+                                                          .map(|row| (row.get(0), row.get(1)))
+                                                          .collect();
+            if let Some((id, _)) = read.iter().find(|r| !stored.contains(r)) {
+                return Err(Box::new(Changed { id: *id }));
+            }
+            Db::check_references(&transaction, source, integrity).await?;
+            Db::check_references(&transaction, target, integrity).await?;
+            Db::check_references(&transaction, provenance, integrity).await?;
+            self.upsert_with(&transaction, source).await?;
+            self.upsert_with(&transaction, target).await?;
+            self.upsert_with(&transaction, provenance).await?;
+            transaction.commit().await?;
+            return Ok(());
+        }
+
+        async fn upsert_with<R: StoredResource>(&self,
+                                                client: &impl GenericClient,
+                                                resource: &R,
+        ) -> Result<Uuid, Box<dyn Error>> {
+            let mut json = serde_json::to_value(resource)?;
+            if let Some(encryption) = &self.encryption {
+                encryption.encrypt(R::RESOURCE_TYPE, &mut json)?;
+            }
+            let index = self.search_index(resource)?;
+            let row = client.query_one("SELECT fhir.upsert_resource($1, $2, $3);",
+                                       &[&R::RESOURCE_TYPE, &json, &index])
                             .await?;
             return Ok(row.get(0));
         }
 
-        /// Returns the patient with the ID.
-        pub async fn get_patient(
+        /// The entries of the resource in the search index, blinded where it is encrypted.
+        fn search_index<R: StoredResource>(&self, resource: &R) -> Result<Value, Box<dyn Error>> {
+            let mut index = resource.search_index();
+            index.extend(resource.references()
+                                 .into_iter()
+                                 .filter_map(|(param, r)| SearchIndex::reference(param, r)));
+            index.extend(security_index(resource.meta()));
+            if let Some(encryption) = &self.encryption {
+                encryption.blind_index(R::RESOURCE_TYPE, &mut index);
+            }
+            return Ok(serde_json::to_value(index)?);
+        }
+
+        /// Returns the resource of type R with the ID.
+        pub async fn get_resource<R: StoredResource>(
             &self,
-            patient_id: Uuid,
-        ) -> Result<Patient, Box<dyn Error>> {
+            resource_id: Uuid,
+        ) -> Result<R, Box<dyn Error>> {
             let client = match self.pool.get().await {
                 Ok(client) => client,
                 Err(error) => {
@@ -85,15 +279,254 @@ pub mod db {
                     return Err(error.into());
                 }
             };
-            let row = client.query_one("SELECT fhir.get_patient($1)", &[&patient_id]).await?;
+            let row = client.query_one("SELECT fhir.get_resource($1, $2)",
+                                       &[&R::RESOURCE_TYPE, &resource_id]).await?;
 
             return if let Some(res) = row.get(0) {
-                Ok(serde_json::from_value(res)?)
+                self.decode(res)
             } else {
-                Err(Box::new(NotFound { id: patient_id.clone() }))
+                Err(Box::new(NotFound { id: resource_id }))
             };
         }
 
+        /// Returns the resource of type R with the ID, unless it is hidden by one of the labels,
+        /// see [ResourceSearch::hidden]. Hidden resources are [NotFound].
+        pub async fn get_visible_resource<R: StoredResource>(
+            &self,
+            resource_id: Uuid,
+            hidden: &[SecurityLabel],
+        ) -> Result<R, Box<dyn Error>> {
+            if hidden.is_empty() {
+                return self.get_resource(resource_id).await;
+            }
+            let client = self.pool.get().await?;
+            let row = client.query_one(
+                "SELECT CASE WHEN fhir.is_restricted($1, $2, $3) THEN NULL \
+                             ELSE fhir.get_resource($1, $2) END;",
+                &[&R::RESOURCE_TYPE, &resource_id, &serde_json::to_value(hidden)?]).await?;
+            return match row.get::<_, Option<Value>>(0) {
+                Some(res) => self.decode(res),
+                None => Err(Box::new(NotFound { id: resource_id })),
+            };
+        }
+
+        /// Whether the stored resource is hidden by one of the labels, see
+        /// [ResourceSearch::hidden]. False if there is no such resource.
+        pub async fn is_hidden<R: StoredResource>(
+            &self,
+            resource_id: Uuid,
+            hidden: &[SecurityLabel],
+        ) -> Result<bool, Box<dyn Error>> {
+            if hidden.is_empty() {
+                return Ok(false);
+            }
+            let client = self.pool.get().await?;
+            let row = client.query_one(
+                "SELECT fhir.is_restricted($1, $2, $3);",
+                &[&R::RESOURCE_TYPE, &resource_id, &serde_json::to_value(hidden)?]).await?;
+            return Ok(row.get(0));
+        }
+
+        /// The resource as stored, decrypted.
+        fn decode<R: DeserializeOwned>(&self, mut resource: Value) -> Result<R, Box<dyn Error>> {
+            if resource.get(ENVELOPE).is_some() {
+                let encryption = self.encryption
+                                     .as_ref()
+                                     .ok_or("The resource is encrypted, but there are no keys")?;
+                encryption.decrypt(&mut resource)?;
+            }
+            return Ok(serde_json::from_value(resource)?);
+        }
+
+        /// Deletes the resource of type R with the ID, including its search index.
+        /// Fails with [Referenced] if other resources still reference it.
+        pub async fn delete_resource<R: StoredResource>(
+            &self,
+            resource_id: Uuid,
+        ) -> Result<(), Box<dyn Error>> {
+            let mut client = self.pool.get().await?;
+            let transaction = client.transaction().await?;
+            // waits for writes of resources referencing it, see Db::check_references
+            transaction.execute("SELECT 1 FROM fhir.resource \
+                                 WHERE resource_type = $1 AND id = $2 \
+                                 FOR UPDATE;",
+                                &[&R::RESOURCE_TYPE, &resource_id]).await?;
+            let by: Vec<String> = transaction.query_one("SELECT fhir.referenced_by($1, $2);",
+                                                        &[&R::RESOURCE_TYPE, &resource_id])
+                                             .await?
+                                             .get(0);
+            if !by.is_empty() {
+                return Err(Box::new(Referenced { id: resource_id, by }));
+            }
+            let row = transaction.query_one("SELECT fhir.delete_resource($1, $2);",
+                                            &[&R::RESOURCE_TYPE, &resource_id]).await?;
+            transaction.commit().await?;
+            return if row.get(0) {
+                Ok(())
+            } else {
+                Err(Box::new(NotFound { id: resource_id }))
+            };
+        }
+
+        /// Allows for searching resources of type R.
+        pub async fn search_resources<R: StoredResource>(
+            &self,
+            search: &ResourceSearch,
+        ) -> Result<Vec<SearchHit<R>>, Box<dyn Error>> {
+            let mut search = search.clone();
+            if let Some(encryption) = &self.encryption {
+                encryption.blind_criteria(R::RESOURCE_TYPE, &mut search.criteria);
+            }
+            let client = self.pool.get().await?;
+            let row = client.query_one(
+                "SELECT fhir.search_resources($1, $2);",
+                &[&R::RESOURCE_TYPE, &serde_json::to_value(search)?]).await?;
+            let r = row.get(0);
+            let hits = match r {
+                Value::String(s) => serde_json::from_str::<Vec<SearchHit<Value>>>(s.as_str())?,
+                Value::Array(_) => serde_json::from_value::<Vec<SearchHit<Value>>>(r)?,
+                v => return Err(format!("Unknown JSON type: {}", v).into())
+            };
+            return hits.into_iter()
+                       .map(|hit| Ok(SearchHit {
+                           id: hit.id,
+                           resource: self.decode(hit.resource)?,
+                           iteration_key: hit.iteration_key,
+                       }))
+                       .collect();
+        }
+
+        /// Rebuilds the search index of all resources, e.g. of those moved by
+        /// `02-migrate-patient.sql`. The resources themselves are left as they are.
+        /// Returns how many were indexed.
+        pub async fn reindex(&self) -> Result<usize, Box<dyn Error>> {
+            let mut indexed = 0;
+            indexed += self.reindex_of::<Patient>().await?;
+            indexed += self.reindex_of::<Observation>().await?;
+            indexed += self.reindex_of::<Encounter>().await?;
+            indexed += self.reindex_of::<Provenance>().await?;
+            return Ok(indexed);
+        }
+
+        async fn reindex_of<R: StoredResource>(&self) -> Result<usize, Box<dyn Error>> {
+            let mut client = self.pool.get().await?;
+            let mut indexed = 0;
+            let mut after: Option<Uuid> = None;
+            loop {
+                let ids: Vec<Uuid> = client.query(
+                    "SELECT id FROM fhir.resource \
+                     WHERE resource_type = $1 AND ($2::UUID IS NULL OR id > $2) \
+                     ORDER BY id \
+                     LIMIT 100;",
+                    &[&R::RESOURCE_TYPE, &after]).await?
+                                           .iter()
+                                           .map(|row| row.get(0))
+                                           .collect();
+                let Some(last) = ids.last() else {
+                    return Ok(indexed);
+                };
+                after = Some(*last);
+                for id in ids {
+                    // locked, so that the index matches the stored version
+                    let transaction = client.transaction().await?;
+                    let Some(row) = transaction.query_opt(
+                        "SELECT data FROM fhir.resource \
+                         WHERE resource_type = $1 AND id = $2 \
+                         FOR UPDATE;",
+                        &[&R::RESOURCE_TYPE, &id]).await? else {
+                        // deleted in the meantime
+                        continue;
+                    };
+                    let resource: R = self.decode(row.get(0))?;
+                    let index = self.search_index(&resource)?;
+                    transaction.execute("SELECT fhir.replace_search_index($1, $2, $3);",
+                                        &[&R::RESOURCE_TYPE, &id, &index]).await?;
+                    transaction.commit().await?;
+                    indexed += 1;
+                }
+            }
+        }
+
+        /// Wraps the data keys of all resources with the current key, and encrypts the resources
+        /// stored in plain that have elements to encrypt. Returns how many were changed.
+        pub async fn rotate_keys(&self) -> Result<usize, Box<dyn Error>> {
+            let encryption = self.encryption.as_ref().ok_or("Encryption isn't configured")?;
+            let mut changed = 0;
+            changed += self.rotate_keys_of::<Patient>(encryption).await?;
+            changed += self.rotate_keys_of::<Observation>(encryption).await?;
+            changed += self.rotate_keys_of::<Encounter>(encryption).await?;
+            changed += self.rotate_keys_of::<Provenance>(encryption).await?;
+            return Ok(changed);
+        }
+
+        async fn rotate_keys_of<R: StoredResource>(&self,
+                                                   encryption: &Encryption,
+        ) -> Result<usize, Box<dyn Error>> {
+            let mut client = self.pool.get().await?;
+            let mut changed = 0;
+            let mut after: Option<Uuid> = None;
+            loop {
+                let ids: Vec<Uuid> = client.query(
+                    "SELECT id FROM fhir.resource \
+                     WHERE resource_type = $1 AND ($2::UUID IS NULL OR id > $2) \
+                     ORDER BY id \
+                     LIMIT 100;",
+                    &[&R::RESOURCE_TYPE, &after]).await?
+                                           .iter()
+                                           .map(|row| row.get(0))
+                                           .collect();
+                let Some(last) = ids.last() else {
+                    return Ok(changed);
+                };
+                after = Some(*last);
+                for id in ids {
+                    // locked, so that writes in the meantime aren't reverted
+                    let transaction = client.transaction().await?;
+                    let Some(row) = transaction.query_opt(
+                        "SELECT data FROM fhir.resource \
+                         WHERE resource_type = $1 AND id = $2 \
+                         FOR UPDATE;",
+                        &[&R::RESOURCE_TYPE, &id]).await? else {
+                        // deleted in the meantime
+                        continue;
+                    };
+                    let mut data: Value = row.get(0);
+                    if data.get(ENVELOPE).is_some() {
+                        // only the envelope changes, this is no new version of the resource
+                        if encryption.rewrap(&mut data)? {
+                            transaction.execute(
+                                "UPDATE fhir.resource SET data = $3 \
+                                 WHERE resource_type = $1 AND id = $2;",
+                                &[&R::RESOURCE_TYPE, &id, &data]).await?;
+                            changed += 1;
+                        }
+                    } else {
+                        let resource: R = serde_json::from_value(data.clone())?;
+                        if encryption.encrypt(R::RESOURCE_TYPE, &mut data)? {
+                            // no new version either, only the index moves to blind values
+                            let index = self.search_index(&resource)?;
+                            transaction.execute(
+                                "UPDATE fhir.resource SET data = $3 \
+                                 WHERE resource_type = $1 AND id = $2;",
+                                &[&R::RESOURCE_TYPE, &id, &data]).await?;
+                            transaction.execute("SELECT fhir.replace_search_index($1, $2, $3);",
+                                                &[&R::RESOURCE_TYPE, &id, &index]).await?;
+                            changed += 1;
+                        }
+                    }
+                    transaction.commit().await?;
+                }
+            }
+        }
+
+        /// Whether the DB answers queries, for health checks.
+        pub async fn is_available(&self) -> bool {
+            let Ok(client) = self.pool.get().await else {
+                return false;
+            };
+            return client.simple_query("SELECT 1;").await.is_ok();
+        }
+
         /// Creates a unique identifier across the DB that can be used for any kind of object.
         pub async fn get_id(&self) -> Result<String, Box<dyn Error>> {
             let client = self.pool.get().await?;
@@ -104,50 +537,180 @@ pub mod db {
                     .to_string());
         }
 
+        /// Stores a new API key, fails if there is already an unrevoked one with the name.
+        pub async fn insert_api_key(&self,
+                                    name: &str,
+                                    key_hash: &[u8],
+                                    scopes: &[String],
+                                    expires_at: Option<DateTime<Utc>>,
+        ) -> Result<(), Box<dyn Error>> {
+            let client = self.pool.get().await?;
+            let inserted = client.execute(
+                "INSERT INTO fhir.api_key (name, key_hash, scopes, expires_at) \
+                 VALUES ($1, $2, $3, $4);",
+                &[&name, &key_hash, &scopes, &expires_at]).await;
+            return match inserted {
+                Ok(_) => Ok(()),
+                Err(e) if e.code() == Some(&SqlState::UNIQUE_VIOLATION) => {
+                    Err(format!("There already is an active API key {}", name).into())
+                }
+                Err(e) => Err(e.into()),
+            };
+        }
+
+        /// Revokes the API key, false if there is no unrevoked key with the name.
+        pub async fn revoke_api_key(&self, name: &str) -> Result<bool, Box<dyn Error>> {
+            let client = self.pool.get().await?;
+            let revoked = client.execute(
+                "UPDATE fhir.api_key SET revoked_at = NOW() WHERE name = $1 AND revoked_at IS NULL;",
+                &[&name]).await?;
+            return Ok(revoked == 1);
+        }
+
+        /// The API key with the hash, whether it is active or not.
+        pub async fn find_api_key(&self, key_hash: &[u8]) -> Result<Option<ApiKey>, Box<dyn Error>> {
+            let client = self.pool.get().await?;
+            let row = client.query_opt(
+                "SELECT name, scopes, created_at, expires_at, revoked_at \
+                 FROM fhir.api_key WHERE key_hash = $1;",
+                &[&key_hash]).await?;
+            return Ok(row.as_ref().map(Db::api_key));
+        }
+
+        /// All API keys, including revoked ones, ordered by name and age.
+        pub async fn api_keys(&self) -> Result<Vec<ApiKey>, Box<dyn Error>> {
+            let client = self.pool.get().await?;
+            let rows = client.query(
+                "SELECT name, scopes, created_at, expires_at, revoked_at \
+                 FROM fhir.api_key ORDER BY name, created_at;",
+                &[]).await?;
+            return Ok(rows.iter().map(Db::api_key).collect());
+        }
+
+        fn api_key(row: &Row) -> ApiKey {
+            return ApiKey {
+                name: row.get(0),
+                scopes: row.get(1),
+                created_at: row.get(2),
+                expires_at: row.get(3),
+                revoked_at: row.get(4),
+            };
+        }
+
+        /// Appends the entry to the audit trail.
+        pub async fn insert_audit_event(&self, entry: &AuditEntry) -> Result<(), Box<dyn Error>> {
+            let client = self.pool.get().await?;
+            client.execute(
+                "INSERT INTO fhir.audit_event (id, recorded, interaction, operation, resource_type, \
+                 resource_id, query, actor, client_id, ip, patient_ids, status, break_glass) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13);",
+                &[&entry.id, &entry.recorded, &entry.interaction.code(), &entry.operation,
+                    &entry.resource_type, &entry.resource_id, &entry.query, &entry.actor,
+                    &entry.client_id, &entry.ip, &entry.patients, &(entry.status as i32),
+                    &entry.break_glass]).await?;
+            return Ok(());
+        }
+
+        pub async fn get_audit_event(&self, id: Uuid) -> Result<AuditEntry, Box<dyn Error>> {
+            let client = self.pool.get().await?;
+            let row = client.query_opt(
+                "SELECT * FROM fhir.audit_event WHERE id = $1;",
+                &[&id]).await?;
+            return match row {
+                Some(row) => Ok(Db::audit_entry(&row)?),
+                None => Err(Box::new(NotFound { id })),
+            };
+        }
+
+        /// Entries of the audit trail in the order they were recorded, starting after the
+        /// entry recorded at `after` with the ID.
+        pub async fn search_audit_events(&self,
+                                         search: &AuditEventSearch,
+                                         after: Option<(DateTime<Utc>, Uuid)>,
+        ) -> Result<Vec<AuditEntry>, Box<dyn Error>> {
+            let client = self.pool.get().await?;
+            let (after_recorded, after_id) = after.unzip();
+            let rows = client.query(
+                "SELECT * FROM fhir.audit_event \
+                 WHERE ($1::TEXT IS NULL OR $1 = ANY (patient_ids)) \
+                   AND ($2::TEXT IS NULL OR actor = $2) \
+                   AND ($3::TIMESTAMPTZ IS NULL OR recorded >= $3) \
+                   AND ($4::TIMESTAMPTZ IS NULL OR recorded <= $4) \
+                   AND ($5::TIMESTAMPTZ IS NULL OR (recorded, id) > ($5, $6::UUID)) \
+                 ORDER BY recorded, id \
+                 LIMIT $7;",
+                &[&search.patient, &search.agent, &search.recorded_from, &search.recorded_until,
+                    &after_recorded, &after_id, &(search.count.min(100) as i64)]).await?;
+            return rows.iter().map(Db::audit_entry).collect();
+        }
+
+        fn audit_entry(row: &Row) -> Result<AuditEntry, Box<dyn Error>> {
+            let interaction: String = row.try_get("interaction")?;
+            let status: i32 = row.try_get("status")?;
+            return Ok(AuditEntry {
+                id: row.try_get("id")?,
+                recorded: row.try_get("recorded")?,
+                interaction: Interaction::from_code(&interaction)
+                    .ok_or(format!("Unknown interaction {}", interaction))?,
+                operation: row.try_get("operation")?,
+                resource_type: row.try_get("resource_type")?,
+                resource_id: row.try_get("resource_id")?,
+                query: row.try_get("query")?,
+                actor: row.try_get("actor")?,
+                client_id: row.try_get("client_id")?,
+                ip: row.try_get("ip")?,
+                patients: row.try_get("patient_ids")?,
+                status: status as u16,
+                break_glass: row.try_get("break_glass")?,
+            });
+        }
+
         /// Allows for searching patients.
         pub async fn search_patient(&self,
-                                    params: PatientSearch,
+                                    search: impl Into<ResourceSearch>,
         ) -> Result<Json<Vec<PatientStub>>, Box<dyn Error>> {
-            let client = self.pool.get().await?;
-            let row = client.query_one(
-                "SELECT fhir.search_patients($1);",
-                &[&serde_json::to_value(params)?]).await?;
-            let r = row.get(0);
-            let patients = match r {
-                Value::String(s) => serde_json::from_str::<Vec<PatientStub>>(s.as_str())?,
-                Value::Array(_) => serde_json::from_value::<Vec<PatientStub>>(r).unwrap(),
-                v => return Err(format!("Unknown JSON type: {}", v).into())
-            };
-            let json = Json(patients);
-            return Ok(json);
+            let hits = self.search_resources::<Patient>(&search.into()).await?;
+            let patients = hits.into_iter()
+                               .map(|hit| PatientStub::new(&hit.resource, hit.iteration_key))
+                               .collect();
+            return Ok(Json(patients));
         }
     }
 
     #[cfg(test)]
-    mod tests {
+    pub(crate) mod tests {
         use super::*;
+        use crate::encryption::encryption;
+        use crate::integrity::integrity::ReferenceIntegrity::Allow;
+        use crate::integrity::integrity::Unresolvable;
         use crate::model::model::Gender::{Female, Male, Unknown};
         use crate::model::model::HumanNameUse::Official;
         use crate::model::model::SearchOperator::{And, Or};
         use crate::model::model::*;
+        use crate::resource::resource::{compartment_criterion, SearchCriterion};
         use crate::setid::SetId;
         use chrono::DateTime;
         use deadpool_postgres::GenericClient;
         use speculoos::assert_that;
-        use speculoos::prelude::ContainingIntoIterAssertions;
+        use speculoos::prelude::{BooleanAssertions, ContainingIntoIterAssertions};
         use testcontainers::core::{IntoContainerPort, WaitFor};
         use testcontainers::runners::AsyncRunner;
         use testcontainers::{ContainerAsync, GenericImage, ImageExt};
+        use std::str::FromStr;
+        use std::sync::Arc;
         use tokio::fs::read_to_string;
         use tokio_postgres::SimpleQueryMessage;
 
-        struct TestDb {
-            db: Db,
+        const PATIENT_COUNT_QUERY: &str =
+            "SELECT count(1) FROM fhir.resource WHERE resource_type = 'Patient';";
+
+        pub(crate) struct TestDb {
+            pub(crate) db: Db,
             _image: ContainerAsync<GenericImage>,
         }
 
         /// Set up the DB with testcontainers, using the schema from the DB in the same project.
-        async fn setup() -> TestDb {
+        pub(crate) async fn setup() -> TestDb {
             let image = GenericImage::new("postgres", "17.6-alpine3.22")
                 .with_exposed_port(5432.tcp())
                 .with_wait_for(
@@ -175,7 +738,7 @@ pub mod db {
             let client = db.pool.get().await.unwrap();
 
             // Path to schema and extension
-            let ext = read_to_string("../db/patient-extension/patient--1.0.sql")
+            let ext = read_to_string("../db/resource-extension/resource--1.0.sql")
                 .await
                 .unwrap();
             // Does not load extension as an actual extension but as a normal schema
@@ -194,10 +757,10 @@ pub mod db {
             let test_db = setup().await;
             let db = test_db.db;
 
-            db.upsert_patient(&mut get_empty_patient()).await.unwrap();
+            db.upsert_resource(&get_empty_patient(), Allow).await.unwrap();
 
             let client = db.pool.get().await.unwrap();
-            let patient_count: i64 = client.query_one("SELECT count(1) FROM fhir.patient;",
+            let patient_count: i64 = client.query_one(PATIENT_COUNT_QUERY,
                                                       &[])
                                            .await
                                            .unwrap()
@@ -212,11 +775,14 @@ pub mod db {
             let db = test_db.db;
 
             let patient = &mut get_full_patient(&db).await;
-            let id = db.upsert_patient(&mut patient.clone()).await.unwrap();
+            let id = db.upsert_resource(&patient.clone(), Allow).await.unwrap();
 
             patient.id = Some(id.to_string());
 
-            let res = db.get_patient(id).await.unwrap();
+            let res = db.get_resource::<Patient>(id).await.unwrap();
+            let meta = patient.meta.as_mut().unwrap();
+            meta.version_id = Some("1".to_string());
+            meta.last_updated = res.meta.as_ref().unwrap().last_updated.clone();
 
             assert_that(&res).is_equal_to(patient);
         }
@@ -231,8 +797,8 @@ pub mod db {
             let orig = get_empty_patient();
             let new = &mut get_full_patient(&db).await;
 
-            let id = db.upsert_patient(&mut orig.clone()).await.unwrap();
-            let orig_count: i64 = client.query_one("SELECT count(1) FROM fhir.patient;",
+            let id = db.upsert_resource(&orig.clone(), Allow).await.unwrap();
+            let orig_count: i64 = client.query_one(PATIENT_COUNT_QUERY,
                                                    &[])
                                         .await
                                         .unwrap()
@@ -240,15 +806,19 @@ pub mod db {
 
             new.id = Some(id.to_string());
 
-            db.upsert_patient(new).await.unwrap();
+            db.upsert_resource(new, Allow).await.unwrap();
 
-            let res = db.get_patient(id).await.unwrap();
-            let new_count: i64 = client.query_one("SELECT count(1) FROM fhir.patient;",
+            let res = db.get_resource::<Patient>(id).await.unwrap();
+            let new_count: i64 = client.query_one(PATIENT_COUNT_QUERY,
                                                   &[])
                                        .await
                                        .unwrap()
                                        .get(0);
 
+            let meta = new.meta.as_mut().unwrap();
+            meta.version_id = Some("2".to_string());
+            meta.last_updated = res.meta.as_ref().unwrap().last_updated.clone();
+
             assert_that(&orig_count).is_equal_to(new_count);
             assert_that(&res).is_equal_to(new);
         }
@@ -364,13 +934,13 @@ pub mod db {
             g.birth_date = Some("1993-10".to_string());
             g.gender = Some(Female);
 
-            db.upsert_patient(a).await.unwrap();
-            db.upsert_patient(b).await.unwrap();
-            db.upsert_patient(c).await.unwrap();
-            db.upsert_patient(d).await.unwrap();
-            db.upsert_patient(e).await.unwrap();
-            db.upsert_patient(f).await.unwrap();
-            db.upsert_patient(g).await.unwrap();
+            db.upsert_resource(a, Allow).await.unwrap();
+            db.upsert_resource(b, Allow).await.unwrap();
+            db.upsert_resource(c, Allow).await.unwrap();
+            db.upsert_resource(d, Allow).await.unwrap();
+            db.upsert_resource(e, Allow).await.unwrap();
+            db.upsert_resource(f, Allow).await.unwrap();
+            db.upsert_resource(g, Allow).await.unwrap();
 
             let page1_name = db.search_patient(
                 PatientSearch {
@@ -553,13 +1123,13 @@ pub mod db {
             g.birth_date = Some("1993-10".to_string());
             g.gender = Some(Female);
 
-            db.upsert_patient(a).await.unwrap();
-            db.upsert_patient(b).await.unwrap();
-            db.upsert_patient(c).await.unwrap();
-            db.upsert_patient(d).await.unwrap();
-            db.upsert_patient(e).await.unwrap();
-            db.upsert_patient(f).await.unwrap();
-            db.upsert_patient(g).await.unwrap();
+            db.upsert_resource(a, Allow).await.unwrap();
+            db.upsert_resource(b, Allow).await.unwrap();
+            db.upsert_resource(c, Allow).await.unwrap();
+            db.upsert_resource(d, Allow).await.unwrap();
+            db.upsert_resource(e, Allow).await.unwrap();
+            db.upsert_resource(f, Allow).await.unwrap();
+            db.upsert_resource(g, Allow).await.unwrap();
 
             let page1_name = db.search_patient(
                 PatientSearch {
@@ -645,7 +1215,7 @@ pub mod db {
             let db = test_db.db;
 
             for p in &mut patients {
-                db.upsert_patient(p).await.unwrap();
+                db.upsert_resource(p, Allow).await.unwrap();
             }
 
             let and_res = db.search_patient(PatientSearch {
@@ -680,8 +1250,8 @@ pub mod db {
             let test_db = setup().await;
             let db = test_db.db;
 
-            let mut patient = get_full_patient(&db).await;
-            let pid = db.upsert_patient(&mut patient).await.unwrap();
+            let patient = get_full_patient(&db).await;
+            let pid = db.upsert_resource(&patient, Allow).await.unwrap();
 
             let Json(res) = db.search_patient(PatientSearch {
                 iteration_key: None,
@@ -704,6 +1274,542 @@ pub mod db {
             assert_that!(p.birthdate).is_equal_to(patient.birth_date);
         }
 
+        #[tokio::test]
+        async fn test_search_observation() {
+            let test_db = setup().await;
+            let db = test_db.db;
+
+            let patient_a = db.upsert_resource(&get_empty_patient(), Allow).await.unwrap();
+            let patient_b = db.upsert_resource(&get_empty_patient(), Allow).await.unwrap();
+
+            let heart_rate = get_observation(&patient_a, "8867-4", "vital-signs", "2024-03-01");
+            let glucose = get_observation(&patient_a, "2339-0", "laboratory", "2024-05-17");
+            let other = get_observation(&patient_b, "8867-4", "vital-signs", "2024-03-01");
+
+            let id = db.upsert_resource(&heart_rate, Allow).await.unwrap();
+            db.upsert_resource(&glucose, Allow).await.unwrap();
+            db.upsert_resource(&other, Allow).await.unwrap();
+
+            let search = |patient: Option<Uuid>, code: Option<&str>, category: Option<&str>,
+                          date: Option<&str>| ObservationSearch {
+                patient: patient.map(|p| p.to_string()),
+                subject: None,
+                code: code.map(str::to_string),
+                category: category.map(str::to_string),
+                date: date.map(str::to_string),
+                date_from: None,
+                date_until: None,
+                operator: And,
+                count: 30,
+                iteration_key: None,
+                last_id: None,
+            };
+
+            let by_patient = db.search_resources::<Observation>(
+                &search(Some(patient_a), None, None, None).into()
+            ).await.unwrap();
+            let by_code = db.search_resources::<Observation>(
+                &search(None, Some("http://loinc.org|8867-4"), None, None).into()
+            ).await.unwrap();
+            let by_patient_and_category = db.search_resources::<Observation>(
+                &search(Some(patient_a), None, Some("laboratory"), None).into()
+            ).await.unwrap();
+            let by_patient_and_date = db.search_resources::<Observation>(
+                &search(Some(patient_a), None, None, Some("2024-03")).into()
+            ).await.unwrap();
+
+            assert_that!(by_patient.len()).is_equal_to(2);
+            assert_that!(by_code.len()).is_equal_to(2);
+            assert_that!(by_patient_and_category.len()).is_equal_to(1);
+            assert_that!(by_patient_and_category[0].resource.code)
+                .is_equal_to(&glucose.code);
+            assert_that!(by_patient_and_date.len()).is_equal_to(1);
+            assert_that!(by_patient_and_date[0].id).is_equal_to(id.to_string());
+
+            let res = db.get_resource::<Observation>(id).await.unwrap();
+            assert_that!(res.value_quantity).is_equal_to(heart_rate.value_quantity);
+        }
+
+        #[tokio::test]
+        async fn test_restricted_records() {
+            let test_db = setup().await;
+            let db = test_db.db;
+
+            let mut vip = get_empty_patient();
+            vip.meta = Some(serde_json::from_value(serde_json::json!({
+                "security": [{
+                    "system": "http://terminology.hl7.org/CodeSystem/v3-Confidentiality",
+                    "code": "R",
+                }],
+            })).unwrap());
+            let vip_id = db.upsert_resource(&vip, Allow).await.unwrap();
+            let other_id = db.upsert_resource(&get_empty_patient(), Allow).await.unwrap();
+            let vip_observation = db.upsert_resource(
+                &get_observation(&vip_id, "8867-4", "vital-signs", "2024-03-01"),
+                Allow,
+            ).await.unwrap();
+            db.upsert_resource(&get_observation(&other_id, "8867-4", "vital-signs", "2024-03-01"),
+                               Allow)
+              .await
+              .unwrap();
+
+            let hidden = vec![SecurityLabel::from_str(
+                "http://terminology.hl7.org/CodeSystem/v3-Confidentiality|R"
+            ).unwrap()];
+            let search = |hidden: &[SecurityLabel]| {
+                let mut search: ResourceSearch = ObservationSearch {
+                    patient: None,
+                    subject: None,
+                    code: Some("http://loinc.org|8867-4".to_string()),
+                    category: None,
+                    date: None,
+                    date_from: None,
+                    date_until: None,
+                    operator: And,
+                    count: 30,
+                    iteration_key: None,
+                    last_id: None,
+                }.into();
+                search.hidden = hidden.to_vec();
+                return search;
+            };
+
+            let all = db.search_resources::<Observation>(&search(&[])).await.unwrap();
+            let visible = db.search_resources::<Observation>(&search(&hidden)).await.unwrap();
+            assert_that!(all.len()).is_equal_to(2);
+            assert_that!(visible.len()).is_equal_to(1);
+            assert_that!(visible[0].resource.subject.as_ref().unwrap().reference.clone())
+                .is_equal_to(Some(format!("Patient/{}", other_id)));
+
+            assert_that!(db.get_visible_resource::<Patient>(vip_id, &[]).await.is_ok()).is_true();
+            assert_that!(db.get_visible_resource::<Patient>(vip_id, &hidden).await.is_err()).is_true();
+            assert_that!(db.get_visible_resource::<Observation>(vip_observation, &hidden).await.is_err())
+                .is_true();
+            assert_that!(db.get_visible_resource::<Patient>(other_id, &hidden).await.is_ok()).is_true();
+        }
+
+        #[tokio::test]
+        async fn test_encryption_at_rest() {
+            let test_db = setup().await;
+            let plain = Db { pool: test_db.db.pool.clone(), encryption: None };
+            let db = test_db.db.with_encryption(encryption::tests::encryption("k1"));
+            let client = db.pool.get().await.unwrap();
+            let stored = async |id: Uuid| -> String {
+                let data: Value = client.query_one(
+                    "SELECT data FROM fhir.resource WHERE resource_type = 'Patient' AND id = $1;",
+                    &[&id]).await.unwrap().get(0);
+                return data.to_string();
+            };
+
+            let patient: Patient = serde_json::from_value(serde_json::json!({
+                "identifier": [{"system": "urn:kvnr", "value": "A123456789"}],
+                "telecom": [{"value": "+49 30 123456"}],
+            })).unwrap();
+            let id = db.upsert_resource(&patient, Allow).await.unwrap();
+            assert_that!(stored(id).await.contains("A123456789")).is_false();
+            assert_that!(stored(id).await.contains("123456")).is_false();
+            let tokens: Vec<String> = client.query(
+                "SELECT code FROM fhir.search_token WHERE resource_id = $1;",
+                &[&id]).await.unwrap().iter().map(|r| r.get(0)).collect();
+            assert_that!(tokens.iter().any(|t| t.contains("A123456789"))).is_false();
+
+            let read: Patient = db.get_resource(id).await.unwrap();
+            assert_that!(read.identifier[0].value.as_deref()).is_equal_to(Some("A123456789"));
+            assert_that!(read.telecom).is_equal_to(&patient.telecom);
+            // without the keys, encrypted resources can't be read
+            assert_that!(plain.get_resource::<Patient>(id).await.is_err()).is_true();
+
+            let search = |code: &str| ResourceSearch {
+                criteria: vec![SearchCriterion::token("identifier", code)],
+                operator: And,
+                compartment: None,
+                hidden: Vec::new(),
+                count: 10,
+                iteration_key: None,
+                last_id: None,
+            };
+            let hits = db.search_resources::<Patient>(&search("urn:kvnr|A123456789")).await.unwrap();
+            assert_that!(hits.len()).is_equal_to(1);
+            assert_that!(hits[0].resource.identifier).is_equal_to(&read.identifier);
+            assert_that!(db.search_resources::<Patient>(&search("A12345")).await.unwrap().len())
+                .is_equal_to(0);
+
+            // rotating wraps the data keys with the new key, and encrypts what was stored in plain
+            let legacy = plain.upsert_resource(&patient, Allow).await.unwrap();
+            let legacy_meta = plain.get_resource::<Patient>(legacy).await.unwrap().meta;
+            let rotated = Db {
+                pool: db.pool.clone(),
+                encryption: Some(encryption::tests::encryption("k2")),
+            };
+            assert_that!(rotated.rotate_keys().await.unwrap()).is_equal_to(2);
+            assert_that!(rotated.rotate_keys().await.unwrap()).is_equal_to(0);
+            assert_that!(stored(id).await.contains(r#""keyId":"k2""#)).is_true();
+            assert_that!(stored(legacy).await.contains("A123456789")).is_false();
+            let read: Patient = rotated.get_resource(id).await.unwrap();
+            assert_that!(read.identifier[0].value.as_deref()).is_equal_to(Some("A123456789"));
+            assert_that!(rotated.get_resource::<Patient>(legacy).await.unwrap().meta)
+                .is_equal_to(legacy_meta);
+            let hits = rotated.search_resources::<Patient>(&search("A123456789")).await.unwrap();
+            assert_that!(hits.len()).is_equal_to(2);
+        }
+
+        #[tokio::test]
+        async fn test_encounter() {
+            let test_db = setup().await;
+            let db = test_db.db;
+
+            let patient = db.upsert_resource(&get_empty_patient(), Allow).await.unwrap();
+
+            let inpatient = get_encounter(&patient,
+                                          EncounterStatus::Finished,
+                                          "2024-03-01T08:00:00+01:00",
+                                          Some("2024-03-10T12:00:00+01:00"));
+            let outpatient = get_encounter(&patient,
+                                           EncounterStatus::InProgress,
+                                           "2024-05-17T09:00:00+02:00",
+                                           None);
+
+            let id = db.upsert_resource(&inpatient, Allow).await.unwrap();
+            db.upsert_resource(&outpatient, Allow).await.unwrap();
+
+            let search = |date: Option<&str>, status: Option<&str>| EncounterSearch {
+                patient: Some(patient.to_string()),
+                subject: None,
+                status: status.map(str::to_string),
+                date: date.map(str::to_string),
+                date_from: None,
+                date_until: None,
+                operator: And,
+                count: 30,
+                iteration_key: None,
+                last_id: None,
+            };
+
+            let by_patient = db.search_resources::<Encounter>(&search(None, None).into())
+                               .await
+                               .unwrap();
+            // inside the period of the inpatient stay
+            let by_date = db.search_resources::<Encounter>(
+                &search(Some("2024-03-05"), None).into()
+            ).await.unwrap();
+            // the outpatient encounter is still in progress
+            let open_ended = db.search_resources::<Encounter>(
+                &search(Some("2025-01-01"), None).into()
+            ).await.unwrap();
+            let by_status = db.search_resources::<Encounter>(
+                &search(None, Some("FINISHED")).into()
+            ).await.unwrap();
+
+            assert_that!(by_patient.len()).is_equal_to(2);
+            assert_that!(by_date.len()).is_equal_to(1);
+            assert_that!(by_date[0].id).is_equal_to(id.to_string());
+            assert_that!(open_ended.len()).is_equal_to(1);
+            assert_that!(open_ended[0].resource.status).is_equal_to(EncounterStatus::InProgress);
+            assert_that!(by_status.len()).is_equal_to(1);
+            assert_that!(by_status[0].id).is_equal_to(id.to_string());
+
+            db.delete_resource::<Encounter>(id).await.unwrap();
+
+            let deleted = db.get_resource::<Encounter>(id).await;
+            let deleted_again = db.delete_resource::<Encounter>(id).await;
+            let remaining = db.search_resources::<Encounter>(&search(None, None).into())
+                              .await
+                              .unwrap();
+
+            assert_that!(deleted.unwrap_err().downcast_ref::<NotFound>().is_some()).is_true();
+            assert_that!(deleted_again.unwrap_err().downcast_ref::<NotFound>().is_some())
+                .is_true();
+            assert_that!(remaining.len()).is_equal_to(1);
+        }
+
+        #[tokio::test]
+        async fn test_references() {
+            let test_db = setup().await;
+            let db = test_db.db;
+
+            let target = db.upsert_resource(&get_empty_patient(), Allow).await.unwrap();
+            let unknown = Uuid::new_v4();
+
+            let mut source = get_empty_patient();
+            source.link = vec![see_also(target)];
+            let source_id = db.upsert_resource(&source, ReferenceIntegrity::Reject).await.unwrap();
+
+            let mut dangling = get_empty_patient();
+            dangling.link = vec![see_also(unknown)];
+            let unresolvable = db.upsert_resource(&dangling, ReferenceIntegrity::Reject)
+                                 .await
+                                 .unwrap_err();
+
+            assert_that!(unresolvable.downcast_ref::<Unresolvable>().unwrap().references)
+                .is_equal_to(vec![format!("Patient/{}", unknown)]);
+            assert_that!(db.upsert_resource(&dangling, ReferenceIntegrity::Warn).await.is_ok())
+                .is_true();
+
+            let referenced = db.delete_resource::<Patient>(target).await.unwrap_err();
+
+            assert_that!(referenced.downcast_ref::<Referenced>().unwrap().by)
+                .is_equal_to(vec![format!("Patient/{}", source_id)]);
+            assert_that!(db.get_resource::<Patient>(target).await.is_ok()).is_true();
+
+            // once nothing references it anymore, it can be deleted
+            db.delete_resource::<Patient>(source_id).await.unwrap();
+            db.delete_resource::<Patient>(target).await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_migrate_patients() {
+            let test_db = setup().await;
+            let db = test_db.db;
+            let client = db.pool.get().await.unwrap();
+            // the table of patients before resources were stored generically
+            client.batch_execute(
+                "CREATE TABLE fhir.patient (\
+                 id UUID PRIMARY KEY NOT NULL, \
+                 data JSONB, \
+                 created_at TEXT NOT NULL DEFAULT (EXTRACT(EPOCH FROM NOW()) * 1000)::BIGINT::TEXT);"
+            ).await.unwrap();
+            let id = Uuid::new_v4();
+            let data = serde_json::json!({
+                "id": id.to_string(),
+                "name": [{"text": "Anna Meier"}],
+                "gender": "FEMALE",
+                "birthDate": "1990-01-01",
+            });
+            client.execute("INSERT INTO fhir.patient (id, data) VALUES ($1, $2);", &[&id, &data])
+                  .await
+                  .unwrap();
+            let search = || PatientSearch {
+                name: Some("Meier".to_string()),
+                birthdate_from: None,
+                birthdate_until: None,
+                gender: None,
+                operator: And,
+                count: 10,
+                iteration_key: None,
+                last_id: None,
+            };
+
+            let migration = read_to_string("../db/02-migrate-patient.sql").await.unwrap();
+            client.batch_execute(&migration).await.unwrap();
+            // moved patients are skipped when it runs again
+            client.batch_execute(&migration).await.unwrap();
+
+            let migrated = db.get_resource::<Patient>(id).await.unwrap();
+            assert_that!(migrated.name[0].text).is_equal_to(Some("Anna Meier".to_string()));
+            assert_that!(migrated.meta.unwrap().version_id).is_equal_to(Some("1".to_string()));
+            assert_that!(db.search_patient(search()).await.unwrap().len()).is_equal_to(0);
+
+            assert_that!(db.reindex().await.unwrap()).is_equal_to(1);
+
+            let found = db.search_patient(search()).await.unwrap();
+            assert_that!(found.iter().map(|p| p.id.clone()).collect::<Vec<_>>())
+                .is_equal_to(vec![id.to_string()]);
+            assert_that!(db.get_resource::<Patient>(id).await.unwrap().meta.unwrap().version_id)
+                .is_equal_to(Some("1".to_string()));
+        }
+
+        #[tokio::test]
+        async fn test_reference_while_deleting() {
+            let test_db = setup().await;
+            let db = Arc::new(test_db.db);
+            let target = db.upsert_resource(&get_empty_patient(), Allow).await.unwrap();
+
+            // a write of a resource referencing the target holds it until the commit
+            let mut client = db.pool.get().await.unwrap();
+            let transaction = client.transaction().await.unwrap();
+            let mut source = get_empty_patient();
+            source.link = vec![see_also(target)];
+            Db::check_references(&transaction, &source, ReferenceIntegrity::Reject).await.unwrap();
+            let deleting = tokio::spawn({
+                let db = db.clone();
+                async move {
+                    return db.delete_resource::<Patient>(target).await.map_err(|e| e.to_string());
+                }
+            });
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            assert_that!(deleting.is_finished()).is_false();
+            let source_id = db.upsert_with(&transaction, &source).await.unwrap();
+            transaction.commit().await.unwrap();
+
+            assert_that!(deleting.await.unwrap().unwrap_err())
+                .is_equal_to(format!("Referenced {{ id={}, by=Patient/{} }}", target, source_id));
+            assert_that!(db.get_resource::<Patient>(target).await.is_ok()).is_true();
+        }
+
+        fn see_also(id: Uuid) -> Link {
+            return Link {
+                other: Reference {
+                    id: None,
+                    extension: Vec::new(),
+                    reference: Some(format!("Patient/{}", id)),
+                    ref_type: Some("Patient".to_string()),
+                    identifier: None,
+                    display: None,
+                },
+                link_type: LinkType::Seealso,
+            };
+        }
+
+        #[tokio::test]
+        async fn test_store_merge_of_changed_patients() {
+            let test_db = setup().await;
+            let db = test_db.db;
+
+            let source_id = db.upsert_resource(&get_empty_patient(), Allow).await.unwrap();
+            let target_id = db.upsert_resource(&get_empty_patient(), Allow).await.unwrap();
+            let mut source = db.get_resource::<Patient>(source_id).await.unwrap();
+            let mut target = db.get_resource::<Patient>(target_id).await.unwrap();
+            crate::merge::merge::merge_patients(&mut source, &mut target);
+            let mut provenance = crate::merge::merge::merge_provenance(
+                &source_id.to_string(),
+                &target_id.to_string(),
+                Vec::new());
+            provenance.set_id(&db).await.unwrap();
+
+            // a PUT of the target after it was read for the merge
+            let mut put = db.get_resource::<Patient>(target_id).await.unwrap();
+            put.gender = Some(Female);
+            db.upsert_resource(&put, Allow).await.unwrap();
+
+            let changed = db.store_merge(&source, &target, &provenance, Allow).await.unwrap_err();
+            assert_that!(changed.downcast_ref::<Changed>().map(|c| c.id))
+                .is_equal_to(Some(target_id));
+            let stored = db.get_resource::<Patient>(target_id).await.unwrap();
+            assert_that!(stored.gender).is_equal_to(Some(Female));
+            assert_that!(stored.link.is_empty()).is_true();
+            assert_that!(db.get_resource::<Patient>(source_id).await.unwrap().active)
+                .is_equal_to(None);
+
+            // a deleted source isn't created again
+            let target = db.get_resource::<Patient>(target_id).await.unwrap();
+            db.delete_resource::<Patient>(source_id).await.unwrap();
+            let deleted = db.store_merge(&source, &target, &provenance, Allow).await.unwrap_err();
+            assert_that!(deleted.downcast_ref::<Changed>().map(|c| c.id))
+                .is_equal_to(Some(source_id));
+            assert_that!(db.get_resource::<Patient>(source_id).await.is_err()).is_true();
+        }
+
+        #[tokio::test]
+        async fn test_store_merge() {
+            let test_db = setup().await;
+            let db = test_db.db;
+
+            let source_id = db.upsert_resource(&get_empty_patient(), Allow).await.unwrap();
+            let target_id = db.upsert_resource(&get_empty_patient(), Allow).await.unwrap();
+            let mut source = db.get_resource::<Patient>(source_id).await.unwrap();
+            let mut target = db.get_resource::<Patient>(target_id).await.unwrap();
+
+            crate::merge::merge::merge_patients(&mut source, &mut target);
+            let mut provenance = crate::merge::merge::merge_provenance(
+                &source_id.to_string(),
+                &target_id.to_string(),
+                Vec::new());
+            source.set_id(&db).await.unwrap();
+            target.set_id(&db).await.unwrap();
+            provenance.set_id(&db).await.unwrap();
+            db.store_merge(&source, &target, &provenance, Allow).await.unwrap();
+
+            let stored_source = db.get_resource::<Patient>(source_id).await.unwrap();
+            let stored_target = db.get_resource::<Patient>(target_id).await.unwrap();
+            assert_that!(stored_source.meta.as_ref().unwrap().version_id.clone())
+                .is_equal_to(Some("2".to_string()));
+            source.meta = stored_source.meta.clone();
+            target.meta = stored_target.meta.clone();
+            assert_that!(stored_source).is_equal_to(&source);
+            assert_that!(stored_target).is_equal_to(&target);
+            assert_that!(stored_source.active).is_equal_to(Some(false));
+
+            let search = ResourceSearch::from(ProvenanceSearch {
+                target: Some(format!("Patient/{}", source_id)),
+                recorded_from: None,
+                recorded_until: None,
+                operator: And,
+                count: 30,
+                iteration_key: None,
+                last_id: None,
+            });
+            let hits = db.search_resources::<Provenance>(&search).await.unwrap();
+            assert_that!(hits.len()).is_equal_to(1);
+            provenance.meta = hits[0].resource.meta.clone();
+            assert_that!(hits[0].resource).is_equal_to(&provenance);
+
+            // the source is still referenced by the target, but not by the provenance
+            assert_that!(db.delete_resource::<Patient>(source_id).await.is_err()).is_true();
+        }
+
+        #[tokio::test]
+        async fn test_match_patients() {
+            let test_db = setup().await;
+            let db = test_db.db;
+            let matcher = crate::matching::matching::Matcher::new(Default::default(), false);
+            let named = |given: &str, family: &str, birth_date: &str| {
+                let mut patient = get_empty_patient();
+                patient.name = vec![HumanName {
+                    id: None,
+                    extension: Vec::new(),
+                    human_name_use: Some(Official),
+                    text: None,
+                    family: Some(family.to_string()),
+                    given: vec![given.to_string()],
+                    prefix: Vec::new(),
+                    suffix: Vec::new(),
+                    period: None,
+                }];
+                patient.birth_date = Some(birth_date.to_string());
+                patient.gender = Some(Female);
+                return patient;
+            };
+
+            let same = db.upsert_resource(&named("Anna", "Meier", "1990-01-01"), Allow)
+                          .await
+                          .unwrap();
+            let typo = db.upsert_resource(&named("Ana", "Meier", "1990-01-01"), Allow)
+                          .await
+                          .unwrap();
+            db.upsert_resource(&named("Anna", "Meier", "1961-07-12"), Allow).await.unwrap();
+            db.upsert_resource(&named("Bernd", "Schulz", "1990-01-01"), Allow).await.unwrap();
+
+            let candidate = named("Anna", "Meier", "1990-01-01");
+            let matches = matcher.find(&db, &candidate, None, Vec::new(), 10).await.unwrap();
+
+            let ids: Vec<String> = matches.iter()
+                                          .map(|m| m.patient.id.clone().unwrap())
+                                          .collect();
+            assert_that!(ids).is_equal_to(vec![same.to_string(), typo.to_string()]);
+            assert_that!(matches[0].score).is_equal_to(1.0);
+
+            // the patient itself is not a duplicate
+            let mut stored = candidate.clone();
+            stored.id = Some(same.to_string());
+            let matches = matcher.find(&db, &stored, None, Vec::new(), 10).await.unwrap();
+            assert_that!(matches.len()).is_equal_to(1);
+
+            // restricted patients are left out before the best matches are taken
+            let mut vip = named("Anna", "Meier", "1990-01-01");
+            vip.meta = Some(serde_json::from_value(serde_json::json!({
+                "security": [{
+                    "system": "http://terminology.hl7.org/CodeSystem/v3-Confidentiality",
+                    "code": "R",
+                }],
+            })).unwrap());
+            let vip = db.upsert_resource(&vip, Allow).await.unwrap();
+            let hidden = vec![SecurityLabel::from_str(
+                "http://terminology.hl7.org/CodeSystem/v3-Confidentiality|R"
+            ).unwrap()];
+            let matches = matcher.find(&db, &candidate, None, Vec::new(), 3).await.unwrap();
+            assert_that!(matches.iter().any(|m| m.patient.id == Some(vip.to_string()))).is_true();
+            let matches = matcher.find(&db, &candidate, None, hidden, 1).await.unwrap();
+            assert_that!(matches[0].patient.id).is_equal_to(Some(same.to_string()));
+
+            // so are patients outside of the compartment
+            let compartment = Some(compartment_criterion::<Patient>(&typo.to_string()));
+            let matches = matcher.find(&db, &candidate, compartment, Vec::new(), 3).await.unwrap();
+            let ids: Vec<String> = matches.iter()
+                                          .map(|m| m.patient.id.clone().unwrap())
+                                          .collect();
+            assert_that!(ids).is_equal_to(vec![typo.to_string()]);
+        }
+
         #[tokio::test]
         async fn test_get_id() {
             let test_db = setup().await;
@@ -731,6 +1837,54 @@ pub mod db {
             assert_that(&(db_ids)).contains_all_of(&(uuids.iter().collect::<Vec<_>>()));
         }
 
+        #[tokio::test]
+        async fn test_audit_trail() {
+            let test_db = setup().await;
+            let db = test_db.db;
+            let entry = |patient: &str, actor: &str| AuditEntry {
+                id: Uuid::new_v4(),
+                recorded: Utc::now(),
+                interaction: Interaction::Read,
+                operation: None,
+                resource_type: "Patient".to_string(),
+                resource_id: Some(patient.to_string()),
+                query: None,
+                actor: Some(actor.to_string()),
+                client_id: None,
+                ip: None,
+                patients: vec![patient.to_string()],
+                status: 200,
+                break_glass: None,
+            };
+            let mut first = entry("p1", "alice");
+            first.break_glass = Some("Emergency admission".to_string());
+            db.insert_audit_event(&first).await.unwrap();
+            db.insert_audit_event(&entry("p1", "bob")).await.unwrap();
+            db.insert_audit_event(&entry("p2", "alice")).await.unwrap();
+
+            let mut search: AuditEventSearch = serde_json::from_str(r#"{"patient": "p1", "count": 1}"#)
+                .unwrap();
+            let page = db.search_audit_events(&search, None).await.unwrap();
+            assert_that!(page).is_equal_to(vec![db.get_audit_event(first.id).await.unwrap()]);
+            let next = db.search_audit_events(&search, Some((page[0].recorded, page[0].id)))
+                         .await
+                         .unwrap();
+            assert_that!(next[0].actor).is_equal_to(Some("bob".to_string()));
+
+            search.patient = None;
+            search.agent = Some("alice".to_string());
+            search.count = 10;
+            assert_that!(db.search_audit_events(&search, None).await.unwrap().len()).is_equal_to(2);
+
+            // the trail is append-only
+            let client = db.pool.get().await.unwrap();
+            assert_that!(client.execute("DELETE FROM fhir.audit_event", &[]).await.is_err())
+                .is_true();
+            assert_that!(client.execute("UPDATE fhir.audit_event SET actor = 'eve'", &[]).await.is_err())
+                .is_true();
+            assert_that!(client.batch_execute("TRUNCATE fhir.audit_event").await.is_err()).is_true();
+        }
+
         #[tokio::test]
         async fn test_set_id_trait() {
             let test_db = setup().await;
@@ -746,14 +1900,18 @@ pub mod db {
 
             patient.meta = Some(Meta {
                 id: None,
+                version_id: None,
+                last_updated: None,
                 extension: vec![Extension {
                     id: None,
                     url: "some url".to_string(),
                     value_integer: None,
+                    value_code: None,
                     extension: vec![Extension {
                         id: None,
                         url: "some url".to_string(),
                         value_integer: None,
+                        value_code: None,
                         extension: Vec::new(),
                         value_string: None,
                         value_base_64_binary: None,
@@ -771,6 +1929,7 @@ pub mod db {
                         id: None,
                         url: "some url".to_string(),
                         value_integer: None,
+                        value_code: None,
                         extension: Vec::new(),
                         value_string: None,
                         value_base_64_binary: None,
@@ -792,6 +1951,7 @@ pub mod db {
                         id: None,
                         url: "some url".to_string(),
                         value_integer: None,
+                        value_code: None,
                         extension: Vec::new(),
                         value_string: None,
                         value_base_64_binary: None,
@@ -807,6 +1967,7 @@ pub mod db {
                     id: None,
                     url: "some url".to_string(),
                     value_integer: None,
+                    value_code: None,
                     extension: Vec::new(),
                     value_string: None,
                     value_base_64_binary: None,
@@ -821,6 +1982,8 @@ pub mod db {
                 implicit_rules: Vec::new(),
                 meta: Some(Meta {
                     id: None,
+                    version_id: None,
+                    last_updated: None,
                     tag: Vec::new(),
                     extension: Vec::new(),
                     source: None,
@@ -833,6 +1996,8 @@ pub mod db {
                 implicit_rules: Vec::new(),
                 meta: Some(Meta {
                     id: None,
+                    version_id: None,
+                    last_updated: None,
                     tag: Vec::new(),
                     extension: Vec::new(),
                     source: None,
@@ -846,6 +2011,7 @@ pub mod db {
                 id: None,
                 url: "some url".to_string(),
                 value_integer: None,
+                value_code: None,
                 extension: Vec::new(),
                 value_string: None,
                 value_base_64_binary: None,
@@ -856,6 +2022,7 @@ pub mod db {
                 id: None,
                 url: "some url".to_string(),
                 value_integer: None,
+                value_code: None,
                 extension: Vec::new(),
                 value_string: None,
                 value_base_64_binary: None,
@@ -869,6 +2036,7 @@ pub mod db {
                     id: None,
                     url: "some url".to_string(),
                     value_integer: None,
+                    value_code: None,
                     extension: Vec::new(),
                     value_string: None,
                     value_base_64_binary: None,
@@ -881,6 +2049,7 @@ pub mod db {
                         id: None,
                         url: "some url".to_string(),
                         value_integer: None,
+                        value_code: None,
                         extension: Vec::new(),
                         value_string: None,
                         value_base_64_binary: None,
@@ -897,6 +2066,7 @@ pub mod db {
                             id: None,
                             url: "some url".to_string(),
                             value_integer: None,
+                            value_code: None,
                             extension: Vec::new(),
                             value_string: None,
                             value_base_64_binary: None,
@@ -923,6 +2093,7 @@ pub mod db {
                         id: None,
                         url: "some url".to_string(),
                         value_integer: None,
+                        value_code: None,
                         extension: Vec::new(),
                         value_string: None,
                         value_base_64_binary: None,
@@ -949,6 +2120,7 @@ pub mod db {
                     id: None,
                     url: "some url".to_string(),
                     value_integer: None,
+                    value_code: None,
                     extension: Vec::new(),
                     value_string: None,
                     value_base_64_binary: None,
@@ -981,6 +2153,7 @@ pub mod db {
                     id: None,
                     url: "some url".to_string(),
                     value_integer: None,
+                    value_code: None,
                     extension: Vec::new(),
                     value_string: None,
                     value_base_64_binary: None,
@@ -989,6 +2162,7 @@ pub mod db {
                     id: None,
                     url: "some url".to_string(),
                     value_integer: None,
+                    value_code: None,
                     extension: Vec::new(),
                     value_string: None,
                     value_base_64_binary: None,
@@ -1013,6 +2187,7 @@ pub mod db {
                     id: None,
                     url: "some url".to_string(),
                     value_integer: None,
+                    value_code: None,
                     extension: Vec::new(),
                     value_string: None,
                     value_base_64_binary: None,
@@ -1021,6 +2196,7 @@ pub mod db {
                     id: None,
                     url: "some url".to_string(),
                     value_integer: None,
+                    value_code: None,
                     extension: Vec::new(),
                     value_string: None,
                     value_base_64_binary: None,
@@ -1044,6 +2220,7 @@ pub mod db {
                     id: None,
                     url: "some url".to_string(),
                     value_integer: None,
+                    value_code: None,
                     extension: Vec::new(),
                     value_string: None,
                     value_base_64_binary: None,
@@ -1060,6 +2237,7 @@ pub mod db {
                         id: None,
                         url: "some url".to_string(),
                         value_integer: None,
+                        value_code: None,
                         extension: Vec::new(),
                         value_string: None,
                         value_base_64_binary: None,
@@ -1076,6 +2254,7 @@ pub mod db {
                         id: None,
                         url: "some url".to_string(),
                         value_integer: None,
+                        value_code: None,
                         extension: Vec::new(),
                         value_string: None,
                         value_base_64_binary: None,
@@ -1093,6 +2272,7 @@ pub mod db {
                     id: None,
                     url: "some url".to_string(),
                     value_integer: None,
+                    value_code: None,
                     extension: Vec::new(),
                     value_string: None,
                     value_base_64_binary: None,
@@ -1101,6 +2281,7 @@ pub mod db {
                     id: None,
                     url: "some url".to_string(),
                     value_integer: None,
+                    value_code: None,
                     extension: Vec::new(),
                     value_string: None,
                     value_base_64_binary: None,
@@ -1131,6 +2312,7 @@ pub mod db {
                     id: None,
                     url: "some url".to_string(),
                     value_integer: None,
+                    value_code: None,
                     extension: Vec::new(),
                     value_string: None,
                     value_base_64_binary: None,
@@ -1139,6 +2321,7 @@ pub mod db {
                     id: None,
                     url: "some url".to_string(),
                     value_integer: None,
+                    value_code: None,
                     extension: Vec::new(),
                     value_string: None,
                     value_base_64_binary: None,
@@ -1148,6 +2331,7 @@ pub mod db {
                     id: None,
                     url: "some url".to_string(),
                     value_integer: None,
+                    value_code: None,
                     extension: Vec::new(),
                     value_string: None,
                     value_base_64_binary: None,
@@ -1156,6 +2340,7 @@ pub mod db {
                     id: None,
                     url: "some url".to_string(),
                     value_integer: None,
+                    value_code: None,
                     extension: Vec::new(),
                     value_string: None,
                     value_base_64_binary: None,
@@ -1302,12 +2487,122 @@ pub mod db {
             };
         }
 
+        fn get_observation(patient: &Uuid, code: &str, category: &str, date: &str) -> Observation {
+            let concept = |system: &str, code: &str| CodeableConcept {
+                id: None,
+                extension: Vec::new(),
+                coding: vec![Coding {
+                    id: None,
+                    extension: Vec::new(),
+                    system: Some(system.to_string()),
+                    version: None,
+                    code: Some(code.to_string()),
+                    display: None,
+                    user_selected: None,
+                }],
+                text: None,
+            };
+            return Observation {
+                id: None,
+                meta: None,
+                implicit_rules: Vec::new(),
+                language: None,
+                text: None,
+                contained: Vec::new(),
+                extension: Vec::new(),
+                modifier_extension: Vec::new(),
+                identifier: Vec::new(),
+                status: ObservationStatus::Final,
+                category: vec![concept(
+                    "http://terminology.hl7.org/CodeSystem/observation-category",
+                    category,
+                )],
+                code: concept("http://loinc.org", code),
+                subject: Some(Reference {
+                    id: None,
+                    extension: Vec::new(),
+                    reference: Some(format!("Patient/{}", patient)),
+                    ref_type: Some("Patient".to_string()),
+                    identifier: None,
+                    display: None,
+                }),
+                encounter: None,
+                effective_date_time: Some(format!("{}T08:00:00+01:00", date)),
+                effective_period: None,
+                effective_instant: None,
+                issued: None,
+                performer: Vec::new(),
+                value_quantity: Some(Quantity {
+                    id: None,
+                    extension: Vec::new(),
+                    value: Some(72.5),
+                    comparator: None,
+                    unit: Some("beats/minute".to_string()),
+                    system: Some("http://unitsofmeasure.org".to_string()),
+                    code: Some("/min".to_string()),
+                }),
+                value_codeable_concept: None,
+                value_string: None,
+                value_boolean: None,
+                value_integer: None,
+                value_period: None,
+                value_date_time: None,
+                interpretation: Vec::new(),
+            };
+        }
+
+        fn get_encounter(patient: &Uuid,
+                         status: EncounterStatus,
+                         start: &str,
+                         end: Option<&str>,
+        ) -> Encounter {
+            return Encounter {
+                id: None,
+                meta: None,
+                implicit_rules: Vec::new(),
+                language: None,
+                text: None,
+                contained: Vec::new(),
+                extension: Vec::new(),
+                modifier_extension: Vec::new(),
+                identifier: Vec::new(),
+                status,
+                class: Coding {
+                    id: None,
+                    extension: Vec::new(),
+                    system: Some("http://terminology.hl7.org/CodeSystem/v3-ActCode".to_string()),
+                    version: None,
+                    code: Some("IMP".to_string()),
+                    display: Some("inpatient encounter".to_string()),
+                    user_selected: None,
+                },
+                encounter_type: Vec::new(),
+                subject: Some(Reference {
+                    id: None,
+                    extension: Vec::new(),
+                    reference: Some(format!("Patient/{}", patient)),
+                    ref_type: Some("Patient".to_string()),
+                    identifier: None,
+                    display: None,
+                }),
+                participant: Vec::new(),
+                period: Some(Period {
+                    start: Some(start.to_string()),
+                    end: end.map(str::to_string),
+                }),
+                reason_code: Vec::new(),
+                service_provider: None,
+            };
+        }
+
         /// Returns a patient with most fields set.
         async fn get_full_patient(db: &Db) -> Patient {
             return Patient {
                 id: None,
                 meta: Some(Meta {
                     id: Some(db.get_id().await.unwrap()),
+                    version_id: None,
+                    last_updated: None,
                     extension: Vec::from([Extension {
                         id: Some(db.get_id().await.unwrap()),
                         extension: Vec::new(),
@@ -1316,6 +2611,7 @@ pub mod db {
                         value_boolean: Some(true),
                         value_string: None,
                         value_integer: None,
+                        value_code: None,
                     }, Extension {
                         id: Some(db.get_id().await.unwrap()),
                         extension: Vec::new(),
@@ -1324,6 +2620,7 @@ pub mod db {
                         value_boolean: None,
                         value_string: None,
                         value_integer: Some(42),
+                        value_code: None,
                     }]),
                     source: Some("http://example.com/meta/source".to_string()),
                     profile: Vec::from(["http://example.com/meta/profile/1".to_string(),
@@ -1338,6 +2635,7 @@ pub mod db {
                             value_boolean: None,
                             value_string: Some("Some value".to_string()),
                             value_integer: None,
+                            value_code: None,
                         }]),
                         system: Some("http://example.com/meta/security/1/system".to_string()),
                         version: Some("1.0.0".to_string()),
@@ -1355,6 +2653,7 @@ pub mod db {
                             value_boolean: None,
                             value_string: None,
                             value_integer: None,
+                            value_code: None,
                         }]),
                         system: Some("http://example.com/meta/security/2/system".to_string()),
                         version: Some("1.0.1".to_string()),
@@ -1375,6 +2674,8 @@ pub mod db {
                     id: Some(db.get_id().await.unwrap()),
                     meta: Some(Meta {
                         id: Some(db.get_id().await.unwrap()),
+                        version_id: None,
+                        last_updated: None,
                         extension: Vec::from([Extension {
                             id: Some(db.get_id().await.unwrap()),
                             extension: Vec::new(),
@@ -1383,6 +2684,7 @@ pub mod db {
                             value_boolean: Some(true),
                             value_string: None,
                             value_integer: None,
+                            value_code: None,
                         }, Extension {
                             id: Some(db.get_id().await.unwrap()),
                             extension: Vec::new(),
@@ -1391,6 +2693,7 @@ pub mod db {
                             value_boolean: None,
                             value_string: None,
                             value_integer: Some(42),
+                            value_code: None,
                         }]),
                         source: Some("http://example.com/meta/source".to_string()),
                         profile: Vec::from([
@@ -1408,6 +2711,7 @@ pub mod db {
                                 value_boolean: None,
                                 value_string: Some("Some value".to_string()),
                                 value_integer: None,
+                                value_code: None,
                             }]),
                             system: Some(
                                 "http://example.com/contained/1/meta/security/1/system".to_string()
@@ -1428,6 +2732,7 @@ pub mod db {
                                 value_boolean: None,
                                 value_string: None,
                                 value_integer: None,
+                                value_code: None,
                             }]),
                             system: Some("http://example.com/meta/security/2/system".to_string()),
                             version: Some("1.0.1".to_string()),
@@ -1447,6 +2752,7 @@ pub mod db {
                     value_boolean: None,
                     value_string: None,
                     value_integer: None,
+                    value_code: None,
                 }]),
                 modifier_extension: Vec::from([Extension {
                     id: Some(db.get_id().await.unwrap()),
@@ -1456,6 +2762,7 @@ pub mod db {
                     value_boolean: None,
                     value_string: None,
                     value_integer: None,
+                    value_code: None,
                 }]),
                 identifier: Vec::from([Identifier {
                     id: Some(db.get_id().await.unwrap()),
@@ -1475,6 +2782,7 @@ pub mod db {
                                 value_boolean: None,
                                 value_string: None,
                                 value_integer: None,
+                                value_code: None,
                             }]),
                             system: Some("http://example.com/identifier/1/system".to_string()),
                             version: Some("1.0.1".to_string()),
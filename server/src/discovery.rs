@@ -0,0 +1,225 @@
+pub mod discovery {
+    use serde::Serialize;
+    use serde_json::{json, Value};
+    use std::env;
+    use std::sync::Arc;
+    use tracing::info;
+
+    /// Resource types with their FHIR interactions and operations, as served by the API.
+    const RESOURCES: [(&str, &[&str], &[&str]); 5] = [
+        ("Patient", &["read", "search-type", "update", "delete"], &["match", "merge"]),
+        ("Observation", &["read", "search-type", "update"], &[]),
+        ("Encounter", &["read", "search-type", "update", "delete"], &[]),
+        ("Provenance", &["read", "search-type"], &[]),
+        ("AuditEvent", &["read", "search-type"], &[]),
+    ];
+
+    /// SMART scopes the server understands, see [crate::smart::smart::Scope].
+    const SCOPES: [&str; 7] = [
+        "launch/patient",
+        "patient/*.cruds",
+        "user/*.cruds",
+        "system/*.cruds",
+        "patient/*.read",
+        "user/*.read",
+        "system/*.read",
+    ];
+
+    /// Discovery document of SMART App Launch, served at `/.well-known/smart-configuration`.
+    /// Tokens are issued by an external authorization server, this only tells clients where.
+    #[derive(Serialize, Clone, Debug, PartialEq)]
+    pub struct SmartConfiguration {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        issuer: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        jwks_uri: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        authorization_endpoint: Option<String>,
+        token_endpoint: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        introspection_endpoint: Option<String>,
+        grant_types_supported: Vec<&'static str>,
+        token_endpoint_auth_methods_supported: Vec<&'static str>,
+        scopes_supported: Vec<&'static str>,
+        response_types_supported: Vec<&'static str>,
+        code_challenge_methods_supported: Vec<&'static str>,
+        capabilities: Vec<&'static str>,
+    }
+
+    impl SmartConfiguration {
+        /// Clients authenticate at the token endpoint and, if there is an authorization endpoint,
+        /// may also launch standalone with a patient picked at the authorization server.
+        pub fn new(token_endpoint: &str,
+                   authorization_endpoint: Option<&str>,
+                   issuer: Option<&str>,
+                   jwks_uri: Option<&str>,
+                   introspection_endpoint: Option<&str>) -> Self {
+            let mut grant_types = vec!["client_credentials"];
+            let mut response_types = Vec::new();
+            let mut capabilities = vec![
+                "client-confidential-symmetric",
+                "permission-patient",
+                "permission-user",
+                "permission-v1",
+                "permission-v2",
+            ];
+            if authorization_endpoint.is_some() {
+                grant_types.insert(0, "authorization_code");
+                response_types.push("code");
+                capabilities.extend(["launch-standalone", "client-public", "context-standalone-patient"]);
+            }
+            return Self {
+                issuer: issuer.map(str::to_string),
+                jwks_uri: jwks_uri.map(str::to_string),
+                authorization_endpoint: authorization_endpoint.map(str::to_string),
+                token_endpoint: token_endpoint.to_string(),
+                introspection_endpoint: introspection_endpoint.map(str::to_string),
+                grant_types_supported: grant_types,
+                token_endpoint_auth_methods_supported: vec!["client_secret_basic", "private_key_jwt"],
+                scopes_supported: SCOPES.to_vec(),
+                response_types_supported: response_types,
+                code_challenge_methods_supported: vec!["S256"],
+                capabilities,
+            };
+        }
+
+        /// Configured by `FHIR_TOKEN_ENDPOINT`, `FHIR_AUTHORIZATION_ENDPOINT` and `FHIR_JWKS_URI`.
+        /// The issuer and introspection endpoint are the ones tokens are validated with.
+        /// None if there is no token endpoint.
+        pub fn from_env() -> Option<Self> {
+            let optional = |name: &str| env::var_os(name).map(|val| val.into_string().unwrap());
+            let token_endpoint = optional("FHIR_TOKEN_ENDPOINT")?;
+            info!(token_endpoint, "Serving SMART configuration");
+            return Some(SmartConfiguration::new(&token_endpoint,
+                                                optional("FHIR_AUTHORIZATION_ENDPOINT").as_deref(),
+                                                optional("FHIR_JWT_ISSUER").as_deref(),
+                                                optional("FHIR_JWKS_URI").as_deref(),
+                                                optional("FHIR_INTROSPECTION_URL").as_deref()));
+        }
+    }
+
+    /// What clients may find out about the server without a token.
+    #[derive(Clone, Debug)]
+    pub struct Discovery {
+        pub smart: Option<SmartConfiguration>,
+        pub capability_statement: Arc<Value>,
+    }
+
+    impl Discovery {
+        pub fn from_env() -> Self {
+            let smart = SmartConfiguration::from_env();
+            let capability_statement = Arc::new(capability_statement(smart.as_ref()));
+            return Self { smart, capability_statement };
+        }
+    }
+
+    /// The CapabilityStatement served at `/fhir/metadata`. Its security section names SMART on
+    /// FHIR and the OAuth2 endpoints if they are configured.
+    pub fn capability_statement(smart: Option<&SmartConfiguration>) -> Value {
+        let resources: Vec<Value> = RESOURCES
+            .iter()
+            .map(|(resource_type, interactions, operations)| {
+                let mut resource = json!({
+                    "type": resource_type,
+                    "interaction": interactions.iter()
+                                               .map(|code| json!({"code": code}))
+                                               .collect::<Vec<_>>(),
+                    "versioning": "versioned",
+                    "updateCreate": interactions.contains(&"update"),
+                });
+                if !operations.is_empty() {
+                    resource["operation"] = operations
+                        .iter()
+                        .map(|name| json!({
+                            "name": name,
+                            "definition": format!("http://hl7.org/fhir/OperationDefinition/{}-{}",
+                                                  resource_type,
+                                                  name),
+                        }))
+                        .collect();
+                }
+                return resource;
+            })
+            .collect();
+
+        let mut security = json!({
+            "cors": true,
+            "description": "Static bearer tokens, JWT or introspected OAuth2 bearer tokens with \
+                            SMART on FHIR scopes.",
+        });
+        if let Some(smart) = smart {
+            security["service"] = json!([{
+                "coding": [{
+                    "system": "http://terminology.hl7.org/CodeSystem/restful-security-service",
+                    "code": "SMART-on-FHIR",
+                }],
+            }]);
+            let mut uris = vec![json!({"url": "token", "valueUri": smart.token_endpoint})];
+            if let Some(authorize) = &smart.authorization_endpoint {
+                uris.push(json!({"url": "authorize", "valueUri": authorize}));
+            }
+            if let Some(introspect) = &smart.introspection_endpoint {
+                uris.push(json!({"url": "introspect", "valueUri": introspect}));
+            }
+            security["extension"] = json!([{
+                "url": "http://fhir-registry.smarthealthit.org/StructureDefinition/oauth-uris",
+                "extension": uris,
+            }]);
+        }
+
+        return json!({
+            "resourceType": "CapabilityStatement",
+            "status": "active",
+            "kind": "instance",
+            "software": {"name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION")},
+            "fhirVersion": "4.0.1",
+            "format": ["json"],
+            "rest": [{
+                "mode": "server",
+                "security": security,
+                "resource": resources,
+            }],
+        });
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use speculoos::assert_that;
+
+        #[test]
+        fn test_smart_configuration() {
+            let system_only = SmartConfiguration::new("https://idp/token", None, None, None, None);
+            let value = serde_json::to_value(&system_only).unwrap();
+            assert_that!(value["grant_types_supported"]).is_equal_to(json!(["client_credentials"]));
+            assert_that!(value.get("authorization_endpoint")).is_equal_to(None);
+
+            let launch = SmartConfiguration::new("https://idp/token",
+                                                 Some("https://idp/authorize"),
+                                                 Some("https://idp"),
+                                                 Some("https://idp/jwks"),
+                                                 Some("https://idp/introspect"));
+            let value = serde_json::to_value(&launch).unwrap();
+            assert_that!(value["grant_types_supported"])
+                .is_equal_to(json!(["authorization_code", "client_credentials"]));
+            assert_that!(value["authorization_endpoint"]).is_equal_to(json!("https://idp/authorize"));
+            assert_that!(value["introspection_endpoint"]).is_equal_to(json!("https://idp/introspect"));
+            assert_that!(value["capabilities"].as_array().unwrap())
+                .matches(|c| c.contains(&json!("launch-standalone")));
+        }
+
+        #[test]
+        fn test_capability_statement() {
+            let without_smart = capability_statement(None);
+            assert_that!(without_smart["rest"][0]["security"].get("service")).is_equal_to(None);
+            assert_that!(without_smart["rest"][0]["resource"][0]["operation"][0]["name"])
+                .is_equal_to(json!("match"));
+
+            let smart = SmartConfiguration::new("https://idp/token", None, None, None, None);
+            let security = &capability_statement(Some(&smart))["rest"][0]["security"];
+            assert_that!(security["service"][0]["coding"][0]["code"]).is_equal_to(json!("SMART-on-FHIR"));
+            assert_that!(security["extension"][0]["extension"])
+                .is_equal_to(json!([{"url": "token", "valueUri": "https://idp/token"}]));
+        }
+    }
+}
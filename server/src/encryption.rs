@@ -0,0 +1,626 @@
+pub mod encryption {
+    use crate::db::db::Db;
+    use crate::resource::resource::{SearchCriterion, SearchIndex};
+    use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+    use base64::Engine;
+    use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+    use ring::hmac;
+    use ring::rand::{SecureRandom, SystemRandom};
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use std::env;
+    use std::error::Error;
+    use std::fmt::{Debug, Formatter};
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use tracing::{error, info};
+
+    /// Member of encrypted resources in `fhir.resource.data` that holds their [Envelope].
+    /// It is removed again when they are read.
+    pub const ENVELOPE: &str = "_encryption";
+    /// Prefix of encrypted values.
+    const ENCRYPTED_PREFIX: &str = "enc:";
+    /// Prefix of blind index entries in `fhir.search_token`.
+    const BLIND_PREFIX: &str = "blind:";
+    const KEY_LEN: usize = 32;
+
+    /// Token search parameters by the element whose values they index. Encrypted elements among
+    /// them are indexed blindly, so that their exact values can still be searched for.
+    const BLIND_INDEXABLE: [(&str, &str, &str); 1] =
+        [("Patient", "identifier.value", "identifier")];
+
+    /// Elements that have to stay in plain. The server reads them itself, or they are searched
+    /// by substring, range or reference, which blind indexes can't do.
+    const PLAIN_ELEMENTS: [(&str, &str); 35] = [
+        ("*", "id"),
+        ("*", "meta"),
+        ("*", "resourceType"),
+        ("Patient", "name.text"),
+        ("Patient", "name.family"),
+        ("Patient", "name.period"),
+        ("Patient", "gender"),
+        ("Patient", "birthDate"),
+        ("Patient", "identifier.system"),
+        ("Patient", "identifier.assigner"),
+        ("Patient", "contact.organization"),
+        ("Patient", "generalPractitioner"),
+        ("Patient", "managingOrganization"),
+        ("Patient", "link"),
+        ("Observation", "code"),
+        ("Observation", "category"),
+        ("Observation", "status"),
+        ("Observation", "effectiveDateTime"),
+        ("Observation", "effectiveInstant"),
+        ("Observation", "effectivePeriod"),
+        ("Observation", "subject"),
+        ("Observation", "encounter"),
+        ("Observation", "performer"),
+        ("Encounter", "status"),
+        ("Encounter", "class"),
+        ("Encounter", "type"),
+        ("Encounter", "period"),
+        ("Encounter", "subject"),
+        ("Encounter", "participant.individual"),
+        ("Encounter", "serviceProvider"),
+        ("Provenance", "recorded"),
+        ("Provenance", "activity"),
+        ("Provenance", "target"),
+        ("Provenance", "agent.who"),
+        ("Provenance", "entity.what"),
+    ];
+
+    /// Key management: wraps and unwraps the data keys of resources with key encryption keys,
+    /// which never leave it, and computes blind indexes.
+    pub trait Kms: Send + Sync {
+        /// ID of the key new data keys are wrapped with.
+        fn current_key_id(&self) -> &str;
+
+        /// Encrypts the data key with the current key.
+        fn wrap(&self, data_key: &[u8]) -> Result<Vec<u8>, Box<dyn Error>>;
+
+        /// Decrypts a data key wrapped with the key with the ID.
+        fn unwrap(&self, key_id: &str, wrapped: &[u8]) -> Result<Vec<u8>, Box<dyn Error>>;
+
+        /// Keyed hash of the value. Doesn't change with the current key, so that the index stays
+        /// valid across rotations.
+        fn blind_index(&self, value: &[u8]) -> Vec<u8>;
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Keyfile {
+        current: String,
+        keys: HashMap<String, String>,
+        blind_index_key: String,
+    }
+
+    /// [Kms] with the keys in a local JSON file, base64 encoded 32 byte keys by ID:
+    /// `{"current": "2025-01", "keys": {"2025-01": "..."}, "blindIndexKey": "..."}`.
+    pub struct KeyfileKms {
+        current: String,
+        keys: HashMap<String, LessSafeKey>,
+        blind_index_key: hmac::Key,
+    }
+
+    impl KeyfileKms {
+        pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+            return KeyfileKms::parse(&std::fs::read_to_string(path)?);
+        }
+
+        pub fn parse(json: &str) -> Result<Self, Box<dyn Error>> {
+            let keyfile: Keyfile = serde_json::from_str(json)?;
+            let decode = |id: &str, key: &str| -> Result<Vec<u8>, Box<dyn Error>> {
+                let key = STANDARD.decode(key)?;
+                if key.len() != KEY_LEN {
+                    return Err(format!("Key {} doesn't have {} bytes", id, KEY_LEN).into());
+                }
+                return Ok(key);
+            };
+            let mut keys = HashMap::new();
+            for (id, key) in &keyfile.keys {
+                keys.insert(id.clone(), aead_key(&decode(id, key)?)?);
+            }
+            if !keys.contains_key(&keyfile.current) {
+                return Err(format!("There is no current key {}", keyfile.current).into());
+            }
+            let blind_index_key = decode("blindIndexKey", &keyfile.blind_index_key)?;
+            return Ok(Self {
+                current: keyfile.current,
+                keys,
+                blind_index_key: hmac::Key::new(hmac::HMAC_SHA256, &blind_index_key),
+            });
+        }
+    }
+
+    /// Only the IDs of the keys, never the keys.
+    impl Debug for KeyfileKms {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            let mut ids: Vec<&String> = self.keys.keys().collect();
+            ids.sort();
+            return f.debug_struct("KeyfileKms")
+                    .field("current", &self.current)
+                    .field("keys", &ids)
+                    .finish();
+        }
+    }
+
+    impl Kms for KeyfileKms {
+        fn current_key_id(&self) -> &str {
+            return &self.current;
+        }
+
+        fn wrap(&self, data_key: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+            return seal(&self.keys[&self.current], self.current.as_bytes(), data_key);
+        }
+
+        fn unwrap(&self, key_id: &str, wrapped: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+            let key = self.keys.get(key_id).ok_or(format!("Unknown key {}", key_id))?;
+            return open(key, key_id.as_bytes(), wrapped);
+        }
+
+        fn blind_index(&self, value: &[u8]) -> Vec<u8> {
+            return hmac::sign(&self.blind_index_key, value).as_ref().to_vec();
+        }
+    }
+
+    fn aead_key(key: &[u8]) -> Result<LessSafeKey, Box<dyn Error>> {
+        let key = UnboundKey::new(&AES_256_GCM, key).map_err(|_| "Invalid AES-256 key")?;
+        return Ok(LessSafeKey::new(key));
+    }
+
+    /// AES-256-GCM with a random nonce, which is put in front of the ciphertext.
+    fn seal(key: &LessSafeKey, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce).map_err(|_| "No randomness for the nonce")?;
+        let mut ciphertext = plaintext.to_vec();
+        key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce),
+                                     Aad::from(aad),
+                                     &mut ciphertext)
+           .map_err(|_| "Could not encrypt")?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        return Ok(sealed);
+    }
+
+    fn open(key: &LessSafeKey, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        if sealed.len() < NONCE_LEN {
+            return Err("Ciphertext is too short".into());
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| "Invalid nonce")?;
+        let mut in_out = ciphertext.to_vec();
+        let plaintext = key.open_in_place(nonce, Aad::from(aad), &mut in_out)
+                           .map_err(|_| "Could not decrypt, the data or key is wrong")?;
+        return Ok(plaintext.to_vec());
+    }
+
+    /// How the encrypted elements of a resource can be decrypted.
+    #[derive(Serialize, Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct Envelope {
+        key_id: String,
+        /// The data key of the resource, wrapped with the key.
+        data_key: String,
+        /// Paths of the encrypted elements, as they were configured when the resource was written.
+        elements: Vec<String>,
+    }
+
+    /// An element of a resource type to encrypt, e.g. `Patient.identifier.value`.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    struct Element {
+        resource_type: String,
+        path: String,
+    }
+
+    impl FromStr for Element {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let (resource_type, path) = s.trim()
+                                         .split_once('.')
+                                         .ok_or(format!("Invalid element {}", s))?;
+            let valid = resource_type.starts_with(|c: char| c.is_ascii_uppercase())
+                && path.split('.').all(|e| !e.is_empty()
+                                           && e.chars().all(|c| c.is_ascii_alphanumeric()));
+            if !valid {
+                return Err(format!("Invalid element {}", s));
+            }
+            let overlaps = |plain: &str| path == plain
+                || path.starts_with(&format!("{}.", plain))
+                || plain.starts_with(&format!("{}.", path));
+            let plain = PLAIN_ELEMENTS.iter()
+                                      .filter(|(t, _)| *t == "*" || *t == resource_type)
+                                      .find(|(_, plain)| overlaps(plain));
+            if let Some((_, plain)) = plain {
+                return Err(format!("{} can't be encrypted, {} has to stay in plain", s, plain));
+            }
+            return Ok(Self { resource_type: resource_type.to_string(), path: path.to_string() });
+        }
+    }
+
+    /// Envelope encryption of configured elements: every resource gets its own data key, which is
+    /// stored wrapped by the [Kms] in the resource. Values of encrypted elements that are searched
+    /// for are indexed by their blind index instead.
+    #[derive(Clone)]
+    pub struct Encryption {
+        kms: Arc<dyn Kms>,
+        elements: Arc<Vec<Element>>,
+    }
+
+    impl Debug for Encryption {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            return f.debug_struct("Encryption")
+                    .field("current_key_id", &self.kms.current_key_id())
+                    .field("elements", &self.elements)
+                    .finish();
+        }
+    }
+
+    impl Encryption {
+        pub fn new(kms: Arc<dyn Kms>, elements: &[&str]) -> Result<Self, String> {
+            let elements = elements.iter()
+                                   .map(|e| Element::from_str(e))
+                                   .collect::<Result<Vec<_>, _>>()?;
+            return Ok(Self { kms, elements: Arc::new(elements) });
+        }
+
+        /// Encrypts the comma separated `FHIR_ENCRYPTED_ELEMENTS`, e.g.
+        /// `Patient.identifier.value,Patient.photo.data`, with the keys in
+        /// `FHIR_ENCRYPTION_KEYFILE`, see [KeyfileKms]. None if no keyfile is configured.
+        pub fn from_env() -> Option<Self> {
+            let keyfile = env::var_os("FHIR_ENCRYPTION_KEYFILE")?.into_string().unwrap();
+            let elements = env::var_os("FHIR_ENCRYPTED_ELEMENTS")
+                .map(|val| val.into_string().unwrap())
+                .unwrap_or_default();
+            let elements: Vec<&str> = elements.split(',')
+                                              .filter(|e| !e.trim().is_empty())
+                                              .collect();
+            let kms = match KeyfileKms::load(&keyfile) {
+                Ok(kms) => kms,
+                Err(e) => {
+                    error!(?e, keyfile, "Invalid FHIR_ENCRYPTION_KEYFILE");
+                    panic!("Invalid FHIR_ENCRYPTION_KEYFILE");
+                }
+            };
+            return match Encryption::new(Arc::new(kms), &elements) {
+                Ok(encryption) => {
+                    info!(?elements, "Encrypting elements at rest");
+                    Some(encryption)
+                }
+                Err(e) => {
+                    error!(?e, "Invalid FHIR_ENCRYPTED_ELEMENTS");
+                    panic!("Invalid FHIR_ENCRYPTED_ELEMENTS");
+                }
+            };
+        }
+
+        /// The resource types with elements to encrypt.
+        pub fn resource_types(&self) -> Vec<String> {
+            let mut types: Vec<String> = self.elements
+                                             .iter()
+                                             .map(|e| e.resource_type.clone())
+                                             .collect();
+            types.sort_unstable();
+            types.dedup();
+            return types;
+        }
+
+        /// Encrypts the configured elements of the resource with a new data key and adds the
+        /// envelope. False if the resource has none of them.
+        pub fn encrypt(&self,
+                       resource_type: &str,
+                       resource: &mut Value,
+        ) -> Result<bool, Box<dyn Error>> {
+            let mut data_key = [0u8; KEY_LEN];
+            SystemRandom::new().fill(&mut data_key).map_err(|_| "No randomness for the data key")?;
+            let key = aead_key(&data_key)?;
+            let mut encrypted = Vec::new();
+            for element in self.elements.iter().filter(|e| e.resource_type == resource_type) {
+                let path: Vec<&str> = element.path.split('.').collect();
+                let found = transform(resource, &path, &mut |value| {
+                    let sealed = seal(&key, element.path.as_bytes(), &serde_json::to_vec(value)?)?;
+                    *value = Value::String(format!("{}{}",
+                                                   ENCRYPTED_PREFIX,
+                                                   STANDARD.encode(sealed)));
+                    return Ok(());
+                })?;
+                if found {
+                    encrypted.push(element.path.clone());
+                }
+            }
+            if encrypted.is_empty() {
+                return Ok(false);
+            }
+            let envelope = Envelope {
+                key_id: self.kms.current_key_id().to_string(),
+                data_key: STANDARD.encode(self.kms.wrap(&data_key)?),
+                elements: encrypted,
+            };
+            resource[ENVELOPE] = serde_json::to_value(envelope)?;
+            return Ok(true);
+        }
+
+        /// Decrypts the elements of the resource and removes the envelope. Resources without one
+        /// are left as they are.
+        pub fn decrypt(&self, resource: &mut Value) -> Result<(), Box<dyn Error>> {
+            let Some(envelope) = resource.as_object_mut().and_then(|r| r.remove(ENVELOPE)) else {
+                return Ok(());
+            };
+            let envelope: Envelope = serde_json::from_value(envelope)?;
+            let data_key = self.kms.unwrap(&envelope.key_id,
+                                           &STANDARD.decode(&envelope.data_key)?)?;
+            let key = aead_key(&data_key)?;
+            for element in &envelope.elements {
+                let path: Vec<&str> = element.split('.').collect();
+                transform(resource, &path, &mut |value| {
+                    let sealed = value.as_str()
+                                      .and_then(|v| v.strip_prefix(ENCRYPTED_PREFIX))
+                                      .ok_or(format!("{} isn't encrypted", element))?;
+                    let plaintext = open(&key, element.as_bytes(), &STANDARD.decode(sealed)?)?;
+                    *value = serde_json::from_slice(&plaintext)?;
+                    return Ok(());
+                })?;
+            }
+            return Ok(());
+        }
+
+        /// Wraps the data key of the resource with the current key, if it isn't already.
+        /// The encrypted elements stay as they are. False if nothing had to be changed.
+        pub fn rewrap(&self, resource: &mut Value) -> Result<bool, Box<dyn Error>> {
+            let Some(envelope) = resource.get(ENVELOPE) else {
+                return Ok(false);
+            };
+            let mut envelope: Envelope = serde_json::from_value(envelope.clone())?;
+            if envelope.key_id == self.kms.current_key_id() {
+                return Ok(false);
+            }
+            let data_key = self.kms.unwrap(&envelope.key_id,
+                                           &STANDARD.decode(&envelope.data_key)?)?;
+            envelope.key_id = self.kms.current_key_id().to_string();
+            envelope.data_key = STANDARD.encode(self.kms.wrap(&data_key)?);
+            resource[ENVELOPE] = serde_json::to_value(envelope)?;
+            return Ok(true);
+        }
+
+        /// Replaces the values of encrypted elements in the index with their blind index.
+        pub fn blind_index(&self, resource_type: &str, index: &mut [SearchIndex]) {
+            for entry in index {
+                if let SearchIndex::Token { param, code, .. } = entry
+                    && self.is_blind(resource_type, param) {
+                    *code = self.blind(param, code);
+                }
+            }
+        }
+
+        /// Replaces the values searched for in encrypted elements with their blind index.
+        pub fn blind_criteria(&self, resource_type: &str, criteria: &mut [SearchCriterion]) {
+            for criterion in criteria {
+                if let SearchCriterion::Token { param, code, .. } = criterion
+                    && self.is_blind(resource_type, param) {
+                    *code = self.blind(param, code);
+                }
+            }
+        }
+
+        fn is_blind(&self, resource_type: &str, param: &str) -> bool {
+            return BLIND_INDEXABLE.iter()
+                                  .filter(|(t, _, p)| *t == resource_type && *p == param)
+                                  .any(|(_, path, _)| self.elements
+                                                          .iter()
+                                                          .any(|e| e.resource_type == resource_type
+                                                              && e.path == *path));
+        }
+
+        fn blind(&self, param: &str, code: &str) -> String {
+            let hash = self.kms.blind_index(format!("{}|{}", param, code).as_bytes());
+            return format!("{}{}", BLIND_PREFIX, URL_SAFE_NO_PAD.encode(hash));
+        }
+    }
+
+    const USAGE: &str = "Usage: FhirDemo encryption rotate";
+
+    /// Runs `FhirDemo encryption <args>`, returns what to print.
+    pub async fn run_cli(db: &Db, args: &[String]) -> Result<String, Box<dyn Error>> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        return match args.as_slice() {
+            ["rotate"] => {
+                let changed = db.rotate_keys().await?;
+                Ok(format!("Rotated the keys of {} resources", changed))
+            }
+            _ => Err(USAGE.into()),
+        };
+    }
+
+    /// Applies the function to the values at the path, descending into arrays on the way.
+    /// True if there was any value.
+    fn transform(value: &mut Value,
+                 path: &[&str],
+                 f: &mut impl FnMut(&mut Value) -> Result<(), Box<dyn Error>>,
+    ) -> Result<bool, Box<dyn Error>> {
+        let Some((element, rest)) = path.split_first() else {
+            if value.is_null() {
+                return Ok(false);
+            }
+            f(value)?;
+            return Ok(true);
+        };
+        return match value {
+            Value::Array(items) => {
+                let mut found = false;
+                for item in items {
+                    found |= transform(item, path, f)?;
+                }
+                Ok(found)
+            }
+            Value::Object(object) => match object.get_mut(*element) {
+                Some(child) => transform(child, rest, f),
+                None => Ok(false),
+            },
+            _ => Ok(false),
+        };
+    }
+
+    #[cfg(test)]
+    pub(crate) mod tests {
+        use super::*;
+        use serde_json::json;
+        use speculoos::assert_that;
+        use speculoos::prelude::ResultAssertions;
+
+        fn key(byte: u8) -> String {
+            return STANDARD.encode([byte; KEY_LEN]);
+        }
+
+        pub(crate) fn keyfile(current: &str) -> KeyfileKms {
+            return KeyfileKms::parse(&json!({
+                "current": current,
+                "keys": {"k1": key(1), "k2": key(2)},
+                "blindIndexKey": key(3),
+            }).to_string()).unwrap();
+        }
+
+        pub(crate) fn encryption(current: &str) -> Encryption {
+            return Encryption::new(Arc::new(keyfile(current)),
+                                   &["Patient.identifier.value",
+                                     "Patient.photo.data",
+                                     "Patient.telecom"])
+                .unwrap();
+        }
+
+        fn patient() -> Value {
+            return json!({
+                "id": "p1",
+                "identifier": [
+                    {"system": "urn:kvnr", "value": "A123456789"},
+                    {"system": "urn:mrn"},
+                ],
+                "telecom": [{"system": "phone", "value": "+49 30 123456"}],
+                "gender": "female",
+            });
+        }
+
+        #[test]
+        fn test_keyfile() {
+            assert_that!(keyfile("k1").current_key_id()).is_equal_to("k1");
+            let parse = |value: Value| KeyfileKms::parse(&value.to_string());
+            assert_that!(parse(json!({
+                "current": "k3",
+                "keys": {"k1": key(1)},
+                "blindIndexKey": key(3),
+            }))).is_err();
+            assert_that!(parse(json!({
+                "current": "k1",
+                "keys": {"k1": "c2hvcnQ="},
+                "blindIndexKey": key(3),
+            }))).is_err();
+        }
+
+        #[test]
+        fn test_elements() {
+            let kms: Arc<dyn Kms> = Arc::new(keyfile("k1"));
+            assert_that!(Encryption::new(kms.clone(),
+                                         &["Patient.photo.data", "Patient.name.given"]))
+                .is_ok();
+            assert_that!(Encryption::new(kms.clone(), &["Patient.identifier"])).is_err();
+            assert_that!(Encryption::new(kms.clone(), &["Patient.name"])).is_err();
+            assert_that!(Encryption::new(kms.clone(), &["Patient.meta.tag"])).is_err();
+            assert_that!(Encryption::new(kms.clone(), &["Observation.subject.display"])).is_err();
+            assert_that!(Encryption::new(kms, &["patient.photo"])).is_err();
+        }
+
+        #[test]
+        fn test_encrypt() {
+            let encryption = encryption("k1");
+            let mut resource = patient();
+            assert_that!(encryption.encrypt("Patient", &mut resource)).is_ok().is_equal_to(true);
+            let stored = resource.to_string();
+            assert_that!(stored.contains("A123456789")).is_equal_to(false);
+            assert_that!(stored.contains("123456")).is_equal_to(false);
+            assert_that!(resource["identifier"][0]["system"]).is_equal_to(json!("urn:kvnr"));
+            assert_that!(resource["gender"]).is_equal_to(json!("female"));
+            assert_that!(resource[ENVELOPE]["elements"])
+                .is_equal_to(json!(["identifier.value", "telecom"]));
+
+            encryption.decrypt(&mut resource).unwrap();
+            assert_that!(resource).is_equal_to(patient());
+
+            let mut observation = json!({"status": "final"});
+            assert_that!(encryption.encrypt("Observation", &mut observation))
+                .is_ok()
+                .is_equal_to(false);
+            assert_that!(observation).is_equal_to(json!({"status": "final"}));
+
+            // values can't be moved to other elements
+            let mut resource = patient();
+            encryption.encrypt("Patient", &mut resource).unwrap();
+            resource["identifier"][0]["value"] = resource["telecom"].clone();
+            assert_that!(encryption.decrypt(&mut resource)).is_err();
+        }
+
+        #[test]
+        fn test_rotation() {
+            let mut resource = patient();
+            encryption("k1").encrypt("Patient", &mut resource).unwrap();
+
+            let rotated = encryption("k2");
+            assert_that!(rotated.rewrap(&mut resource)).is_ok().is_equal_to(true);
+            assert_that!(resource[ENVELOPE]["keyId"]).is_equal_to(json!("k2"));
+            assert_that!(rotated.rewrap(&mut resource)).is_ok().is_equal_to(false);
+
+            // the old key isn't needed anymore
+            let without_k1 = KeyfileKms::parse(&json!({
+                "current": "k2",
+                "keys": {"k2": key(2)},
+                "blindIndexKey": key(3),
+            }).to_string()).unwrap();
+            let without_k1 = Encryption::new(Arc::new(without_k1), &[]).unwrap();
+            without_k1.decrypt(&mut resource).unwrap();
+            assert_that!(resource).is_equal_to(patient());
+        }
+
+        #[test]
+        fn test_blind_index() {
+            let mut index = vec![
+                SearchIndex::Token {
+                    param: "identifier",
+                    system: Some("urn:kvnr".to_string()),
+                    code: "A123456789".to_string(),
+                },
+                SearchIndex::Token { param: "gender", system: None, code: "female".to_string() },
+            ];
+            encryption("k1").blind_index("Patient", &mut index);
+            let mut criteria = vec![SearchCriterion::token("identifier", "urn:kvnr|A123456789")];
+            encryption("k2").blind_criteria("Patient", &mut criteria);
+
+            let SearchIndex::Token { code: indexed, system, .. } = &index[0] else {
+                unreachable!()
+            };
+            let SearchCriterion::Token { code: searched, .. } = &criteria[0] else {
+                unreachable!()
+            };
+            assert_that!(indexed.starts_with(BLIND_PREFIX)).is_equal_to(true);
+            assert_that!(indexed).is_equal_to(searched);
+            assert_that!(system.as_deref()).is_equal_to(Some("urn:kvnr"));
+            assert_that!(index[1]).is_equal_to(SearchIndex::Token {
+                param: "gender",
+                system: None,
+                code: "female".to_string(),
+            });
+
+            // not encrypted, not blind
+            let plain = Encryption::new(Arc::new(keyfile("k1")), &["Patient.photo.data"]).unwrap();
+            let mut plain_index = vec![SearchIndex::Token {
+                param: "identifier",
+                system: None,
+                code: "4711".to_string(),
+            }];
+            plain.blind_index("Patient", &mut plain_index);
+            assert_that!(plain_index[0]).is_equal_to(SearchIndex::Token {
+                param: "identifier",
+                system: None,
+                code: "4711".to_string(),
+            });
+        }
+    }
+}
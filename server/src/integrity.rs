@@ -0,0 +1,119 @@
+pub mod integrity {
+    use crate::resource::resource::{reference_target, StoredResource, HOSTED_RESOURCE_TYPES};
+    use std::env;
+    use std::error::Error;
+    use std::fmt::{Display, Formatter};
+    use std::str::FromStr;
+    use tracing::{error, warn};
+
+    /// How writes treat references to resources hosted on this server that do not exist.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum ReferenceIntegrity {
+        /// Refuse the write.
+        Reject,
+        /// Store the resource anyway but log a warning.
+        Warn,
+        /// Do not check references at all.
+        Allow,
+    }
+
+    /// The resource references resources hosted on this server that do not exist.
+    #[derive(Debug)]
+    pub struct Unresolvable {
+        pub references: Vec<String>,
+    }
+
+    impl Display for Unresolvable {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            return f.write_str(format!("Unresolvable references: {}",
+                                       self.references.join(", ")).as_str());
+        }
+    }
+
+    impl Error for Unresolvable {}
+
+    impl FromStr for ReferenceIntegrity {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            return match s.to_lowercase().as_str() {
+                "reject" => Ok(ReferenceIntegrity::Reject),
+                "warn" => Ok(ReferenceIntegrity::Warn),
+                "allow" => Ok(ReferenceIntegrity::Allow),
+                other => Err(format!("Unknown reference integrity mode: {}", other)),
+            };
+        }
+    }
+
+    impl ReferenceIntegrity {
+        /// Reads the mode from `FHIR_REFERENCE_INTEGRITY`, defaulting to [ReferenceIntegrity::Reject].
+        pub fn from_env() -> Self {
+            return match env::var_os("FHIR_REFERENCE_INTEGRITY") {
+                None => ReferenceIntegrity::Reject,
+                Some(val) => match val.into_string().unwrap().parse() {
+                    Ok(mode) => mode,
+                    Err(e) => {
+                        error!(?e, "Invalid FHIR_REFERENCE_INTEGRITY");
+                        panic!("Invalid FHIR_REFERENCE_INTEGRITY");
+                    }
+                },
+            };
+        }
+
+        /// The references (`Type/id`) of the resource to types hosted on this server, which have
+        /// to resolve. None with [ReferenceIntegrity::Allow].
+        pub fn targets<R: StoredResource>(&self, resource: &R) -> Vec<String> {
+            if *self == ReferenceIntegrity::Allow {
+                return Vec::new();
+            }
+            return resource.references()
+                           .into_iter()
+                           .filter_map(|(_, r)| reference_target(r))
+                           .filter(|(t, _)| HOSTED_RESOURCE_TYPES.contains(t))
+                           .map(|(t, id)| format!("{}/{}", t, id))
+                           .collect();
+        }
+
+        /// Decides about a resource of the type whose `missing` targets do not exist.
+        pub fn check(&self, resource_type: &str, missing: Vec<String>) -> Result<(), Unresolvable> {
+            if missing.is_empty() {
+                return Ok(());
+            }
+            return match self {
+                ReferenceIntegrity::Reject => Err(Unresolvable { references: missing }),
+                _ => {
+                    warn!(?missing, "Storing {} with unresolvable references", resource_type);
+                    Ok(())
+                }
+            };
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use speculoos::assert_that;
+        use speculoos::prelude::{ContainingResultAssertions, ResultAssertions};
+
+        #[test]
+        fn test_parse_mode() {
+            assert_that!("reject".parse::<ReferenceIntegrity>())
+                .is_ok_containing(ReferenceIntegrity::Reject);
+            assert_that!("WARN".parse::<ReferenceIntegrity>())
+                .is_ok_containing(ReferenceIntegrity::Warn);
+            assert_that!("allow".parse::<ReferenceIntegrity>())
+                .is_ok_containing(ReferenceIntegrity::Allow);
+            assert_that!("strict".parse::<ReferenceIntegrity>()).is_err();
+        }
+
+        #[test]
+        fn test_check() {
+            let missing = vec!["Patient/1".to_string()];
+            assert_that!(ReferenceIntegrity::Reject.check("Observation", Vec::new())).is_ok();
+            assert_that!(ReferenceIntegrity::Reject.check("Observation", missing.clone())
+                                                   .map_err(|e| e.to_string()))
+                .is_err_containing("Unresolvable references: Patient/1".to_string());
+            assert_that!(ReferenceIntegrity::Warn.check("Observation", missing)).is_ok();
+        }
+    }
+}
@@ -0,0 +1,288 @@
+pub mod introspection {
+    use crate::auth::auth::Identity;
+    use crate::lru::lru::Lru;
+    use axum::body::Bytes;
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use chrono::Utc;
+    use http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+    use http::{HeaderValue, Request, Uri};
+    use http_body_util::{BodyExt, Full};
+    use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+    use hyper_util::client::legacy::connect::HttpConnector;
+    use hyper_util::client::legacy::Client;
+    use hyper_util::rt::TokioExecutor;
+    use ring::digest::{digest, SHA256};
+    use serde::Deserialize;
+    use std::env;
+    use std::error::Error;
+    use std::fmt::{Display, Formatter};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::time::timeout;
+    use tracing::{error, info};
+
+    /// How long an active token is trusted without asking the endpoint again. Revocations take
+    /// at most this long to be noticed.
+    const CACHE_TTL: Duration = Duration::from_secs(30);
+    const CACHE_CAPACITY: usize = 10_000;
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Validates opaque bearer tokens with the token introspection endpoint of the authorization
+    /// server, see RFC 7662. Active tokens are remembered for [CACHE_TTL].
+    #[derive(Clone)]
+    pub struct Introspector {
+        endpoint: Uri,
+        /// `Authorization` header of this server at the endpoint.
+        credentials: Option<HeaderValue>,
+        client: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+        /// Identities by the SHA-256 of the token, so that tokens aren't kept in memory.
+        active: Arc<Mutex<Lru<Vec<u8>, Identity>>>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct IntrospectionResponse {
+        active: bool,
+        sub: Option<String>,
+        client_id: Option<String>,
+        /// Space separated scopes.
+        scope: Option<String>,
+        exp: Option<i64>,
+        /// SMART launch context, as in the token response.
+        patient: Option<String>,
+        #[serde(default)]
+        roles: Vec<String>,
+    }
+
+    /// Why a token couldn't be introspected or was rejected.
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum IntrospectionError {
+        /// The endpoint didn't answer properly, the token may well be valid.
+        Unavailable(String),
+        Inactive,
+        MissingSubject,
+    }
+
+    impl Display for IntrospectionError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            return match self {
+                IntrospectionError::Unavailable(reason) => {
+                    write!(f, "Introspection unavailable: {}", reason)
+                }
+                IntrospectionError::Inactive => write!(f, "Token is not active"),
+                IntrospectionError::MissingSubject => write!(f, "Token has no subject"),
+            };
+        }
+    }
+
+    impl Error for IntrospectionError {}
+
+    impl Introspector {
+        /// Introspects at the endpoint, authenticating with HTTP basic auth if there is a client
+        /// secret. The endpoint may use http or https.
+        pub fn new(endpoint: &str,
+                   client_id: Option<&str>,
+                   client_secret: Option<&str>) -> Result<Self, Box<dyn Error>> {
+            let endpoint: Uri = endpoint.parse()?;
+            let credentials = match (client_id, client_secret) {
+                (Some(id), Some(secret)) => {
+                    let basic = STANDARD.encode(format!("{}:{}", encode(id), encode(secret)));
+                    Some(HeaderValue::from_str(&format!("Basic {}", basic))?)
+                }
+                (None, None) => None,
+                _ => return Err("Client ID and secret have to be set together".into()),
+            };
+            let connector = HttpsConnectorBuilder::new().with_native_roots()?
+                                                        .https_or_http()
+                                                        .enable_http1()
+                                                        .build();
+            return Ok(Self {
+                endpoint,
+                credentials,
+                client: Client::builder(TokioExecutor::new()).build(connector),
+                active: Arc::new(Mutex::new(Lru::new(CACHE_CAPACITY))),
+            });
+        }
+
+        /// Introspects at `FHIR_INTROSPECTION_URL` as `FHIR_INTROSPECTION_CLIENT_ID` with
+        /// `FHIR_INTROSPECTION_CLIENT_SECRET`. None if no endpoint is configured.
+        pub fn from_env() -> Option<Self> {
+            let endpoint = env::var_os("FHIR_INTROSPECTION_URL")?.into_string().unwrap();
+            let optional = |name: &str| env::var_os(name).map(|val| val.into_string().unwrap());
+            let client_id = optional("FHIR_INTROSPECTION_CLIENT_ID");
+            let client_secret = optional("FHIR_INTROSPECTION_CLIENT_SECRET");
+            let introspector = Introspector::new(&endpoint,
+                                                 client_id.as_deref(),
+                                                 client_secret.as_deref());
+            return match introspector {
+                Ok(introspector) => {
+                    info!(endpoint, "Introspecting opaque bearer tokens");
+                    Some(introspector)
+                }
+                Err(e) => {
+                    error!(?e, "Invalid FHIR_INTROSPECTION_URL or client credentials");
+                    panic!("Invalid FHIR_INTROSPECTION_URL or client credentials");
+                }
+            };
+        }
+
+        /// The identity the token was issued for, if the authorization server says it is active.
+        pub async fn introspect(&self, token: &str) -> Result<Identity, IntrospectionError> {
+            let key = digest(&SHA256, token.as_bytes()).as_ref().to_vec();
+            if let Some(identity) = self.active.lock().unwrap().get_mut(&key) {
+                return Ok(identity.clone());
+            }
+            let response = self.request(token).await?;
+            if !response.active {
+                return Err(IntrospectionError::Inactive);
+            }
+            let now = Utc::now().timestamp();
+            let remaining = match response.exp {
+                Some(exp) if exp <= now => return Err(IntrospectionError::Inactive),
+                Some(exp) => Duration::from_secs((exp - now) as u64).min(CACHE_TTL),
+                None => CACHE_TTL,
+            };
+            let Some(subject) = response.sub.filter(|s| !s.is_empty()) else {
+                return Err(IntrospectionError::MissingSubject);
+            };
+            let identity = Identity {
+                subject,
+                client_id: response.client_id,
+                scopes: response.scope
+                                .unwrap_or_default()
+                                .split_whitespace()
+                                .map(str::to_string)
+                                .collect(),
+                patient: response.patient,
+                roles: response.roles,
+            };
+            self.active.lock().unwrap().get_or_insert_with(key, remaining, || identity.clone());
+            return Ok(identity);
+        }
+
+        async fn request(&self, token: &str) -> Result<IntrospectionResponse, IntrospectionError> {
+            let unavailable = |e: &dyn Display| IntrospectionError::Unavailable(e.to_string());
+            let body = form_urlencoded::Serializer::new(String::new())
+                .append_pair("token", token)
+                .append_pair("token_type_hint", "access_token")
+                .finish();
+            let mut request = Request::post(&self.endpoint)
+                .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .header(ACCEPT, "application/json");
+            if let Some(credentials) = &self.credentials {
+                request = request.header(AUTHORIZATION, credentials);
+            }
+            let request = request.body(Full::from(body)).map_err(|e| unavailable(&e))?;
+            let response = timeout(TIMEOUT, self.client.request(request))
+                .await
+                .map_err(|e| unavailable(&e))?
+                .map_err(|e| unavailable(&e))?;
+            if !response.status().is_success() {
+                return Err(unavailable(&response.status()));
+            }
+            let body = response.into_body().collect().await.map_err(|e| unavailable(&e))?;
+            return serde_json::from_slice(&body.to_bytes()).map_err(|e| unavailable(&e));
+        }
+    }
+
+    /// Client credentials are form encoded before basic auth, see RFC 6749 section 2.3.1.
+    fn encode(credential: &str) -> String {
+        return form_urlencoded::byte_serialize(credential.as_bytes()).collect();
+    }
+
+    #[cfg(test)]
+    pub(crate) mod tests {
+        use super::*;
+        use axum::extract::State;
+        use axum::routing::post;
+        use axum::{Form, Json, Router};
+        use http::HeaderMap;
+        use serde_json::{json, Value};
+        use speculoos::assert_that;
+        use speculoos::prelude::ResultAssertions;
+        use std::collections::HashMap;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::net::TcpListener;
+
+        /// Local introspection endpoint that knows the token `good` and counts its calls.
+        pub(crate) async fn stub_endpoint() -> (String, Arc<AtomicUsize>) {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let introspect = async |State(calls): State<Arc<AtomicUsize>>,
+                                    headers: HeaderMap,
+                                    Form(form): Form<HashMap<String, String>>| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                // the secret `s3cret=` is form encoded first
+                let expected = format!("Basic {}", STANDARD.encode("fhir:s3cret%3D"));
+                let authenticated = headers.get(AUTHORIZATION)
+                                           .is_some_and(|h| h.as_bytes() == expected.as_bytes());
+                if !authenticated {
+                    return Json(json!({"active": false}));
+                }
+                let response: Value = match form.get("token").map(String::as_str) {
+                    Some("good") => json!({
+                        "active": true,
+                        "sub": "alice",
+                        "client_id": "gui",
+                        "scope": "launch/patient patient/*.rs",
+                        "patient": "p1",
+                        "exp": Utc::now().timestamp() + 300,
+                    }),
+                    Some("expired") => json!({
+                        "active": true,
+                        "sub": "alice",
+                        "exp": Utc::now().timestamp() - 1,
+                    }),
+                    _ => json!({"active": false}),
+                };
+                return Json(response);
+            };
+            let app = Router::new().route("/introspect", post(introspect)).with_state(calls.clone());
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/introspect", listener.local_addr().unwrap());
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+            return (url, calls);
+        }
+
+        #[tokio::test]
+        async fn test_introspect() {
+            let (url, calls) = stub_endpoint().await;
+            let introspector = Introspector::new(&url, Some("fhir"), Some("s3cret=")).unwrap();
+
+            assert_that!(introspector.introspect("good").await).is_ok().is_equal_to(Identity {
+                subject: "alice".to_string(),
+                client_id: Some("gui".to_string()),
+                scopes: vec!["launch/patient".to_string(), "patient/*.rs".to_string()],
+                patient: Some("p1".to_string()),
+                roles: Vec::new(),
+            });
+            assert_that!(introspector.introspect("good").await).is_ok();
+            assert_that!(calls.load(Ordering::SeqCst)).is_equal_to(1);
+
+            assert_that!(introspector.introspect("unknown").await)
+                .is_err()
+                .is_equal_to(IntrospectionError::Inactive);
+            assert_that!(introspector.introspect("expired").await)
+                .is_err()
+                .is_equal_to(IntrospectionError::Inactive);
+            // inactive tokens are asked about every time, they may become active
+            assert_that!(introspector.introspect("unknown").await).is_err();
+            assert_that!(calls.load(Ordering::SeqCst)).is_equal_to(4);
+
+            let unauthenticated = Introspector::new(&url, None, None).unwrap();
+            assert_that!(unauthenticated.introspect("good").await)
+                .is_err()
+                .is_equal_to(IntrospectionError::Inactive);
+        }
+
+        #[tokio::test]
+        async fn test_unavailable_endpoint() {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/introspect", listener.local_addr().unwrap());
+            drop(listener);
+            let introspector = Introspector::new(&url, None, None).unwrap();
+            assert_that!(introspector.introspect("good").await)
+                .is_err()
+                .matches(|e| matches!(e, IntrospectionError::Unavailable(_)));
+        }
+    }
+}
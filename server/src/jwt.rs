@@ -0,0 +1,404 @@
+pub mod jwt {
+    use crate::auth::auth::Identity;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use chrono::Utc;
+    use ring::signature::{
+        RsaPublicKeyComponents,
+        UnparsedPublicKey,
+        ECDSA_P256_SHA256_FIXED,
+        RSA_PKCS1_2048_8192_SHA256,
+    };
+    use serde::de::DeserializeOwned;
+    use serde::Deserialize;
+    use std::env;
+    use std::error::Error;
+    use std::fmt::{Display, Formatter};
+    use std::fs::read_to_string;
+    use std::sync::Arc;
+    use tracing::{error, info};
+
+    /// Seconds by which the clocks of the identity provider and this server may differ.
+    const LEEWAY: i64 = 60;
+
+    /// Validates JWT bearer tokens issued by an OAuth2/OIDC identity provider, using the keys
+    /// of a local JWKS file. Only RS256 and ES256 are accepted.
+    #[derive(Clone, Debug)]
+    pub struct JwtValidator {
+        issuer: String,
+        audience: String,
+        keys: Arc<Vec<Jwk>>,
+    }
+
+    /// A public key of a JWKS, see RFC 7517.
+    #[derive(Deserialize, Debug, Clone)]
+    struct Jwk {
+        kty: String,
+        kid: Option<String>,
+        alg: Option<String>,
+        #[serde(rename = "use")]
+        key_use: Option<String>,
+        crv: Option<String>,
+        n: Option<String>,
+        e: Option<String>,
+        x: Option<String>,
+        y: Option<String>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct Jwks {
+        keys: Vec<Jwk>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct Header {
+        alg: String,
+        kid: Option<String>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[serde(untagged)]
+    enum Audience {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct Claims {
+        iss: Option<String>,
+        sub: Option<String>,
+        aud: Option<Audience>,
+        exp: Option<i64>,
+        nbf: Option<i64>,
+        /// The client the token was issued to, OIDC uses `azp`, RFC 9068 `client_id`.
+        azp: Option<String>,
+        client_id: Option<String>,
+        /// Space separated scopes as in RFC 8693, some providers use an array in `scp` instead.
+        scope: Option<String>,
+        #[serde(default)]
+        scp: Vec<String>,
+        /// SMART launch context, the patient the token is about.
+        patient: Option<String>,
+        #[serde(default)]
+        roles: Vec<String>,
+    }
+
+    /// Why a token was rejected.
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum JwtError {
+        Malformed,
+        UnsupportedAlgorithm(String),
+        UnknownKey,
+        InvalidSignature,
+        InvalidIssuer,
+        InvalidAudience,
+        Expired,
+        NotYetValid,
+        MissingSubject,
+    }
+
+    impl Display for JwtError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            return match self {
+                JwtError::Malformed => write!(f, "Malformed token"),
+                JwtError::UnsupportedAlgorithm(alg) => write!(f, "Unsupported algorithm {}", alg),
+                JwtError::UnknownKey => write!(f, "Unknown signing key"),
+                JwtError::InvalidSignature => write!(f, "Invalid signature"),
+                JwtError::InvalidIssuer => write!(f, "Invalid issuer"),
+                JwtError::InvalidAudience => write!(f, "Invalid audience"),
+                JwtError::Expired => write!(f, "Token expired"),
+                JwtError::NotYetValid => write!(f, "Token not yet valid"),
+                JwtError::MissingSubject => write!(f, "Token has no subject"),
+            };
+        }
+    }
+
+    impl Error for JwtError {}
+
+    impl JwtValidator {
+        /// Accepts tokens of the issuer for the audience, signed by a key of the JWKS.
+        pub fn new(issuer: &str, audience: &str, jwks: &str) -> Result<Self, Box<dyn Error>> {
+            let jwks: Jwks = serde_json::from_str(jwks)?;
+            let keys: Vec<Jwk> = jwks.keys
+                                     .into_iter()
+                                     .filter(|k| k.key_use.as_deref().is_none_or(|u| u == "sig"))
+                                     .collect();
+            if keys.is_empty() {
+                return Err("JWKS contains no signing keys".into());
+            }
+            return Ok(Self {
+                issuer: issuer.to_string(),
+                audience: audience.to_string(),
+                keys: Arc::new(keys),
+            });
+        }
+
+        /// Validates tokens with the keys in `FHIR_JWKS_FILE`, which must be issued by
+        /// `FHIR_JWT_ISSUER` for `FHIR_JWT_AUDIENCE`. None if no JWKS file is configured.
+        pub fn from_env() -> Option<Self> {
+            let path = env::var_os("FHIR_JWKS_FILE")?.into_string().unwrap();
+            let required = |name: &str| match env::var_os(name) {
+                Some(val) => val.into_string().unwrap(),
+                None => {
+                    error!("{} is required with FHIR_JWKS_FILE", name);
+                    panic!("{} is required with FHIR_JWKS_FILE", name);
+                }
+            };
+            let issuer = required("FHIR_JWT_ISSUER");
+            let audience = required("FHIR_JWT_AUDIENCE");
+            let validator = read_to_string(&path)
+                .map_err(|e| e.into())
+                .and_then(|jwks| JwtValidator::new(&issuer, &audience, &jwks));
+            return match validator {
+                Ok(validator) => {
+                    info!(path, issuer, audience, "Accepting JWT bearer tokens");
+                    Some(validator)
+                }
+                Err(e) => {
+                    error!(?e, "Invalid FHIR_JWKS_FILE");
+                    panic!("Invalid FHIR_JWKS_FILE");
+                }
+            };
+        }
+
+        /// The identity the token was issued for, if it is valid now.
+        pub fn validate(&self, token: &str) -> Result<Identity, JwtError> {
+            let mut parts = token.split('.');
+            let (Some(header), Some(payload), Some(signature), None) =
+                (parts.next(), parts.next(), parts.next(), parts.next()) else {
+                return Err(JwtError::Malformed);
+            };
+            let header: Header = decode_json(header)?;
+            let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| JwtError::Malformed)?;
+            let signed = &token[..header_and_payload_len(token)];
+            self.verify(&header, signed.as_bytes(), &signature)?;
+
+            let claims: Claims = decode_json(payload)?;
+            if claims.iss.as_deref() != Some(self.issuer.as_str()) {
+                return Err(JwtError::InvalidIssuer);
+            }
+            let audience_matches = match &claims.aud {
+                Some(Audience::One(aud)) => *aud == self.audience,
+                Some(Audience::Many(auds)) => auds.contains(&self.audience),
+                None => false,
+            };
+            if !audience_matches {
+                return Err(JwtError::InvalidAudience);
+            }
+            let now = Utc::now().timestamp();
+            // tokens without expiration would be valid forever
+            if claims.exp.is_none_or(|exp| exp + LEEWAY <= now) {
+                return Err(JwtError::Expired);
+            }
+            if claims.nbf.is_some_and(|nbf| nbf - LEEWAY > now) {
+                return Err(JwtError::NotYetValid);
+            }
+            let Some(subject) = claims.sub.filter(|s| !s.is_empty()) else {
+                return Err(JwtError::MissingSubject);
+            };
+            let mut scopes: Vec<String> = claims.scope
+                                                .unwrap_or_default()
+                                                .split_whitespace()
+                                                .map(str::to_string)
+                                                .collect();
+            scopes.extend(claims.scp);
+            return Ok(Identity {
+                subject,
+                client_id: claims.azp.or(claims.client_id),
+                scopes,
+                patient: claims.patient,
+                roles: claims.roles,
+            });
+        }
+
+        fn verify(&self, header: &Header, signed: &[u8], signature: &[u8]) -> Result<(), JwtError> {
+            let kty = match header.alg.as_str() {
+                "RS256" => "RSA",
+                "ES256" => "EC",
+                // in particular `none` and the HMAC algorithms, which would turn a public key
+                // into a shared secret
+                alg => return Err(JwtError::UnsupportedAlgorithm(alg.to_string())),
+            };
+            let mut candidates = self.keys
+                                     .iter()
+                                     .filter(|k| k.kty == kty)
+                                     .filter(|k| k.alg.as_ref().is_none_or(|a| *a == header.alg))
+                                     .filter(|k| header.kid.is_none() || k.kid == header.kid);
+            let key = match (candidates.next(), candidates.next()) {
+                (Some(key), None) => key,
+                // without a kid, the key has to be unambiguous
+                _ => return Err(JwtError::UnknownKey),
+            };
+            let verified = match kty {
+                "RSA" => {
+                    let n = decode_key_part(&key.n)?;
+                    let e = decode_key_part(&key.e)?;
+                    RsaPublicKeyComponents { n: &n, e: &e }
+                        .verify(&RSA_PKCS1_2048_8192_SHA256, signed, signature)
+                }
+                _ => {
+                    if key.crv.as_deref() != Some("P-256") {
+                        return Err(JwtError::UnknownKey);
+                    }
+                    let mut point = vec![0x04];
+                    point.extend(decode_key_part(&key.x)?);
+                    point.extend(decode_key_part(&key.y)?);
+                    UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, point).verify(signed, signature)
+                }
+            };
+            return verified.map_err(|_| JwtError::InvalidSignature);
+        }
+    }
+
+    /// Length of the signed part of the token, everything before the second dot.
+    fn header_and_payload_len(token: &str) -> usize {
+        return token.rfind('.').unwrap_or(token.len());
+    }
+
+    fn decode_json<T: DeserializeOwned>(part: &str) -> Result<T, JwtError> {
+        let bytes = URL_SAFE_NO_PAD.decode(part).map_err(|_| JwtError::Malformed)?;
+        return serde_json::from_slice(&bytes).map_err(|_| JwtError::Malformed);
+    }
+
+    fn decode_key_part(part: &Option<String>) -> Result<Vec<u8>, JwtError> {
+        let part = part.as_deref().ok_or(JwtError::UnknownKey)?;
+        return URL_SAFE_NO_PAD.decode(part).map_err(|_| JwtError::UnknownKey);
+    }
+
+    #[cfg(test)]
+    pub(crate) mod tests {
+        use super::*;
+        use ring::rand::SystemRandom;
+        use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+        use serde_json::{json, Value};
+        use speculoos::assert_that;
+        use speculoos::prelude::{ContainingResultAssertions, ResultAssertions};
+
+        pub(crate) const ISSUER: &str = "https://idp.example.org";
+        pub(crate) const AUDIENCE: &str = "fhir-demo";
+
+        /// Signs tokens with a freshly generated ES256 key.
+        pub(crate) struct TestIssuer {
+            key_pair: EcdsaKeyPair,
+            pub(crate) kid: String,
+        }
+
+        impl TestIssuer {
+            pub(crate) fn new(kid: &str) -> Self {
+                let rng = SystemRandom::new();
+                let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                    .unwrap();
+                let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING,
+                                                        pkcs8.as_ref(),
+                                                        &rng).unwrap();
+                return Self { key_pair, kid: kid.to_string() };
+            }
+
+            pub(crate) fn jwks(&self) -> String {
+                // uncompressed point: 0x04, x, y
+                let point = self.key_pair.public_key().as_ref();
+                return json!({"keys": [{
+                    "kty": "EC",
+                    "crv": "P-256",
+                    "use": "sig",
+                    "alg": "ES256",
+                    "kid": self.kid,
+                    "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+                    "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+                }]}).to_string();
+            }
+
+            pub(crate) fn sign(&self, header: &Value, claims: &Value) -> String {
+                let signed = format!("{}.{}",
+                                     URL_SAFE_NO_PAD.encode(header.to_string()),
+                                     URL_SAFE_NO_PAD.encode(claims.to_string()));
+                let signature = self.key_pair
+                                    .sign(&SystemRandom::new(), signed.as_bytes())
+                                    .unwrap();
+                return format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(signature.as_ref()));
+            }
+
+            /// A valid token for the subject with the scopes.
+            pub(crate) fn token(&self, subject: &str, scope: &str) -> String {
+                return self.sign_claims(&claims(subject, scope));
+            }
+
+            pub(crate) fn sign_claims(&self, claims: &Value) -> String {
+                return self.sign(&json!({"alg": "ES256", "typ": "JWT", "kid": self.kid}), claims);
+            }
+        }
+
+        /// Valid claims for the subject with the scopes.
+        pub(crate) fn claims(subject: &str, scope: &str) -> Value {
+            return json!({
+                "iss": ISSUER,
+                "aud": [AUDIENCE, "other"],
+                "sub": subject,
+                "azp": "gui",
+                "scope": scope,
+                "exp": Utc::now().timestamp() + 300,
+            });
+        }
+
+        #[test]
+        fn test_valid_token() {
+            let issuer = TestIssuer::new("key-1");
+            let validator = JwtValidator::new(ISSUER, AUDIENCE, &issuer.jwks()).unwrap();
+
+            let mut claims = claims("alice", "openid patient/*.rs");
+            claims["patient"] = json!("p1");
+            claims["roles"] = json!(["restricted-records"]);
+            let identity = validator.validate(&issuer.sign_claims(&claims));
+
+            assert_that!(identity).is_ok().is_equal_to(Identity {
+                subject: "alice".to_string(),
+                client_id: Some("gui".to_string()),
+                scopes: vec!["openid".to_string(), "patient/*.rs".to_string()],
+                patient: Some("p1".to_string()),
+                roles: vec!["restricted-records".to_string()],
+            });
+        }
+
+        #[test]
+        fn test_rejected_tokens() {
+            let issuer = TestIssuer::new("key-1");
+            let validator = JwtValidator::new(ISSUER, AUDIENCE, &issuer.jwks()).unwrap();
+            let header = json!({"alg": "ES256", "kid": "key-1"});
+            let now = Utc::now().timestamp();
+            let claims = json!({"iss": ISSUER, "aud": AUDIENCE, "sub": "alice", "exp": now + 300});
+            let with = |key: &str, value: Value| {
+                let mut claims = claims.clone();
+                claims[key] = value;
+                return issuer.sign(&header, &claims);
+            };
+
+            assert_that!(validator.validate(&issuer.sign(&header, &claims))).is_ok();
+            assert_that!(validator.validate(&with("iss", json!("https://evil.example.org"))))
+                .is_err_containing(JwtError::InvalidIssuer);
+            assert_that!(validator.validate(&with("aud", json!("other"))))
+                .is_err_containing(JwtError::InvalidAudience);
+            assert_that!(validator.validate(&with("exp", json!(now - LEEWAY - 1))))
+                .is_err_containing(JwtError::Expired);
+            assert_that!(validator.validate(&with("exp", Value::Null)))
+                .is_err_containing(JwtError::Expired);
+            assert_that!(validator.validate(&with("nbf", json!(now + LEEWAY + 10))))
+                .is_err_containing(JwtError::NotYetValid);
+            assert_that!(validator.validate(&with("sub", json!(""))))
+                .is_err_containing(JwtError::MissingSubject);
+
+            // signed by another key with the same kid
+            let forged = TestIssuer::new("key-1").sign(&header, &claims);
+            assert_that!(validator.validate(&forged)).is_err_containing(JwtError::InvalidSignature);
+            let unknown = TestIssuer::new("key-2").token("alice", "");
+            assert_that!(validator.validate(&unknown)).is_err_containing(JwtError::UnknownKey);
+
+            let valid = issuer.sign(&header, &claims);
+            let (signed, _) = valid.rsplit_once('.').unwrap();
+            let (_, payload) = signed.split_once('.').unwrap();
+            let unsigned = format!("{}.{}.", URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#), payload);
+            assert_that!(validator.validate(&unsigned))
+                .is_err_containing(JwtError::UnsupportedAlgorithm("none".to_string()));
+            assert_that!(validator.validate("not a token")).is_err_containing(JwtError::Malformed);
+        }
+    }
+}
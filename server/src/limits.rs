@@ -0,0 +1,268 @@
+pub mod limits {
+    use crate::config::config::env_or;
+    use axum::body::to_bytes;
+    use axum::extract::State;
+    use axum::middleware::Next;
+    use axum_core::body::Body;
+    use axum_core::extract::Request;
+    use axum_core::response::{IntoResponse, Response};
+    use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+    use http::{Method, StatusCode};
+    use serde_json::json;
+    use tracing::{error, info};
+
+    /// Default upper bound of a request body in bytes.
+    const MAX_BODY: usize = 4 * 1024 * 1024;
+    /// Default nesting of objects and arrays. Resources rarely go deeper than 10, but
+    /// `Extension.extension` is recursive.
+    const MAX_DEPTH: usize = 32;
+    /// Default number of items in an array.
+    const MAX_ARRAY: usize = 1000;
+    /// Default decoded size of the base64 `Attachment.data`.
+    const MAX_ATTACHMENT: usize = 1024 * 1024;
+
+    /// Why a body was rejected.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum Violation {
+        BodyTooLarge(usize),
+        TooDeep(usize),
+        ArrayTooLong(usize),
+        AttachmentTooLarge(usize),
+    }
+
+    impl Violation {
+        pub fn status(&self) -> StatusCode {
+            return match self {
+                Violation::BodyTooLarge(_) | Violation::AttachmentTooLarge(_) => {
+                    StatusCode::PAYLOAD_TOO_LARGE
+                }
+                Violation::TooDeep(_) | Violation::ArrayTooLong(_) => {
+                    StatusCode::UNPROCESSABLE_ENTITY
+                }
+            };
+        }
+
+        /// The code of the issue in the OperationOutcome.
+        fn code(&self) -> &'static str {
+            return match self {
+                Violation::TooDeep(_) => "structure",
+                _ => "too-long",
+            };
+        }
+
+        fn diagnostics(&self) -> String {
+            return match self {
+                Violation::BodyTooLarge(max) => format!("Body is larger than {} bytes", max),
+                Violation::TooDeep(max) => format!("JSON is nested deeper than {} levels", max),
+                Violation::ArrayTooLong(max) => format!("Array has more than {} items", max),
+                Violation::AttachmentTooLarge(max) => {
+                    format!("Attachment.data is larger than {} bytes", max)
+                }
+            };
+        }
+
+        pub fn operation_outcome(&self) -> Response<Body> {
+            let outcome = json!({
+                "resourceType": "OperationOutcome",
+                "issue": [{
+                    "severity": "error",
+                    "code": self.code(),
+                    "diagnostics": self.diagnostics(),
+                }],
+            });
+            return (self.status(),
+                    [(CONTENT_TYPE, "application/fhir+json")],
+                    outcome.to_string()).into_response();
+        }
+    }
+
+    /// Limits of request bodies, checked before they are deserialized.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Limits {
+        pub body: usize,
+        pub depth: usize,
+        pub array: usize,
+        pub attachment: usize,
+    }
+
+    impl Default for Limits {
+        fn default() -> Self {
+            return Self {
+                body: MAX_BODY,
+                depth: MAX_DEPTH,
+                array: MAX_ARRAY,
+                attachment: MAX_ATTACHMENT,
+            };
+        }
+    }
+
+    impl Limits {
+        /// `FHIR_MAX_BODY_BYTES`, `FHIR_MAX_JSON_DEPTH`, `FHIR_MAX_ARRAY_LENGTH` and
+        /// `FHIR_MAX_ATTACHMENT_BYTES`, each with a default.
+        pub fn from_env() -> Self {
+            let limits = Limits {
+                body: env_or("FHIR_MAX_BODY_BYTES", MAX_BODY),
+                depth: env_or("FHIR_MAX_JSON_DEPTH", MAX_DEPTH),
+                array: env_or("FHIR_MAX_ARRAY_LENGTH", MAX_ARRAY),
+                attachment: env_or("FHIR_MAX_ATTACHMENT_BYTES", MAX_ATTACHMENT),
+            };
+            info!(?limits, "Request limits");
+            return limits;
+        }
+
+        /// Checks the structure of a JSON document without parsing it, so that deeply nested
+        /// documents are rejected before serde, and the recursive [crate::setid::SetId], descend
+        /// into them. Syntax errors are left to serde.
+        pub fn check(&self, json: &[u8]) -> Result<(), Violation> {
+            // per open container, the commas so far in arrays, None for objects
+            let mut stack: Vec<Option<usize>> = Vec::new();
+            let mut expect_key = false;
+            // whether the last key was `data`, the base64 of an Attachment
+            let mut data_key = false;
+            let mut i = 0;
+            while i < json.len() {
+                match json[i] {
+                    b'"' => {
+                        let start = i + 1;
+                        i = start;
+                        while i < json.len() && json[i] != b'"' {
+                            i += if json[i] == b'\\' { 2 } else { 1 };
+                        }
+                        let string = &json[start..i.min(json.len())];
+                        if expect_key {
+                            data_key = string == b"data";
+                            expect_key = false;
+                        } else if data_key && matches!(stack.last(), Some(None)) {
+                            // base64 has 3 bytes in every 4 characters
+                            if string.len() / 4 * 3 > self.attachment {
+                                return Err(Violation::AttachmentTooLarge(self.attachment));
+                            }
+                        }
+                    }
+                    open @ (b'{' | b'[') => {
+                        if stack.len() >= self.depth {
+                            return Err(Violation::TooDeep(self.depth));
+                        }
+                        stack.push((open == b'[').then_some(0));
+                        expect_key = open == b'{';
+                    }
+                    b'}' | b']' => {
+                        stack.pop();
+                    }
+                    b',' => match stack.last_mut() {
+                        Some(Some(commas)) => {
+                            *commas += 1;
+                            if *commas >= self.array {
+                                return Err(Violation::ArrayTooLong(self.array));
+                            }
+                        }
+                        Some(None) => expect_key = true,
+                        None => {}
+                    },
+                    _ => {}
+                }
+                i += 1;
+            }
+            return Ok(());
+        }
+
+        /// Rejects bodies over the limits with an OperationOutcome, before they are read by
+        /// a handler.
+        pub async fn limits_middleware(State(limits): State<Limits>,
+                                       req: Request<Body>,
+                                       next: Next) -> Response<Body> {
+            if !matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH) {
+                return next.run(req).await;
+            }
+            let too_large = Violation::BodyTooLarge(limits.body);
+            let length = req.headers()
+                            .get(CONTENT_LENGTH)
+                            .and_then(|l| l.to_str().ok())
+                            .and_then(|l| l.parse::<usize>().ok());
+            if length.is_some_and(|l| l > limits.body) {
+                return too_large.operation_outcome();
+            }
+            let (parts, body) = req.into_parts();
+            let bytes = match to_bytes(body, limits.body).await {
+                Ok(bytes) => bytes,
+                // the limit is the only way reading a buffered body fails
+                Err(_) => return too_large.operation_outcome(),
+            };
+            if let Err(violation) = limits.check(&bytes) {
+                error!(?violation, path = parts.uri.path(), "Rejected request body");
+                return violation.operation_outcome();
+            }
+            return next.run(Request::from_parts(parts, Body::from(bytes))).await;
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use axum::middleware::from_fn_with_state;
+        use axum::routing::put;
+        use axum::Router;
+        use serde_json::Value;
+        use speculoos::assert_that;
+        use speculoos::prelude::ResultAssertions;
+        use tower::ServiceExt;
+
+        fn limits() -> Limits {
+            return Limits { body: 200, depth: 4, array: 3, attachment: 6 };
+        }
+
+        #[test]
+        fn test_check() {
+            let limits = limits();
+            assert_that!(limits.check(br#"{"a": [{"b": [1]}]}"#)).is_ok();
+            assert_that!(limits.check(br#"{"a": [{"b": [[1]]}]}"#))
+                .is_err()
+                .is_equal_to(Violation::TooDeep(4));
+            // brackets in strings don't count
+            assert_that!(limits.check(br#"{"a": "[[[[[\"[["}"#)).is_ok();
+
+            assert_that!(limits.check(br#"{"a": [1, 2, 3], "b": [[], [], []]}"#)).is_ok();
+            assert_that!(limits.check(br#"{"a": [1, 2, 3, 4]}"#))
+                .is_err()
+                .is_equal_to(Violation::ArrayTooLong(3));
+
+            assert_that!(limits.check(br#"{"photo": [{"data": "QUJDREVG"}]}"#)).is_ok();
+            assert_that!(limits.check(br#"{"photo": [{"data": "QUJDREVGR0hJ"}]}"#))
+                .is_err()
+                .is_equal_to(Violation::AttachmentTooLarge(6));
+            // only the values of `data` are attachments
+            assert_that!(limits.check(br#"{"text": "QUJDREVGR0hJ", "data": 1}"#)).is_ok();
+            assert_that!(limits.check(br#"{"data": ["QUJDREVGR0hJ"]}"#)).is_ok();
+        }
+
+        #[tokio::test]
+        async fn test_limits_middleware() {
+            let app = Router::new()
+                .route("/fhir/patient", put(async |body: String| body))
+                .layer(from_fn_with_state(limits(), Limits::limits_middleware));
+            let put_patient = async |body: String| {
+                let request = Request::put("/fhir/patient").body(Body::from(body)).unwrap();
+                let response = app.clone().oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                return (status, String::from_utf8(body.to_vec()).unwrap());
+            };
+
+            let patient = r#"{"name": [{"text": "Anna Meier"}]}"#.to_string();
+            assert_that!(put_patient(patient.clone()).await)
+                .is_equal_to((StatusCode::OK, patient));
+
+            let (status, body) = put_patient(format!(r#"{{"text": "{}"}}"#, "a".repeat(200))).await;
+            assert_that!(status).is_equal_to(StatusCode::PAYLOAD_TOO_LARGE);
+            let outcome: Value = serde_json::from_str(&body).unwrap();
+            assert_that!(outcome["resourceType"]).is_equal_to(json!("OperationOutcome"));
+            assert_that!(outcome["issue"][0]["code"]).is_equal_to(json!("too-long"));
+
+            let nested = r#"{"extension": [{"extension": [{"extension": [{}]}]}]}"#.to_string();
+            let (status, body) = put_patient(nested).await;
+            assert_that!(status).is_equal_to(StatusCode::UNPROCESSABLE_ENTITY);
+            let outcome: Value = serde_json::from_str(&body).unwrap();
+            assert_that!(outcome["issue"][0]["code"]).is_equal_to(json!("structure"));
+        }
+    }
+}
@@ -0,0 +1,132 @@
+pub mod lru {
+    use std::collections::{BTreeMap, HashMap};
+    use std::hash::Hash;
+    use std::time::{Duration, Instant};
+
+    /// Map with a maximum number of entries that drops the least recently used entry when full.
+    /// Entries expire a given time after they were inserted, using them doesn't extend that.
+    pub struct Lru<K, V> {
+        capacity: usize,
+        entries: HashMap<K, Slot<V>>,
+        /// Keys by the tick of their last use, the first one is the least recently used.
+        order: BTreeMap<u64, K>,
+        tick: u64,
+    }
+
+    struct Slot<V> {
+        value: V,
+        expires_at: Instant,
+        tick: u64,
+    }
+
+    impl<K: Hash + Eq + Clone, V> Lru<K, V> {
+        pub fn new(capacity: usize) -> Self {
+            return Self {
+                capacity,
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                tick: 0,
+            };
+        }
+
+        /// The value of the key, unless it expired. Marks the key as used.
+        pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+            let expired = self.entries.get(key)?.expires_at <= Instant::now();
+            if expired {
+                self.remove(key);
+                return None;
+            }
+            self.touch(key);
+            return self.entries.get_mut(key).map(|slot| &mut slot.value);
+        }
+
+        /// The value of the key, inserting it first if it is missing or expired. An inserted
+        /// value expires after `ttl`. Marks the key as used.
+        pub fn get_or_insert_with(&mut self,
+                                  key: K,
+                                  ttl: Duration,
+                                  value: impl FnOnce() -> V) -> &mut V {
+            if self.get_mut(&key).is_none() {
+                // the new entry is always kept, so the caller has somewhere to put its value
+                while self.entries.len() >= self.capacity.max(1) {
+                    self.evict_least_recently_used();
+                }
+                self.tick += 1;
+                self.order.insert(self.tick, key.clone());
+                self.entries.insert(key.clone(), Slot {
+                    value: value(),
+                    expires_at: Instant::now() + ttl,
+                    tick: self.tick,
+                });
+            }
+            return &mut self.entries.get_mut(&key).unwrap().value;
+        }
+
+        pub fn remove(&mut self, key: &K) {
+            if let Some(slot) = self.entries.remove(key) {
+                self.order.remove(&slot.tick);
+            }
+        }
+
+        fn touch(&mut self, key: &K) {
+            self.tick += 1;
+            let tick = self.tick;
+            if let Some(slot) = self.entries.get_mut(key) {
+                self.order.remove(&slot.tick);
+                slot.tick = tick;
+                self.order.insert(tick, key.clone());
+            }
+        }
+
+        fn evict_least_recently_used(&mut self) {
+            if let Some((_, key)) = self.order.pop_first() {
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use speculoos::assert_that;
+        use speculoos::prelude::OptionAssertions;
+
+        #[test]
+        fn test_evicts_least_recently_used() {
+            let ttl = Duration::from_secs(60);
+            let mut lru = Lru::new(2);
+            lru.get_or_insert_with("a", ttl, || 1);
+            lru.get_or_insert_with("b", ttl, || 2);
+            // a is now used more recently than b
+            lru.get_mut(&"a");
+            lru.get_or_insert_with("c", ttl, || 3);
+
+            assert_that!(lru.entries.len()).is_equal_to(2);
+            assert_that!(lru.get_mut(&"a").copied()).is_some().is_equal_to(1);
+            assert_that!(lru.get_mut(&"b")).is_none();
+            assert_that!(lru.get_mut(&"c").copied()).is_some().is_equal_to(3);
+        }
+
+        #[test]
+        fn test_expires() {
+            let mut lru = Lru::new(2);
+            lru.get_or_insert_with("a", Duration::ZERO, || 1);
+            lru.get_or_insert_with("b", Duration::from_secs(60), || 2);
+
+            assert_that!(lru.get_mut(&"a")).is_none();
+            assert_that!(lru.get_mut(&"b").copied()).is_some().is_equal_to(2);
+            assert_that!(lru.entries.len()).is_equal_to(1);
+        }
+
+        #[test]
+        fn test_keeps_existing_value() {
+            let ttl = Duration::from_secs(60);
+            let mut lru = Lru::new(2);
+            *lru.get_or_insert_with("a", ttl, || 1) += 1;
+
+            assert_that!(*lru.get_or_insert_with("a", ttl, || 10)).is_equal_to(2);
+            lru.remove(&"a");
+            assert_that!(lru.get_mut(&"a")).is_none();
+        }
+    }
+}
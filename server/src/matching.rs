@@ -0,0 +1,450 @@
+pub mod matching {
+    use crate::consent::consent::SecurityLabel;
+    use crate::db::db::Db;
+    use crate::merge::merge::replaced_by;
+    use crate::model::model::{
+        Address,
+        Bundle,
+        BundleEntry,
+        BundleEntrySearch,
+        BundleType,
+        Extension,
+        HumanName,
+        Patient,
+        SearchEntryMode,
+        SearchOperator,
+    };
+    use crate::resource::resource::{ResourceSearch, SearchCriterion, StoredResource};
+    use std::env;
+    use std::error::Error;
+    use std::str::FromStr;
+    use tracing::error;
+
+    const MATCH_GRADE_URL: &str = "http://hl7.org/fhir/StructureDefinition/match-grade";
+
+    /// Relative weights of the compared features. Only features the candidate has are scored,
+    /// so a candidate without address isn't penalized for it.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct MatchWeights {
+        pub name: f64,
+        pub birthdate: f64,
+        pub gender: f64,
+        pub identifier: f64,
+        pub address: f64,
+    }
+
+    impl Default for MatchWeights {
+        fn default() -> Self {
+            return Self {
+                name: 0.3,
+                birthdate: 0.25,
+                gender: 0.05,
+                identifier: 0.3,
+                address: 0.1,
+            };
+        }
+    }
+
+    impl FromStr for MatchWeights {
+        type Err = String;
+
+        /// Parses `feature=weight` pairs separated by commas, e.g. `name=0.5,address=0`.
+        /// Features that are not listed keep their default weight.
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let mut weights = MatchWeights::default();
+            for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+                let (feature, weight) = pair.split_once('=')
+                                            .ok_or(format!("Missing weight in {}", pair))?;
+                let weight = weight.trim()
+                                   .parse::<f64>()
+                                   .ok()
+                                   .filter(|w| w.is_finite() && *w >= 0.0)
+                                   .ok_or(format!("Invalid weight in {}", pair))?;
+                match feature.trim().to_lowercase().as_str() {
+                    "name" => weights.name = weight,
+                    "birthdate" => weights.birthdate = weight,
+                    "gender" => weights.gender = weight,
+                    "identifier" => weights.identifier = weight,
+                    "address" => weights.address = weight,
+                    other => return Err(format!("Unknown match feature: {}", other)),
+                }
+            }
+            return Ok(weights);
+        }
+    }
+
+    /// How likely an existing patient is the same person as the candidate,
+    /// see <http://hl7.org/fhir/valueset-match-grade.html>.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum MatchGrade {
+        CertainlyNot,
+        Possible,
+        Probable,
+        Certain,
+    }
+
+    impl MatchGrade {
+        pub fn from_score(score: f64) -> Self {
+            return if score >= 0.9 {
+                MatchGrade::Certain
+            } else if score >= 0.75 {
+                MatchGrade::Probable
+            } else if score >= 0.6 {
+                MatchGrade::Possible
+            } else {
+                MatchGrade::CertainlyNot
+            };
+        }
+
+        pub fn code(&self) -> &'static str {
+            return match self {
+                MatchGrade::Certain => "certain",
+                MatchGrade::Probable => "probable",
+                MatchGrade::Possible => "possible",
+                MatchGrade::CertainlyNot => "certainly-not",
+            };
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct PatientMatch {
+        pub patient: Patient,
+        pub score: f64,
+        pub grade: MatchGrade,
+    }
+
+    /// Finds existing patients that are likely the same person as a candidate.
+    #[derive(Clone, Debug)]
+    pub struct Matcher {
+        weights: MatchWeights,
+        /// Whether `PUT /fhir/patient` warns about likely duplicates.
+        pub warn_on_write: bool,
+    }
+
+    impl Matcher {
+        pub fn new(weights: MatchWeights, warn_on_write: bool) -> Self {
+            return Self { weights, warn_on_write };
+        }
+
+        /// Reads the weights from `FHIR_MATCH_WEIGHTS` and whether to warn on writes from
+        /// `FHIR_MATCH_WARN_ON_WRITE`. Both are optional.
+        pub fn from_env() -> Self {
+            let weights = match env::var_os("FHIR_MATCH_WEIGHTS") {
+                None => MatchWeights::default(),
+                Some(val) => match val.into_string().unwrap().parse() {
+                    Ok(weights) => weights,
+                    Err(e) => {
+                        error!(?e, "Invalid FHIR_MATCH_WEIGHTS");
+                        panic!("Invalid FHIR_MATCH_WEIGHTS");
+                    }
+                },
+            };
+            let warn_on_write = match env::var_os("FHIR_MATCH_WARN_ON_WRITE") {
+                None => false,
+                Some(val) => match val.into_string().unwrap().parse() {
+                    Ok(warn) => warn,
+                    Err(e) => {
+                        error!(?e, "Invalid FHIR_MATCH_WARN_ON_WRITE");
+                        panic!("Invalid FHIR_MATCH_WARN_ON_WRITE");
+                    }
+                },
+            };
+            return Matcher::new(weights, warn_on_write);
+        }
+
+        /// Scores how similar the existing patient is to the candidate, between 0 and 1.
+        pub fn score(&self, candidate: &Patient, existing: &Patient) -> f64 {
+            let mut features: Vec<(f64, f64)> = Vec::new();
+            if !candidate.name.is_empty() {
+                features.push((self.weights.name, name_similarity(candidate, existing)));
+            }
+            if let Some(birth_date) = &candidate.birth_date {
+                let equal = existing.birth_date.as_ref() == Some(birth_date);
+                features.push((self.weights.birthdate, if equal { 1.0 } else { 0.0 }));
+            }
+            if let Some(gender) = &candidate.gender {
+                let equal = existing.gender.as_ref() == Some(gender);
+                features.push((self.weights.gender, if equal { 1.0 } else { 0.0 }));
+            }
+            if candidate.identifier.iter().any(|i| i.value.is_some()) {
+                let shared = candidate.identifier
+                                      .iter()
+                                      .filter(|i| i.value.is_some())
+                                      .any(|i| existing.identifier
+                                                       .iter()
+                                                       .any(|e| e.system == i.system
+                                                           && e.value == i.value));
+                features.push((self.weights.identifier, if shared { 1.0 } else { 0.0 }));
+            }
+            if let Some(address) = &candidate.address {
+                features.push((self.weights.address,
+                               address_similarity(address, existing.address.as_ref())));
+            }
+
+            let total: f64 = features.iter().map(|(weight, _)| weight).sum();
+            if total <= 0.0 {
+                return 0.0;
+            }
+            return features.iter().map(|(weight, score)| weight * score).sum::<f64>() / total;
+        }
+
+        /// Returns the existing patients that at least possibly match the candidate,
+        /// best match first. Patients that have been merged into others and the candidate itself
+        /// are never returned. Only the first 100 patients sharing any searchable value with the
+        /// candidate are scored, out of those in the compartment and without hidden labels.
+        pub async fn find(&self,
+                          db: &Db,
+                          candidate: &Patient,
+                          compartment: Option<SearchCriterion>,
+                          hidden: Vec<SecurityLabel>,
+                          count: usize,
+        ) -> Result<Vec<PatientMatch>, Box<dyn Error>> {
+            let search = candidate_search(candidate, compartment, hidden);
+            if search.criteria.is_empty() {
+                return Ok(Vec::new());
+            }
+            let mut matches: Vec<PatientMatch> =
+                db.search_resources::<Patient>(&search)
+                  .await?
+                  .into_iter()
+                  .map(|hit| hit.resource)
+                  .filter(|p| candidate.id.is_none() || p.id != candidate.id)
+                  .filter(|p| replaced_by(p).is_none())
+                  .map(|patient| {
+                      let score = self.score(candidate, &patient);
+                      return PatientMatch { patient, score, grade: MatchGrade::from_score(score) };
+                  })
+                  .filter(|m| m.grade != MatchGrade::CertainlyNot)
+                  .collect();
+            matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+            matches.truncate(count);
+            return Ok(matches);
+        }
+    }
+
+    /// Search result bundle of matches, with their score and match grade.
+    pub fn match_bundle(matches: Vec<PatientMatch>) -> Bundle<Patient> {
+        return Bundle {
+            resource_type: "Bundle".to_string(),
+            bundle_type: BundleType::Searchset,
+            total: Some(matches.len() as u32),
+            link: Vec::new(),
+            entry: matches.into_iter()
+                          .map(|m| BundleEntry {
+                              full_url: m.patient
+                                         .id
+                                         .as_ref()
+                                         .map(|id| format!("{}/{}", Patient::RESOURCE_TYPE, id)),
+                              resource: m.patient,
+                              search: Some(BundleEntrySearch {
+                                  extension: vec![Extension {
+                                      id: None,
+                                      extension: Vec::new(),
+                                      url: MATCH_GRADE_URL.to_string(),
+                                      value_base_64_binary: None,
+                                      value_boolean: None,
+                                      value_string: None,
+                                      value_integer: None,
+                                      value_code: Some(m.grade.code().to_string()),
+                                  }],
+                                  mode: SearchEntryMode::Match,
+                                  score: Some(m.score),
+                              }),
+                          })
+                          .collect(),
+        };
+    }
+
+    /// Patients sharing an identifier, the birthdate or a family name with the candidate.
+    fn candidate_search(candidate: &Patient,
+                        compartment: Option<SearchCriterion>,
+                        hidden: Vec<SecurityLabel>,
+    ) -> ResourceSearch {
+        let mut criteria = Vec::new();
+        for identifier in &candidate.identifier {
+            if let Some(value) = &identifier.value {
+                criteria.push(SearchCriterion::Token {
+                    param: "identifier",
+                    system: identifier.system.clone(),
+                    code: value.clone(),
+                });
+            }
+        }
+        if let Some(birth_date) = &candidate.birth_date {
+            criteria.push(SearchCriterion::Date {
+                param: "birthdate",
+                from: Some(birth_date.clone()),
+                until: Some(birth_date.clone()),
+            });
+        }
+        for family in candidate.name.iter().filter_map(|n| n.family.as_ref()) {
+            criteria.push(SearchCriterion::String { param: "family", value: family.clone() });
+        }
+        return ResourceSearch {
+            criteria,
+            operator: SearchOperator::Or,
+            compartment,
+            hidden,
+            count: 100,
+            iteration_key: None,
+            last_id: None,
+        };
+    }
+
+    /// Jaro-Winkler similarity below which names are considered unrelated.
+    /// Even completely different names tend to reach about 0.5.
+    const NAME_SIMILARITY_FLOOR: f64 = 0.7;
+
+    /// Best Jaro-Winkler similarity between any names of the two patients,
+    /// rescaled so that unrelated names score 0.
+    fn name_similarity(candidate: &Patient, existing: &Patient) -> f64 {
+        let existing: Vec<String> = existing.name.iter().filter_map(full_name).collect();
+        let best = candidate.name
+                            .iter()
+                            .filter_map(full_name)
+                            .flat_map(|c| existing.iter()
+                                                  .map(move |e| strsim::jaro_winkler(&c, e)))
+                            .fold(0.0, f64::max);
+        return ((best - NAME_SIMILARITY_FLOOR) / (1.0 - NAME_SIMILARITY_FLOOR)).max(0.0);
+    }
+
+    /// The text of the name, or given names and family name if there is no text.
+    fn full_name(name: &HumanName) -> Option<String> {
+        let full = match &name.text {
+            Some(text) => text.clone(),
+            None => name.given.iter().chain(name.family.iter()).cloned().collect::<Vec<_>>().join(" "),
+        };
+        let normalized = full.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+        return Some(normalized).filter(|n| !n.is_empty());
+    }
+
+    /// Share of the candidate's postal code, city and lines that are the same in the existing
+    /// address, ignoring case.
+    fn address_similarity(candidate: &Address, existing: Option<&Address>) -> f64 {
+        let Some(existing) = existing else {
+            return 0.0;
+        };
+        let normalize = |s: &String| s.trim().to_lowercase();
+        let mut compared = 0;
+        let mut equal = 0;
+        let pairs = [
+            (&candidate.postal_code, &existing.postal_code),
+            (&candidate.city, &existing.city),
+        ];
+        for (c, e) in pairs {
+            if let Some(c) = c {
+                compared += 1;
+                if e.as_ref().map(normalize) == Some(normalize(c)) {
+                    equal += 1;
+                }
+            }
+        }
+        if !candidate.line.is_empty() {
+            compared += 1;
+            let lines = |a: &Address| a.line.iter().map(normalize).collect::<Vec<_>>();
+            if lines(candidate) == lines(existing) {
+                equal += 1;
+            }
+        }
+        if compared == 0 {
+            return 0.0;
+        }
+        return equal as f64 / compared as f64;
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::model::model::Gender::{Female, Male};
+        use crate::model::model::Identifier;
+        use speculoos::assert_that;
+        use speculoos::prelude::{ContainingResultAssertions, ResultAssertions};
+
+        fn patient(given: &str, family: &str, birth_date: &str) -> Patient {
+            let mut patient: Patient = serde_json::from_str("{}").unwrap();
+            patient.name = vec![HumanName {
+                id: None,
+                extension: Vec::new(),
+                human_name_use: None,
+                text: None,
+                family: Some(family.to_string()),
+                given: vec![given.to_string()],
+                prefix: Vec::new(),
+                suffix: Vec::new(),
+                period: None,
+            }];
+            patient.birth_date = Some(birth_date.to_string());
+            patient.gender = Some(Female);
+            return patient;
+        }
+
+        fn identifier(value: &str) -> Identifier {
+            return Identifier {
+                id: None,
+                extension: Vec::new(),
+                identifier_use: None,
+                identifier_type: None,
+                system: Some("urn:mrn".to_string()),
+                value: Some(value.to_string()),
+                period: None,
+                assigner: None,
+            };
+        }
+
+        #[test]
+        fn test_parse_weights() {
+            assert_that!("name=0.5, address=0".parse::<MatchWeights>())
+                .is_ok_containing(MatchWeights {
+                    name: 0.5,
+                    address: 0.0,
+                    ..MatchWeights::default()
+                });
+            assert_that!("".parse::<MatchWeights>()).is_ok_containing(MatchWeights::default());
+            assert_that!("name".parse::<MatchWeights>()).is_err();
+            assert_that!("name=-1".parse::<MatchWeights>()).is_err();
+            assert_that!("shoe_size=1".parse::<MatchWeights>()).is_err();
+        }
+
+        #[test]
+        fn test_score() {
+            let matcher = Matcher::new(MatchWeights::default(), false);
+            let candidate = patient("Anna", "Meier", "1990-01-01");
+
+            let same = patient("Anna", "Meier", "1990-01-01");
+            let typo = patient("Anna", "Maier", "1990-01-01");
+            let mut other = patient("Bernd", "Schulz", "1971-05-05");
+            other.gender = Some(Male);
+
+            assert_that!(matcher.score(&candidate, &same)).is_equal_to(1.0);
+            assert_that!(MatchGrade::from_score(matcher.score(&candidate, &typo)))
+                .is_equal_to(MatchGrade::Probable);
+            assert_that!(MatchGrade::from_score(matcher.score(&candidate, &other)))
+                .is_equal_to(MatchGrade::CertainlyNot);
+
+            // a shared identifier keeps a renamed patient a possible match
+            let mut renamed = patient("Anna", "Schulz", "1990-01-01");
+            assert_that!(MatchGrade::from_score(matcher.score(&candidate, &renamed)))
+                .is_equal_to(MatchGrade::CertainlyNot);
+            let mut with_identifier = candidate.clone();
+            with_identifier.identifier = vec![identifier("42")];
+            renamed.identifier = vec![identifier("42")];
+            assert_that!(MatchGrade::from_score(matcher.score(&with_identifier, &renamed)))
+                .is_equal_to(MatchGrade::Possible);
+        }
+
+        #[test]
+        fn test_weights_ignore_missing_features() {
+            let only_names = Matcher::new(MatchWeights {
+                name: 1.0,
+                birthdate: 0.0,
+                gender: 0.0,
+                identifier: 0.0,
+                address: 0.0,
+            }, false);
+            let candidate = patient("Anna", "Meier", "1990-01-01");
+            let older = patient("Anna", "Meier", "1950-01-01");
+
+            assert_that!(only_names.score(&candidate, &older)).is_equal_to(1.0);
+        }
+    }
+}
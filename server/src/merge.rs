@@ -0,0 +1,229 @@
+pub mod merge {
+    use crate::model::model::{
+        CodeableConcept,
+        Coding,
+        Link,
+        LinkType,
+        Patient,
+        Provenance,
+        ProvenanceAgent,
+        Reference,
+    };
+    use crate::resource::resource::{reference_target, StoredResource};
+    use chrono::{SecondsFormat, Utc};
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use serde_json::Value;
+
+    const LIFECYCLE_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/iso-21089-lifecycle";
+
+    /// Checks whether the source can be merged into the target.
+    /// Returns the reason if it can't.
+    pub fn check_mergeable(source: &Patient, target: &Patient) -> Result<(), String> {
+        if source.id.is_some() && source.id == target.id {
+            return Err("Cannot merge a patient into itself".to_string());
+        }
+        if let Some(replaced_by) = replaced_by(target) {
+            return Err(format!("Target has already been merged into {}", replaced_by));
+        }
+        if let Some(replaced_by) = replaced_by(source)
+            && Some(replaced_by) != target.id.as_deref() {
+            return Err(format!("Source has already been merged into {}", replaced_by));
+        }
+        return Ok(());
+    }
+
+    /// Moves identifiers and names of the source into the target, unless the target already
+    /// has them. Afterwards the source is inactive and replaced by the target, and the target
+    /// replaces the source. Merging again is a no-op.
+    /// Both patients must have their IDs set.
+    pub fn merge_patients(source: &mut Patient, target: &mut Patient) {
+        for identifier in &source.identifier {
+            let known = target.identifier
+                              .iter()
+                              .any(|i| i.system == identifier.system && i.value == identifier.value);
+            if !known {
+                target.identifier.push(without_ids(identifier));
+            }
+        }
+        for name in &source.name {
+            let name = without_ids(name);
+            if !target.name.iter().any(|n| without_ids(n) == name) {
+                target.name.push(name);
+            }
+        }
+
+        let source_id = source.id.clone().unwrap_or_default();
+        let target_id = target.id.clone().unwrap_or_default();
+        source.active = Some(false);
+        add_link(source, LinkType::ReplacedBy, &target_id);
+        add_link(target, LinkType::Replaces, &source_id);
+    }
+
+    /// Provenance of merging the source into the target, performed by the agents.
+    pub fn merge_provenance(source_id: &str,
+                            target_id: &str,
+                            agents: Vec<ProvenanceAgent>,
+    ) -> Provenance {
+        return Provenance {
+            id: None,
+            meta: None,
+            text: None,
+            extension: Vec::new(),
+            target: vec![patient_reference(target_id), patient_reference(source_id)],
+            occurred_date_time: None,
+            recorded: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            reason: Vec::new(),
+            activity: Some(CodeableConcept {
+                id: None,
+                extension: Vec::new(),
+                coding: vec![Coding {
+                    id: None,
+                    extension: Vec::new(),
+                    system: Some(LIFECYCLE_SYSTEM.to_string()),
+                    version: None,
+                    code: Some("merge".to_string()),
+                    display: Some("Merge Record Lifecycle Event".to_string()),
+                    user_selected: None,
+                }],
+                text: None,
+            }),
+            agent: agents,
+            entity: Vec::new(),
+        };
+    }
+
+    /// ID of the patient that replaces this one, if any.
+    pub fn replaced_by(patient: &Patient) -> Option<&str> {
+        return patient.link
+                      .iter()
+                      .filter(|l| l.link_type == LinkType::ReplacedBy)
+                      .filter_map(|l| reference_target(&l.other))
+                      .find(|(t, _)| *t == Patient::RESOURCE_TYPE)
+                      .map(|(_, id)| id);
+    }
+
+    fn add_link(patient: &mut Patient, link_type: LinkType, other_id: &str) {
+        let other = patient_reference(other_id);
+        let linked = patient.link
+                            .iter()
+                            .any(|l| l.link_type == link_type && l.other.reference == other.reference);
+        if !linked {
+            patient.link.push(Link { other, link_type });
+        }
+    }
+
+    fn patient_reference(id: &str) -> Reference {
+        return Reference {
+            id: None,
+            extension: Vec::new(),
+            reference: Some(format!("{}/{}", Patient::RESOURCE_TYPE, id)),
+            ref_type: Some(Patient::RESOURCE_TYPE.to_string()),
+            identifier: None,
+            display: None,
+        };
+    }
+
+    /// Copy of the element without any element IDs, so that the copy gets IDs of its own.
+    fn without_ids<T: Serialize + DeserializeOwned + Clone>(element: &T) -> T {
+        fn strip(value: &mut Value) {
+            match value {
+                Value::Object(map) => {
+                    map.remove("id");
+                    map.values_mut().for_each(strip);
+                }
+                Value::Array(values) => values.iter_mut().for_each(strip),
+                _ => {}
+            }
+        }
+        let Ok(mut value) = serde_json::to_value(element) else {
+            return element.clone();
+        };
+        strip(&mut value);
+        return serde_json::from_value(value).unwrap_or_else(|_| element.clone());
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::model::model::{HumanName, Identifier};
+        use speculoos::assert_that;
+        use speculoos::prelude::{ResultAssertions, VecAssertions};
+
+        fn patient(id: &str, name: &str, identifier: &str) -> Patient {
+            let mut patient: Patient = serde_json::from_str("{}").unwrap();
+            patient.id = Some(id.to_string());
+            patient.name = vec![HumanName {
+                id: Some(format!("{}-name", id)),
+                extension: Vec::new(),
+                human_name_use: None,
+                text: Some(name.to_string()),
+                family: None,
+                given: Vec::new(),
+                prefix: Vec::new(),
+                suffix: Vec::new(),
+                period: None,
+            }];
+            patient.identifier = vec![Identifier {
+                id: Some(format!("{}-identifier", id)),
+                extension: Vec::new(),
+                identifier_use: None,
+                identifier_type: None,
+                system: Some("urn:mrn".to_string()),
+                value: Some(identifier.to_string()),
+                period: None,
+                assigner: None,
+            }];
+            return patient;
+        }
+
+        #[test]
+        fn test_merge_patients() {
+            let mut source = patient("s", "Anna Meier", "1");
+            let mut target = patient("t", "Anna Schulz", "2");
+            target.identifier.push(source.identifier[0].clone());
+
+            merge_patients(&mut source, &mut target);
+
+            assert_that!(source.active).is_equal_to(Some(false));
+            assert_that!(source.link).has_length(1);
+            assert_that!(source.link[0].link_type.clone()).is_equal_to(LinkType::ReplacedBy);
+            assert_that!(source.link[0].other.reference.clone())
+                .is_equal_to(Some("Patient/t".to_string()));
+            assert_that!(target.link).has_length(1);
+            assert_that!(target.link[0].link_type.clone()).is_equal_to(LinkType::Replaces);
+            assert_that!(target.link[0].other.reference.clone())
+                .is_equal_to(Some("Patient/s".to_string()));
+            // the identifier was already known
+            assert_that!(target.identifier).has_length(2);
+            assert_that!(target.name).has_length(2);
+            assert_that!(target.name[1].text.clone()).is_equal_to(Some("Anna Meier".to_string()));
+            assert_that!(target.name[1].id.clone()).is_equal_to(None);
+
+            // merging again doesn't change anything
+            let (source_before, target_before) = (source.clone(), target.clone());
+            merge_patients(&mut source, &mut target);
+            assert_that!(source).is_equal_to(source_before);
+            assert_that!(target).is_equal_to(target_before);
+        }
+
+        #[test]
+        fn test_check_mergeable() {
+            let mut source = patient("s", "A", "1");
+            let mut target = patient("t", "B", "2");
+            let mut other = patient("o", "C", "3");
+
+            assert_that!(check_mergeable(&source, &target)).is_ok();
+            assert_that!(check_mergeable(&source, &source.clone())).is_err();
+
+            merge_patients(&mut source, &mut target);
+            // repeating the same merge is fine
+            assert_that!(check_mergeable(&source, &target)).is_ok();
+            assert_that!(check_mergeable(&source, &other)).is_err();
+            assert_that!(check_mergeable(&other, &source)).is_err();
+
+            merge_patients(&mut target, &mut other);
+            assert_that!(check_mergeable(&source, &target)).is_err();
+        }
+    }
+}
@@ -1,12 +1,13 @@
 pub mod model {
     use crate::model::model::SearchOperator::And;
-    use chrono::{DateTime, FixedOffset};
+    use chrono::{DateTime, FixedOffset, Utc};
     use serde::{Deserialize, Serialize};
     use std::error::Error;
     use std::fmt::Debug;
     use tokio_postgres::types::{FromSql, Type};
 
     fn default_count() -> u32 { 30 }
+    fn default_match_count() -> u32 { 10 }
     fn default_vec<T>() -> Vec<T> { Vec::new() }
     fn deserialize_vec<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
     where
@@ -84,11 +85,162 @@ pub mod model {
         pub link: Vec<Link>,
     }
 
+    #[derive(Serialize, Deserialize, FromSql, Debug, PartialEq, Clone)]
+    #[serde(rename_all = "camelCase")]
+    #[postgres(name = "observation")]
+    pub struct Observation {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub meta: Option<Meta>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub implicit_rules: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub language: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub text: Option<Narrative>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub contained: Vec<Resource>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub extension: Vec<Extension>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub modifier_extension: Vec<Extension>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub identifier: Vec<Identifier>,
+        pub status: ObservationStatus,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub category: Vec<CodeableConcept>,
+        pub code: CodeableConcept,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub subject: Option<Reference>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub encounter: Option<Reference>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub effective_date_time: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub effective_period: Option<Period>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub effective_instant: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub issued: Option<String>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub performer: Vec<Reference>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub value_quantity: Option<Quantity>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub value_codeable_concept: Option<CodeableConcept>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub value_string: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub value_boolean: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub value_integer: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub value_period: Option<Period>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub value_date_time: Option<String>,
+        // Skipping the remaining value[x] types, the pattern would continue like this.
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub interpretation: Vec<CodeableConcept>,
+    }
+
+    #[derive(Serialize, Deserialize, FromSql, Debug, PartialEq, Eq, Clone)]
+    #[serde(rename_all = "camelCase")]
+    #[postgres(name = "encounter")]
+    pub struct Encounter {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub meta: Option<Meta>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub implicit_rules: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub language: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub text: Option<Narrative>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub contained: Vec<Resource>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub extension: Vec<Extension>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub modifier_extension: Vec<Extension>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub identifier: Vec<Identifier>,
+        pub status: EncounterStatus,
+        pub class: Coding,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec", rename = "type")]
+        pub encounter_type: Vec<CodeableConcept>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub subject: Option<Reference>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub participant: Vec<EncounterParticipant>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub period: Option<Period>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub reason_code: Vec<CodeableConcept>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub service_provider: Option<Reference>,
+    }
+
+    #[derive(Serialize, Deserialize, FromSql, Debug, PartialEq, Eq, Clone)]
+    #[serde(rename_all = "camelCase")]
+    #[postgres(name = "provenance")]
+    pub struct Provenance {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub meta: Option<Meta>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub text: Option<Narrative>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub extension: Vec<Extension>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub target: Vec<Reference>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub occurred_date_time: Option<String>,
+        pub recorded: String,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub reason: Vec<CodeableConcept>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub activity: Option<CodeableConcept>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub agent: Vec<ProvenanceAgent>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub entity: Vec<ProvenanceEntity>,
+    }
+
+    #[derive(Serialize, Deserialize, FromSql, Debug, PartialEq, Eq, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ProvenanceAgent {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none", rename = "type")]
+        pub agent_type: Option<CodeableConcept>,
+        pub who: Reference,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub on_behalf_of: Option<Reference>,
+    }
+
+    #[derive(Serialize, Deserialize, FromSql, Debug, PartialEq, Eq, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ProvenanceEntity {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub id: Option<String>,
+        pub role: ProvenanceEntityRole,
+        pub what: Reference,
+    }
+
     #[derive(Serialize, Deserialize, FromSql, Debug, PartialEq, Eq, Clone)]
     #[serde(rename_all = "camelCase")]
     pub struct Meta {
         #[serde(skip_serializing_if = "Option::is_none")]
         pub id: Option<String>,
+        /// Set by the server, counts the writes of the resource starting at 1.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub version_id: Option<String>,
+        /// Set by the server on every write.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub last_updated: Option<String>,
         #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
         pub extension: Vec<Extension>,
         #[serde(skip_serializing_if = "Option::is_none")]
@@ -276,6 +428,23 @@ pub mod model {
         pub preferred: Option<bool>,
     }
 
+    #[derive(Serialize, Deserialize, FromSql, Debug, PartialEq, Eq, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct EncounterParticipant {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub id: Option<String>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub extension: Vec<Extension>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub modifier_extension: Vec<Extension>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec", rename = "type")]
+        pub participant_type: Vec<CodeableConcept>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub period: Option<Period>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub individual: Option<Reference>,
+    }
+
     #[derive(Serialize, Deserialize, FromSql, Debug, PartialEq, Eq, Clone)]
     #[serde(rename_all = "camelCase")]
     pub struct Extension {
@@ -292,6 +461,8 @@ pub mod model {
         pub value_string: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub value_integer: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub value_code: Option<String>,
         // Skipping the rest, many are not implemented in the DB, and the pattern
         // would continue like this.
     }
@@ -378,6 +549,24 @@ pub mod model {
         pub link_type: LinkType,
     }
 
+    #[derive(Serialize, Deserialize, FromSql, Debug, PartialEq, Clone)]
+    pub struct Quantity {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub id: Option<String>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub extension: Vec<Extension>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub value: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub comparator: Option<QuantityComparator>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub unit: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub system: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub code: Option<String>,
+    }
+
     #[derive(Serialize, Deserialize, FromSql, Debug, PartialEq, Eq, Clone)]
     pub enum IdentifierUse {
         #[serde(rename = "USUAL")]
@@ -404,6 +593,20 @@ pub mod model {
         Seealso,
     }
 
+    #[derive(Serialize, Deserialize, FromSql, Debug, PartialEq, Eq, Clone)]
+    pub enum ProvenanceEntityRole {
+        #[serde(rename = "DERIVATION")]
+        Derivation,
+        #[serde(rename = "REVISION")]
+        Revision,
+        #[serde(rename = "QUOTATION")]
+        Quotation,
+        #[serde(rename = "SOURCE")]
+        Source,
+        #[serde(rename = "REMOVAL")]
+        Removal,
+    }
+
     #[derive(Serialize, Deserialize, FromSql, Debug, PartialEq, Eq, Clone)]
     pub enum NarrativeStatus {
         #[serde(rename = "GENERATED")]
@@ -506,6 +709,60 @@ pub mod model {
         Both,
     }
 
+    #[derive(Serialize, Deserialize, FromSql, Debug, PartialEq, Eq, Clone)]
+    pub enum ObservationStatus {
+        #[serde(rename = "REGISTERED")]
+        Registered,
+        #[serde(rename = "PRELIMINARY")]
+        Preliminary,
+        #[serde(rename = "FINAL")]
+        Final,
+        #[serde(rename = "AMENDED")]
+        Amended,
+        #[serde(rename = "CORRECTED")]
+        Corrected,
+        #[serde(rename = "CANCELLED")]
+        Cancelled,
+        #[serde(rename = "ENTERED-IN-ERROR")]
+        EnteredInError,
+        #[serde(rename = "UNKNOWN")]
+        Unknown,
+    }
+
+    #[derive(Serialize, Deserialize, FromSql, Debug, PartialEq, Eq, Clone)]
+    pub enum EncounterStatus {
+        #[serde(rename = "PLANNED")]
+        Planned,
+        #[serde(rename = "ARRIVED")]
+        Arrived,
+        #[serde(rename = "TRIAGED")]
+        Triaged,
+        #[serde(rename = "IN-PROGRESS")]
+        InProgress,
+        #[serde(rename = "ONLEAVE")]
+        Onleave,
+        #[serde(rename = "FINISHED")]
+        Finished,
+        #[serde(rename = "CANCELLED")]
+        Cancelled,
+        #[serde(rename = "ENTERED-IN-ERROR")]
+        EnteredInError,
+        #[serde(rename = "UNKNOWN")]
+        Unknown,
+    }
+
+    #[derive(Serialize, Deserialize, FromSql, Debug, PartialEq, Eq, Clone)]
+    pub enum QuantityComparator {
+        #[serde(rename = "<")]
+        LessThan,
+        #[serde(rename = "<=")]
+        LessOrEqual,
+        #[serde(rename = ">=")]
+        GreaterOrEqual,
+        #[serde(rename = ">")]
+        GreaterThan,
+    }
+
     #[derive(Serialize, Deserialize, Debug)]
     #[serde(rename_all = "camelCase")]
     pub struct PatientSearch {
@@ -527,7 +784,121 @@ pub mod model {
         pub last_id: Option<String>,
     }
 
-    #[derive(Serialize, Deserialize, FromSql, Debug)]
+    /// Token parameters (`code`, `category`) are either `code` or `system|code`.
+    /// `subject` is either `Type/id` or a bare ID of any type.
+    #[derive(Serialize, Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ObservationSearch {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub patient: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub subject: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub code: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub category: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub date: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub date_from: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub date_until: Option<String>,
+        #[serde(default = "default_operator")]
+        pub operator: SearchOperator,
+        #[serde(default = "default_count")]
+        pub count: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub iteration_key: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub last_id: Option<String>,
+    }
+
+    /// `date` matches encounters whose period overlaps the date.
+    /// `subject` is either `Type/id` or a bare ID of any type.
+    #[derive(Serialize, Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct EncounterSearch {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub patient: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub subject: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub status: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub date: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub date_from: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub date_until: Option<String>,
+        #[serde(default = "default_operator")]
+        pub operator: SearchOperator,
+        #[serde(default = "default_count")]
+        pub count: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub iteration_key: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub last_id: Option<String>,
+    }
+
+    /// `target` is either `Type/id` or a bare ID of any type.
+    #[derive(Serialize, Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ProvenanceSearch {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub target: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub recorded_from: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub recorded_until: Option<String>,
+        #[serde(default = "default_operator")]
+        pub operator: SearchOperator,
+        #[serde(default = "default_count")]
+        pub count: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub iteration_key: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub last_id: Option<String>,
+    }
+
+    /// Search of the audit trail. `patient` is a patient ID, `agent` the subject of a caller.
+    #[derive(Serialize, Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct AuditEventSearch {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub patient: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub agent: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub recorded_from: Option<DateTime<Utc>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub recorded_until: Option<DateTime<Utc>>,
+        #[serde(default = "default_count")]
+        pub count: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub iteration_key: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub last_id: Option<String>,
+    }
+
+    /// Parameters of the patient `$match` operation, the candidate patient is the body.
+    #[derive(Serialize, Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct PatientMatchParams {
+        #[serde(default = "default_match_count")]
+        pub count: u32,
+        #[serde(default)]
+        pub only_certain_matches: bool,
+    }
+
+    /// Body of the patient `$merge` operation. Both values are patient IDs.
+    #[derive(Serialize, Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct PatientMerge {
+        pub source_patient: String,
+        pub target_patient: String,
+    }
+
+    #[derive(Serialize, Deserialize, FromSql, Debug, Clone)]
     pub enum SearchOperator {
         #[serde(rename = "AND")]
         #[postgres(name = "AND")]
@@ -537,6 +908,130 @@ pub mod model {
         Or,
     }
 
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Bundle<R> {
+        pub resource_type: String,
+        #[serde(rename = "type")]
+        pub bundle_type: BundleType,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub total: Option<u32>,
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub link: Vec<BundleLink>,
+        #[serde(default = "default_vec")]
+        pub entry: Vec<BundleEntry<R>>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+    pub struct BundleLink {
+        pub relation: String,
+        pub url: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct BundleEntry<R> {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub full_url: Option<String>,
+        pub resource: R,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub search: Option<BundleEntrySearch>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    pub struct BundleEntrySearch {
+        #[serde(default = "default_vec", deserialize_with = "deserialize_vec")]
+        pub extension: Vec<Extension>,
+        pub mode: SearchEntryMode,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub score: Option<f64>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+    pub enum BundleType {
+        #[serde(rename = "SEARCHSET")]
+        Searchset,
+        #[serde(rename = "COLLECTION")]
+        Collection,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+    pub enum SearchEntryMode {
+        #[serde(rename = "MATCH")]
+        Match,
+        #[serde(rename = "INCLUDE")]
+        Include,
+        #[serde(rename = "OUTCOME")]
+        Outcome,
+    }
+
+    impl PatientStub {
+        /// Summarizes the patient, only using names that are valid right now.
+        pub fn new(patient: &Patient, iteration_key: String) -> Self {
+            return Self {
+                id: patient.id.clone().unwrap_or_default(),
+                name: patient.name
+                             .iter()
+                             .filter(|n| n.period.as_ref().is_none_or(Period::is_current))
+                             .filter_map(|n| n.text.clone())
+                             .collect(),
+                birthdate: patient.birth_date.clone(),
+                iteration_key,
+                gender: patient.gender.clone(),
+            };
+        }
+    }
+
+    impl Period {
+        /// Whether now lies within the period.
+        /// Boundaries that are not full dateTimes are treated as open.
+        pub fn is_current(&self) -> bool {
+            let now = Utc::now();
+            let parse = |s: &Option<String>| {
+                s.as_ref().and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+            };
+            return parse(&self.start).is_none_or(|start| start <= now)
+                && parse(&self.end).is_none_or(|end| end > now);
+        }
+    }
+
+    impl Gender {
+        /// The code as used in FHIR JSON and the search index.
+        pub fn code(&self) -> &'static str {
+            return match self {
+                Gender::Male => "MALE",
+                Gender::Female => "FEMALE",
+                Gender::Other => "OTHER",
+                Gender::Unknown => "UNKNOWN",
+            };
+        }
+    }
+
+    impl<R> Bundle<R> {
+        /// Search result bundle. `next` is the URL of the following page, if there is one.
+        pub fn searchset(resources: Vec<R>, next: Option<String>) -> Self {
+            return Self {
+                resource_type: "Bundle".to_string(),
+                bundle_type: BundleType::Searchset,
+                total: None,
+                link: next.into_iter()
+                          .map(|url| BundleLink { relation: "next".to_string(), url })
+                          .collect(),
+                entry: resources.into_iter()
+                                .map(|resource| BundleEntry {
+                                    full_url: None,
+                                    resource,
+                                    search: Some(BundleEntrySearch {
+                                        extension: Vec::new(),
+                                        mode: SearchEntryMode::Match,
+                                        score: None,
+                                    }),
+                                })
+                                .collect(),
+            };
+        }
+    }
+
     /// FromSql is not implemented for Box types, so we need to do it manually.
     /// All this one does is wrap the derived implementation of [Reference] into a Box.
     impl<'a> FromSql<'a> for Box<Reference> {
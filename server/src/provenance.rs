@@ -0,0 +1,192 @@
+pub mod provenance {
+    use crate::auth::auth::Identity;
+    use crate::model::model::{CodeableConcept, Coding, Provenance, ProvenanceAgent, Reference};
+    use chrono::{SecondsFormat, Utc};
+    use http::HeaderValue;
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    /// Header with the provenance of a write, as sent by clients.
+    /// See <https://hl7.org/fhir/R4/provenance.html#header>.
+    pub const X_PROVENANCE: &str = "x-provenance";
+
+    const PARTICIPANT_TYPE_SYSTEM: &str =
+        "http://terminology.hl7.org/CodeSystem/provenance-participant-type";
+    const DATA_OPERATION_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/v3-DataOperation";
+    const DICOM_SYSTEM: &str = "http://dicom.nema.org/resources/ontology/DCM";
+
+    /// Agents of a change made by the caller, with the participant type of the caller.
+    /// If the caller uses a client of its own, the client is an agent, too.
+    pub fn agents(identity: &Identity, participant_type: &str) -> Vec<ProvenanceAgent> {
+        let mut agents = vec![ProvenanceAgent {
+            id: None,
+            agent_type: Some(codeable_concept(PARTICIPANT_TYPE_SYSTEM, participant_type, None)),
+            who: display_reference(&identity.subject),
+            on_behalf_of: None,
+        }];
+        if let Some(client_id) = identity.client_id.as_ref().filter(|c| **c != identity.subject) {
+            agents.push(ProvenanceAgent {
+                id: None,
+                agent_type: Some(codeable_concept(DICOM_SYSTEM, "110150", Some("Application"))),
+                who: display_reference(client_id),
+                on_behalf_of: None,
+            });
+        }
+        return agents;
+    }
+
+    /// Provenance of a write by the caller, taken from the `X-Provenance` header if the client
+    /// sent one. The client may describe the activity, reasons, entities and its own agents,
+    /// the caller is added as enterer then, otherwise as author. The server sets ID, `recorded`
+    /// and, once the version written is known, the target, see [for_version].
+    /// Returns the reason if the header isn't a valid Provenance.
+    pub fn write_provenance(header: Option<&HeaderValue>,
+                            identity: &Identity,
+    ) -> Result<Provenance, String> {
+        let mut value = match header {
+            None => json!({}),
+            Some(header) => serde_json::from_slice::<Value>(header.as_bytes())
+                .map_err(|e| format!("Invalid X-Provenance: {}", e))?,
+        };
+        let Some(object) = value.as_object_mut() else {
+            return Err("Invalid X-Provenance: not a JSON object".to_string());
+        };
+        if object.remove("resourceType").is_some_and(|t| t != "Provenance") {
+            return Err("Invalid X-Provenance: not a Provenance".to_string());
+        }
+        object.remove("id");
+        object.remove("target");
+        object.insert("recorded".to_string(),
+                      json!(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)));
+        let mut provenance: Provenance = serde_json::from_value(value)
+            .map_err(|e| format!("Invalid X-Provenance: {}", e))?;
+        let participant_type = if provenance.agent.is_empty() { "author" } else { "enterer" };
+        provenance.agent.extend(agents(identity, participant_type));
+        return Ok(provenance);
+    }
+
+    /// Completes the provenance of a write with its target, the version of the resource
+    /// written. Without an activity of the client, it is a create or an update.
+    pub fn for_version(mut provenance: Provenance,
+                       resource_type: &str,
+                       id: Uuid,
+                       version: &str,
+    ) -> Provenance {
+        provenance.target = vec![Reference {
+            id: None,
+            extension: Vec::new(),
+            reference: Some(format!("{}/{}/_history/{}", resource_type, id, version)),
+            ref_type: Some(resource_type.to_string()),
+            identifier: None,
+            display: None,
+        }];
+        if provenance.activity.is_none() {
+            let (code, display) = match version {
+                "1" => ("CREATE", "create"),
+                _ => ("UPDATE", "revise"),
+            };
+            provenance.activity = Some(codeable_concept(DATA_OPERATION_SYSTEM, code, Some(display)));
+        }
+        return provenance;
+    }
+
+    fn display_reference(display: &str) -> Reference {
+        return Reference {
+            id: None,
+            extension: Vec::new(),
+            reference: None,
+            ref_type: None,
+            identifier: None,
+            display: Some(display.to_string()),
+        };
+    }
+
+    fn codeable_concept(system: &str, code: &str, display: Option<&str>) -> CodeableConcept {
+        return CodeableConcept {
+            id: None,
+            extension: Vec::new(),
+            coding: vec![Coding {
+                id: None,
+                extension: Vec::new(),
+                system: Some(system.to_string()),
+                version: None,
+                code: Some(code.to_string()),
+                display: display.map(str::to_string),
+                user_selected: None,
+            }],
+            text: None,
+        };
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use speculoos::assert_that;
+        use speculoos::prelude::{ResultAssertions, VecAssertions};
+
+        fn identity(client_id: Option<&str>) -> Identity {
+            return Identity {
+                subject: "alice".to_string(),
+                client_id: client_id.map(str::to_string),
+                scopes: Vec::new(),
+                patient: None,
+                roles: Vec::new(),
+            };
+        }
+
+        fn code(concept: &Option<CodeableConcept>) -> Option<String> {
+            return concept.as_ref().and_then(|c| c.coding[0].code.clone());
+        }
+
+        #[test]
+        fn test_write_provenance() {
+            let provenance = write_provenance(None, &identity(None)).unwrap();
+            assert_that!(provenance.agent).has_length(1);
+            assert_that!(provenance.agent[0].who.display.clone()).is_equal_to(Some("alice".to_string()));
+            assert_that!(code(&provenance.agent[0].agent_type)).is_equal_to(Some("author".to_string()));
+
+            let id = Uuid::nil();
+            let created = for_version(provenance.clone(), "Patient", id, "1");
+            assert_that!(created.target[0].reference.clone())
+                .is_equal_to(Some(format!("Patient/{}/_history/1", id)));
+            assert_that!(code(&created.activity)).is_equal_to(Some("CREATE".to_string()));
+            let updated = for_version(provenance, "Patient", id, "2");
+            assert_that!(code(&updated.activity)).is_equal_to(Some("UPDATE".to_string()));
+        }
+
+        #[test]
+        fn test_x_provenance_header() {
+            let header = json!({
+                "resourceType": "Provenance",
+                "id": "x",
+                "target": [{"reference": "Patient/other"}],
+                "recorded": "2000-01-01T00:00:00Z",
+                "reason": [{"text": "Correction after phone call"}],
+                "activity": {"coding": [{"code": "UPDATE"}]},
+                "agent": [{"who": {"display": "Dr. Meier"}}],
+            });
+            let header = HeaderValue::from_str(&header.to_string()).unwrap();
+            let provenance = write_provenance(Some(&header), &identity(Some("gui"))).unwrap();
+            assert_that!(provenance.id).is_equal_to(None);
+            assert_that!(provenance.target).is_empty();
+            assert_that!(provenance.recorded.starts_with("2000")).is_equal_to(false);
+            assert_that!(provenance.reason[0].text.clone())
+                .is_equal_to(Some("Correction after phone call".to_string()));
+            let agents: Vec<Option<String>> = provenance.agent
+                                                        .iter()
+                                                        .map(|a| a.who.display.clone())
+                                                        .collect();
+            assert_that!(agents).is_equal_to(vec![Some("Dr. Meier".to_string()),
+                                                  Some("alice".to_string()),
+                                                  Some("gui".to_string())]);
+            assert_that!(code(&provenance.agent[1].agent_type)).is_equal_to(Some("enterer".to_string()));
+            let completed = for_version(provenance, "Patient", Uuid::nil(), "1");
+            assert_that!(code(&completed.activity)).is_equal_to(Some("UPDATE".to_string()));
+
+            for invalid in [r#"{"resourceType": "Patient"}"#, "[]", "{", r#"{"agent": 1}"#] {
+                let header = HeaderValue::from_static(invalid);
+                assert_that!(write_provenance(Some(&header), &identity(None))).is_err();
+            }
+        }
+    }
+}
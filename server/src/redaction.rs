@@ -0,0 +1,403 @@
+pub mod redaction {
+    use crate::api::api::route_interaction;
+    use crate::auth::auth::Identity;
+    use crate::smart::smart::{Access, Interaction};
+    use axum::body::to_bytes;
+    use axum::extract::{MatchedPath, State};
+    use axum::middleware::Next;
+    use axum_core::body::Body;
+    use axum_core::extract::Request;
+    use axum_core::response::Response;
+    use http::header::{CONTENT_LENGTH, CONTENT_TYPE, ETAG};
+    use http::StatusCode;
+    use serde_json::{json, Value};
+    use std::env;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use tracing::{error, info};
+
+    /// What masked strings are replaced with.
+    const MASK: &str = "***";
+    /// Security label of resources that had elements removed or masked.
+    const REDACTED_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/v3-ObservationValue";
+    const REDACTED_CODE: &str = "REDACTED";
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum RedactionAction {
+        /// The element is left out.
+        Remove,
+        /// All strings within the element are replaced with [MASK], its structure stays.
+        Mask,
+    }
+
+    /// Removes or masks an element of a resource type, e.g. `Patient.address.line=mask`.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct RedactionRule {
+        resource_type: String,
+        /// Names of the elements from the resource down, arrays are descended into.
+        path: Vec<String>,
+        action: RedactionAction,
+    }
+
+    impl FromStr for RedactionRule {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let (path, action) = s.trim().split_once('=').ok_or(format!("No action in {}", s))?;
+            let action = match action.trim() {
+                "remove" => RedactionAction::Remove,
+                "mask" => RedactionAction::Mask,
+                a => return Err(format!("Unknown action {}", a)),
+            };
+            let mut elements = path.trim().split('.').map(str::to_string);
+            let resource_type = elements.next().unwrap_or_default();
+            let path: Vec<String> = elements.collect();
+            if !resource_type.starts_with(|c: char| c.is_ascii_uppercase()) || path.is_empty() {
+                return Err(format!("Invalid path {}", s));
+            }
+            if path.iter().any(|e| e.is_empty() || !e.chars().all(|c| c.is_ascii_alphanumeric())) {
+                return Err(format!("Invalid path {}", s));
+            }
+            return Ok(Self { resource_type, path, action });
+        }
+    }
+
+    impl RedactionRule {
+        /// Redacts the element in the resource, true if there was anything to redact.
+        fn apply(&self, resource: &mut Value) -> bool {
+            return redact(resource, &self.path, self.action);
+        }
+
+        /// Patient searches return stubs with the names as text, the birthdate and the gender.
+        /// Rules on these elements redact the stubs, too, rules on parts of them mask the whole
+        /// field.
+        fn apply_to_stub(&self, stub: &mut Value) -> bool {
+            let field = match self.path[0].as_str() {
+                "name" => "name",
+                "birthDate" => "birthdate",
+                "gender" => "gender",
+                _ => return false,
+            };
+            let action = match self.path.len() {
+                1 => self.action,
+                _ => RedactionAction::Mask,
+            };
+            return redact(stub, &[field.to_string()], action);
+        }
+    }
+
+    fn redact(value: &mut Value, path: &[String], action: RedactionAction) -> bool {
+        let Some((element, rest)) = path.split_first() else {
+            return false;
+        };
+        return match value {
+            Value::Array(items) => items.iter_mut()
+                                        .map(|item| redact(item, path, action))
+                                        .fold(false, |a, b| a | b),
+            Value::Object(object) if rest.is_empty() => match action {
+                RedactionAction::Remove => object.remove(element).is_some(),
+                RedactionAction::Mask => object.get_mut(element).is_some_and(mask),
+            },
+            Value::Object(object) => {
+                object.get_mut(element).is_some_and(|child| redact(child, rest, action))
+            }
+            _ => false,
+        };
+    }
+
+    fn mask(value: &mut Value) -> bool {
+        return match value {
+            Value::String(s) => {
+                *s = MASK.to_string();
+                true
+            }
+            Value::Array(items) => items.iter_mut().map(mask).fold(false, |a, b| a | b),
+            Value::Object(object) => object.values_mut().map(mask).fold(false, |a, b| a | b),
+            _ => false,
+        };
+    }
+
+    /// Strips or masks elements of read and search responses for callers who may only read a
+    /// resource type. Callers whose scopes grant updates of it see everything.
+    #[derive(Clone, Debug, Default)]
+    pub struct Redaction {
+        rules: Arc<Vec<RedactionRule>>,
+    }
+
+    impl Redaction {
+        pub fn new(rules: Vec<RedactionRule>) -> Self {
+            return Self { rules: Arc::new(rules) };
+        }
+
+        /// Rules from `FHIR_REDACTION`, comma separated, e.g.
+        /// `Patient.photo=remove,Patient.identifier.value=mask`. Nothing is redacted without.
+        pub fn from_env() -> Self {
+            let Some(val) = env::var_os("FHIR_REDACTION") else {
+                return Redaction::default();
+            };
+            let rules = val.into_string()
+                           .unwrap()
+                           .split(',')
+                           .filter(|r| !r.trim().is_empty())
+                           .map(RedactionRule::from_str)
+                           .collect::<Result<Vec<_>, _>>();
+            return match rules {
+                Ok(rules) => {
+                    info!(rules = rules.len(), "Redacting responses for read-only callers");
+                    Redaction::new(rules)
+                }
+                Err(e) => {
+                    error!(?e, "Invalid FHIR_REDACTION");
+                    panic!("Invalid FHIR_REDACTION");
+                }
+            };
+        }
+
+        /// The rules for the caller on the resource type, none if it may update the type.
+        fn rules_for(&self, identity: &Identity, resource_type: &str) -> Vec<&RedactionRule> {
+            let rules: Vec<&RedactionRule> = self.rules
+                                                 .iter()
+                                                 .filter(|r| r.resource_type == resource_type)
+                                                 .collect();
+            let may_update = Access::granted(&identity.scopes,
+                                             identity.patient.as_deref(),
+                                             resource_type,
+                                             Interaction::Update).is_some();
+            return if may_update { Vec::new() } else { rules };
+        }
+
+        /// Redacts the resource and labels it as redacted if anything was.
+        fn redact_resource(rules: &[&RedactionRule], resource: &mut Value) -> bool {
+            let redacted = rules.iter().map(|r| r.apply(resource)).fold(false, |a, b| a | b);
+            if redacted && resource.is_object() {
+                let meta = resource.as_object_mut()
+                                   .unwrap()
+                                   .entry("meta")
+                                   .or_insert_with(|| json!({}));
+                if let Some(meta) = meta.as_object_mut() {
+                    let security = meta.entry("security").or_insert_with(|| json!([]));
+                    if let Some(security) = security.as_array_mut() {
+                        security.push(json!({"system": REDACTED_SYSTEM, "code": REDACTED_CODE}));
+                    }
+                }
+            }
+            return redacted;
+        }
+
+        /// Redacts the body of a read (the resource) or search (a Bundle or patient stubs).
+        fn redact_body(rules: &[&RedactionRule], interaction: Interaction, body: &mut Value) -> bool {
+            if interaction != Interaction::Search {
+                return Redaction::redact_resource(rules, body);
+            }
+            return match body {
+                Value::Array(stubs) => stubs.iter_mut()
+                                            .map(|stub| rules.iter()
+                                                             .map(|r| r.apply_to_stub(stub))
+                                                             .fold(false, |a, b| a | b))
+                                            .fold(false, |a, b| a | b),
+                Value::Object(bundle) => match bundle.get_mut("entry").and_then(Value::as_array_mut) {
+                    Some(entries) => entries.iter_mut()
+                                            .filter_map(|e| e.get_mut("resource"))
+                                            .map(|r| Redaction::redact_resource(rules, r))
+                                            .fold(false, |a, b| a | b),
+                    None => false,
+                },
+                _ => false,
+            };
+        }
+
+        /// Redacts successful read and search responses. Runs after authentication and wraps the
+        /// cache, which only ever holds unredacted responses.
+        pub async fn redaction_middleware(State(redaction): State<Redaction>,
+                                          req: Request<Body>,
+                                          next: Next) -> Response<Body> {
+            let Some(path) = req.extensions().get::<MatchedPath>() else {
+                return next.run(req).await;
+            };
+            let Some((resource_type, interaction)) = route_interaction(req.method(), path.as_str())
+            else {
+                return next.run(req).await;
+            };
+            if !matches!(interaction, Interaction::Read | Interaction::Search) {
+                return next.run(req).await;
+            }
+            let Some(identity) = req.extensions().get::<Identity>() else {
+                return next.run(req).await;
+            };
+            let rules: Vec<RedactionRule> = redaction.rules_for(identity, resource_type)
+                                                     .into_iter()
+                                                     .cloned()
+                                                     .collect();
+            if rules.is_empty() {
+                return next.run(req).await;
+            }
+
+            let response = next.run(req).await;
+            if !response.status().is_success() {
+                return response;
+            }
+            // exports like $anonymize are streamed and already leave out what they have to
+            let json = response.headers()
+                               .get(CONTENT_TYPE)
+                               .and_then(|ct| ct.to_str().ok())
+                               .is_some_and(|ct| ct.contains("json") && !ct.contains("ndjson"));
+            if !json {
+                return response;
+            }
+            let (mut parts, body) = response.into_parts();
+            let bytes = match to_bytes(body, usize::MAX).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    error!(?e, "Could not read the response to redact");
+                    return Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR)
+                                              .body(Body::empty())
+                                              .unwrap();
+                }
+            };
+            let Ok(mut value) = serde_json::from_slice::<Value>(&bytes) else {
+                return Response::from_parts(parts, Body::from(bytes));
+            };
+            let rules: Vec<&RedactionRule> = rules.iter().collect();
+            if !Redaction::redact_body(&rules, interaction, &mut value) {
+                return Response::from_parts(parts, Body::from(bytes));
+            }
+            // both describe the unredacted body
+            parts.headers.remove(CONTENT_LENGTH);
+            parts.headers.remove(ETAG);
+            return Response::from_parts(parts, Body::from(serde_json::to_vec(&value).unwrap()));
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::api::api::GET_PATIENT_PATH;
+        use axum::middleware::{from_fn, from_fn_with_state};
+        use axum::routing::get;
+        use axum::{Json, Router};
+        use speculoos::assert_that;
+        use speculoos::prelude::ResultAssertions;
+        use tower::ServiceExt;
+
+        fn rules(rules: &[&str]) -> Vec<RedactionRule> {
+            return rules.iter().map(|r| r.parse().unwrap()).collect();
+        }
+
+        fn identity(scopes: &[&str]) -> Identity {
+            return Identity {
+                subject: "alice".to_string(),
+                client_id: None,
+                scopes: scopes.iter().map(|s| s.to_string()).collect(),
+                patient: None,
+                roles: Vec::new(),
+            };
+        }
+
+        fn patient() -> Value {
+            return json!({
+                "id": "p1",
+                "name": [{"family": "Meier", "given": ["Anna"]}],
+                "identifier": [
+                    {"system": "urn:kvnr", "value": "A123456789"},
+                    {"system": "urn:mrn", "value": "4711"},
+                ],
+                "address": {"line": ["Hauptstr. 1"], "city": "Berlin"},
+                "photo": [{"contentType": "image/png", "data": "iVBORw0KGgo="}],
+            });
+        }
+
+        #[test]
+        fn test_parse_rules() {
+            assert_that!(RedactionRule::from_str("Patient.address.line=mask"))
+                .is_ok()
+                .is_equal_to(RedactionRule {
+                    resource_type: "Patient".to_string(),
+                    path: vec!["address".to_string(), "line".to_string()],
+                    action: RedactionAction::Mask,
+                });
+            assert_that!(RedactionRule::from_str("Patient.photo")).is_err();
+            assert_that!(RedactionRule::from_str("Patient=remove")).is_err();
+            assert_that!(RedactionRule::from_str("patient.photo=remove")).is_err();
+            assert_that!(RedactionRule::from_str("Patient.photo=hide")).is_err();
+            assert_that!(RedactionRule::from_str("Patient..photo=remove")).is_err();
+        }
+
+        #[test]
+        fn test_redact() {
+            let rules = rules(&["Patient.photo=remove",
+                "Patient.address.line=mask",
+                "Patient.identifier.value=mask",
+                "Patient.telecom=remove"]);
+            let rules: Vec<&RedactionRule> = rules.iter().collect();
+            let mut resource = patient();
+            assert_that!(Redaction::redact_resource(&rules, &mut resource)).is_equal_to(true);
+            assert_that!(resource).is_equal_to(json!({
+                "id": "p1",
+                "name": [{"family": "Meier", "given": ["Anna"]}],
+                "identifier": [
+                    {"system": "urn:kvnr", "value": "***"},
+                    {"system": "urn:mrn", "value": "***"},
+                ],
+                "address": {"line": ["***"], "city": "Berlin"},
+                "meta": {"security": [{"system": REDACTED_SYSTEM, "code": "REDACTED"}]},
+            }));
+
+            // nothing to redact, so it isn't labeled
+            let mut bare = json!({"id": "p2", "gender": "female"});
+            assert_that!(Redaction::redact_resource(&rules, &mut bare)).is_equal_to(false);
+            assert_that!(bare).is_equal_to(json!({"id": "p2", "gender": "female"}));
+
+            let mut bundle = json!({"resourceType": "Bundle", "entry": [{"resource": patient()}]});
+            assert_that!(Redaction::redact_body(&rules, Interaction::Search, &mut bundle))
+                .is_equal_to(true);
+            assert_that!(bundle["entry"][0]["resource"]["photo"]).is_equal_to(Value::Null);
+
+            let name = RedactionRule::from_str("Patient.name.family=remove").unwrap();
+            let birth_date = RedactionRule::from_str("Patient.birthDate=remove").unwrap();
+            let mut stubs = json!([{"id": "p1", "name": ["Anna Meier"], "birthdate": "1980-01-01"}]);
+            assert_that!(Redaction::redact_body(&[&name, &birth_date], Interaction::Search, &mut stubs))
+                .is_equal_to(true);
+            assert_that!(stubs).is_equal_to(json!([{"id": "p1", "name": ["***"]}]));
+        }
+
+        #[test]
+        fn test_rules_for() {
+            let redaction = Redaction::new(rules(&["Patient.photo=remove", "Observation.note=remove"]));
+            assert_that!(redaction.rules_for(&identity(&["system/*.rs"]), "Patient").len())
+                .is_equal_to(1);
+            assert_that!(redaction.rules_for(&identity(&["system/*.cruds"]), "Patient").len())
+                .is_equal_to(0);
+            assert_that!(redaction.rules_for(&identity(&["system/Observation.cruds"]), "Patient").len())
+                .is_equal_to(1);
+            assert_that!(redaction.rules_for(&identity(&["system/*.rs"]), "Encounter").len())
+                .is_equal_to(0);
+        }
+
+        #[tokio::test]
+        async fn test_redaction_middleware() {
+            let redaction = Redaction::new(rules(&["Patient.photo=remove"]));
+            let app = Router::new()
+                .route(GET_PATIENT_PATH, get(async || Json(patient())))
+                .layer(from_fn_with_state(redaction, Redaction::redaction_middleware))
+                .layer(from_fn(async |mut req: Request<Body>, next: Next| {
+                    let scopes = req.headers()["scope"].to_str().unwrap().to_string();
+                    req.extensions_mut().insert(identity(&[&scopes]));
+                    return next.run(req).await;
+                }));
+            let get_patient = async |scope: &str| {
+                let request = Request::get("/fhir/patient/p1").header("scope", scope)
+                                                             .body(Body::empty())
+                                                             .unwrap();
+                let response = app.clone().oneshot(request).await.unwrap();
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                return serde_json::from_slice::<Value>(&body).unwrap();
+            };
+
+            let read = get_patient("system/*.rs").await;
+            assert_that!(read["photo"]).is_equal_to(Value::Null);
+            assert_that!(read["meta"]["security"][0]["code"]).is_equal_to(json!("REDACTED"));
+            let write = get_patient("system/*.cruds").await;
+            assert_that!(write).is_equal_to(patient());
+        }
+    }
+}
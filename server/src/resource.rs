@@ -0,0 +1,706 @@
+pub mod resource {
+    use crate::consent::consent::SecurityLabel;
+    use crate::model::model::{
+        CodeableConcept,
+        Encounter,
+        EncounterSearch,
+        Meta,
+        Observation,
+        ObservationSearch,
+        Patient,
+        PatientSearch,
+        Period,
+        Provenance,
+        ProvenanceSearch,
+        Reference,
+        SearchOperator,
+    };
+    use chrono::DateTime;
+    use serde::de::DeserializeOwned;
+    use serde::{Deserialize, Serialize};
+
+    /// A FHIR resource that can be stored generically in `fhir.resource`.
+    /// Implementations only declare their resource type and which values are searchable,
+    /// everything else is handled by [Db](crate::db::db::Db).
+    pub trait StoredResource: Serialize + DeserializeOwned + Send + Sync {
+        /// The FHIR resource type, e.g. `Patient`. Used as part of the primary key.
+        const RESOURCE_TYPE: &'static str;
+
+        /// Search parameter of the reference that puts the resource into the compartment of a
+        /// patient. None for patients, which make up their own compartment.
+        const PATIENT_PARAM: Option<&'static str>;
+
+        /// Returns the values under which the resource can be found.
+        /// References are indexed separately, see [StoredResource::references].
+        fn search_index(&self) -> Vec<SearchIndex>;
+
+        /// Returns all references of the resource, together with the search parameter they are
+        /// indexed under. These are used for search and to keep references between resources
+        /// hosted on this server intact.
+        fn references(&self) -> Vec<(&'static str, &Reference)>;
+
+        /// Returns the meta of the resource, its security labels are indexed as `_security`.
+        fn meta(&self) -> Option<&Meta>;
+    }
+
+    /// Index entries of the security labels in the meta.
+    pub fn security_index(meta: Option<&Meta>) -> Vec<SearchIndex> {
+        return meta.iter()
+                   .flat_map(|m| m.security.iter())
+                   .filter_map(|coding| coding.code.as_ref().map(|code| SearchIndex::Token {
+                       param: "_security",
+                       system: coding.system.clone(),
+                       code: code.clone(),
+                   }))
+                   .collect();
+    }
+
+    /// The `id` of the resource, if it has one.
+    pub fn resource_id<R: Serialize>(resource: &R) -> Option<String> {
+        return serde_json::to_value(resource).ok()?.get("id")?.as_str().map(str::to_string);
+    }
+
+    /// Criterion that only matches resources of the type in the compartment of the patient.
+    pub fn compartment_criterion<R: StoredResource>(patient_id: &str) -> SearchCriterion {
+        return match R::PATIENT_PARAM {
+            Some(param) => SearchCriterion::Reference {
+                param,
+                target_type: Some(Patient::RESOURCE_TYPE.to_string()),
+                target_id: patient_id.to_string(),
+            },
+            None => SearchCriterion::Id { id: patient_id.to_string() },
+        };
+    }
+
+    /// Whether the resource with the ID is in the compartment of the patient.
+    pub fn in_compartment<R: StoredResource>(id: Option<&str>,
+                                             resource: &R,
+                                             patient_id: &str) -> bool {
+        return patient_ids(id, resource).iter().any(|p| p == patient_id);
+    }
+
+    /// IDs of the patients in whose compartment the resource with the ID is.
+    pub fn patient_ids<R: StoredResource>(id: Option<&str>, resource: &R) -> Vec<String> {
+        return match R::PATIENT_PARAM {
+            Some(param) => resource.references()
+                                   .into_iter()
+                                   .filter(|(p, _)| *p == param)
+                                   .filter_map(|(_, r)| reference_target(r))
+                                   .filter(|(target_type, _)| *target_type == Patient::RESOURCE_TYPE)
+                                   .map(|(_, target_id)| target_id.to_string())
+                                   .collect(),
+            None => id.map(str::to_string).into_iter().collect(),
+        };
+    }
+
+    /// Resource types stored on this server. References to other types are not checked.
+    pub const HOSTED_RESOURCE_TYPES: [&str; 4] = [
+        Patient::RESOURCE_TYPE,
+        Observation::RESOURCE_TYPE,
+        Encounter::RESOURCE_TYPE,
+        Provenance::RESOURCE_TYPE,
+    ];
+
+    /// Splits a relative reference `Type/id` into type and ID. References to a version,
+    /// `Type/id/_history/vid`, are references to the resource.
+    pub fn reference_target(reference: &Reference) -> Option<(&str, &str)> {
+        let reference = reference.reference.as_deref()?;
+        let reference = reference.split_once("/_history/").map_or(reference, |(r, _)| r);
+        let (target_type, target_id) = reference.split_once('/')?;
+        if target_type.is_empty() || target_id.is_empty() || target_id.contains('/') {
+            return None;
+        }
+        return Some((target_type, target_id));
+    }
+
+    /// A single searchable value of a resource.
+    /// Each kind is stored in its own index table (`fhir.search_<kind>`).
+    #[derive(Serialize, Debug, PartialEq, Eq, Clone)]
+    #[serde(tag = "kind", rename_all = "camelCase", rename_all_fields = "camelCase")]
+    pub enum SearchIndex {
+        /// Free text, matched by substring. Only matches while the period is valid.
+        String {
+            param: &'static str,
+            value: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            period_start: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            period_end: Option<String>,
+        },
+        /// A code, optionally qualified by a system.
+        Token {
+            param: &'static str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            system: Option<String>,
+            code: String,
+        },
+        /// A range of partial dates (`YYYY`, `YYYY-MM`, `YYYY-MM-DD`) or dateTimes.
+        /// A missing boundary means the range is open on that side.
+        Date {
+            param: &'static str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            start: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            end: Option<String>,
+        },
+        /// A local reference in the form `{target_type}/{target_id}`.
+        Reference {
+            param: &'static str,
+            target_type: String,
+            target_id: String,
+        },
+    }
+
+    impl SearchIndex {
+        /// String index entry that is only valid during the period.
+        /// Period boundaries that are not full dateTimes are ignored.
+        pub fn string(param: &'static str, value: String, period: Option<&Period>) -> Self {
+            let parse = |s: &Option<String>| {
+                s.as_ref()
+                 .filter(|s| DateTime::parse_from_rfc3339(s).is_ok())
+                 .cloned()
+            };
+            return SearchIndex::String {
+                param,
+                value,
+                period_start: period.and_then(|p| parse(&p.start)),
+                period_end: period.and_then(|p| parse(&p.end)),
+            };
+        }
+
+        /// Date index entry for a single point in time.
+        pub fn date(param: &'static str, value: &str) -> Self {
+            return SearchIndex::Date {
+                param,
+                start: Some(value.to_string()),
+                end: Some(value.to_string()),
+            };
+        }
+
+        /// Date index entry covering the period.
+        pub fn period(param: &'static str, period: &Period) -> Self {
+            return SearchIndex::Date {
+                param,
+                start: period.start.clone(),
+                end: period.end.clone(),
+            };
+        }
+
+        /// Reference index entry, if the reference points to a resource in the form `Type/id`.
+        pub fn reference(param: &'static str, reference: &Reference) -> Option<Self> {
+            let (target_type, target_id) = reference_target(reference)?;
+            return Some(SearchIndex::Reference {
+                param,
+                target_type: target_type.to_string(),
+                target_id: target_id.to_string(),
+            });
+        }
+    }
+
+    /// A single search parameter, evaluated against the [SearchIndex] entries of a resource.
+    #[derive(Serialize, Debug, PartialEq, Eq, Clone)]
+    #[serde(tag = "kind", rename_all = "camelCase", rename_all_fields = "camelCase")]
+    pub enum SearchCriterion {
+        String {
+            param: &'static str,
+            value: String,
+        },
+        Token {
+            param: &'static str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            system: Option<String>,
+            code: String,
+        },
+        /// Matches indexed ranges overlapping this one. Both bounds are inclusive.
+        Date {
+            param: &'static str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            from: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            until: Option<String>,
+        },
+        /// Without a target type, references to resources of any type match.
+        Reference {
+            param: &'static str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            target_type: Option<String>,
+            target_id: String,
+        },
+        /// Matches the resource with the ID.
+        Id {
+            id: String,
+        },
+    }
+
+    impl SearchCriterion {
+        /// Token criterion from the search syntax `code` or `system|code`.
+        pub fn token(param: &'static str, value: &str) -> Self {
+            return match value.split_once('|') {
+                Some((system, code)) => SearchCriterion::Token {
+                    param,
+                    system: Some(system.to_string()).filter(|s| !s.is_empty()),
+                    code: code.to_string(),
+                },
+                None => SearchCriterion::Token { param, system: None, code: value.to_string() },
+            };
+        }
+
+        /// Reference criterion from the search syntax `Type/id` or `id`.
+        pub fn reference(param: &'static str, value: &str) -> Self {
+            return match value.split_once('/') {
+                Some((target_type, target_id)) => SearchCriterion::Reference {
+                    param,
+                    target_type: Some(target_type.to_string()),
+                    target_id: target_id.to_string(),
+                },
+                None => SearchCriterion::Reference {
+                    param,
+                    target_type: None,
+                    target_id: value.to_string(),
+                },
+            };
+        }
+    }
+
+    /// Search over one resource type, paginated by creation time and ID.
+    #[derive(Serialize, Debug, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ResourceSearch {
+        pub criteria: Vec<SearchCriterion>,
+        pub operator: SearchOperator,
+        /// Restricts the results regardless of the operator, see [compartment_criterion].
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub compartment: Option<SearchCriterion>,
+        /// Leaves out resources with one of the labels, and those of patients with one.
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub hidden: Vec<SecurityLabel>,
+        pub count: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub iteration_key: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub last_id: Option<String>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase", bound = "R: DeserializeOwned")]
+    pub struct SearchHit<R> {
+        pub id: String,
+        pub resource: R,
+        pub iteration_key: String,
+    }
+
+    impl From<PatientSearch> for ResourceSearch {
+        fn from(search: PatientSearch) -> Self {
+            let mut criteria = Vec::new();
+            if let Some(name) = search.name {
+                criteria.push(SearchCriterion::String { param: "name", value: name });
+            }
+            if search.birthdate_from.is_some() || search.birthdate_until.is_some() {
+                criteria.push(SearchCriterion::Date {
+                    param: "birthdate",
+                    from: search.birthdate_from,
+                    until: search.birthdate_until,
+                });
+            }
+            if let Some(gender) = search.gender {
+                criteria.push(SearchCriterion::Token {
+                    param: "gender",
+                    system: None,
+                    code: gender.code().to_string(),
+                });
+            }
+            return Self {
+                criteria,
+                operator: search.operator,
+                compartment: None,
+                hidden: Vec::new(),
+                count: search.count,
+                iteration_key: search.iteration_key,
+                last_id: search.last_id,
+            };
+        }
+    }
+
+    impl From<ObservationSearch> for ResourceSearch {
+        fn from(search: ObservationSearch) -> Self {
+            let mut criteria = Vec::new();
+            if let Some(patient) = search.patient {
+                criteria.push(SearchCriterion::Reference {
+                    param: "subject",
+                    target_type: Some(Patient::RESOURCE_TYPE.to_string()),
+                    target_id: patient,
+                });
+            }
+            if let Some(subject) = search.subject {
+                criteria.push(SearchCriterion::reference("subject", &subject));
+            }
+            if let Some(code) = search.code {
+                criteria.push(SearchCriterion::token("code", &code));
+            }
+            if let Some(category) = search.category {
+                criteria.push(SearchCriterion::token("category", &category));
+            }
+            if let Some(date) = search.date {
+                criteria.push(SearchCriterion::Date {
+                    param: "date",
+                    from: Some(date.clone()),
+                    until: Some(date),
+                });
+            }
+            if search.date_from.is_some() || search.date_until.is_some() {
+                criteria.push(SearchCriterion::Date {
+                    param: "date",
+                    from: search.date_from,
+                    until: search.date_until,
+                });
+            }
+            return Self {
+                criteria,
+                operator: search.operator,
+                compartment: None,
+                hidden: Vec::new(),
+                count: search.count,
+                iteration_key: search.iteration_key,
+                last_id: search.last_id,
+            };
+        }
+    }
+
+    impl From<EncounterSearch> for ResourceSearch {
+        fn from(search: EncounterSearch) -> Self {
+            let mut criteria = Vec::new();
+            if let Some(patient) = search.patient {
+                criteria.push(SearchCriterion::Reference {
+                    param: "subject",
+                    target_type: Some(Patient::RESOURCE_TYPE.to_string()),
+                    target_id: patient,
+                });
+            }
+            if let Some(subject) = search.subject {
+                criteria.push(SearchCriterion::reference("subject", &subject));
+            }
+            if let Some(status) = search.status {
+                criteria.push(SearchCriterion::token("status", &status));
+            }
+            if let Some(date) = search.date {
+                criteria.push(SearchCriterion::Date {
+                    param: "date",
+                    from: Some(date.clone()),
+                    until: Some(date),
+                });
+            }
+            if search.date_from.is_some() || search.date_until.is_some() {
+                criteria.push(SearchCriterion::Date {
+                    param: "date",
+                    from: search.date_from,
+                    until: search.date_until,
+                });
+            }
+            return Self {
+                criteria,
+                operator: search.operator,
+                compartment: None,
+                hidden: Vec::new(),
+                count: search.count,
+                iteration_key: search.iteration_key,
+                last_id: search.last_id,
+            };
+        }
+    }
+
+    impl From<ProvenanceSearch> for ResourceSearch {
+        fn from(search: ProvenanceSearch) -> Self {
+            let mut criteria = Vec::new();
+            if let Some(target) = search.target {
+                criteria.push(SearchCriterion::reference("target", &target));
+            }
+            if search.recorded_from.is_some() || search.recorded_until.is_some() {
+                criteria.push(SearchCriterion::Date {
+                    param: "recorded",
+                    from: search.recorded_from,
+                    until: search.recorded_until,
+                });
+            }
+            return Self {
+                criteria,
+                operator: search.operator,
+                compartment: None,
+                hidden: Vec::new(),
+                count: search.count,
+                iteration_key: search.iteration_key,
+                last_id: search.last_id,
+            };
+        }
+    }
+
+    /// Token index entries for all codings of the concept.
+    fn codeable_concept_index(param: &'static str,
+                              concept: &CodeableConcept,
+    ) -> impl Iterator<Item = SearchIndex> {
+        return concept.coding
+                      .iter()
+                      .filter_map(move |coding| coding.code.as_ref().map(|code| {
+                          SearchIndex::Token {
+                              param,
+                              system: coding.system.clone(),
+                              code: code.clone(),
+                          }
+                      }));
+    }
+
+    /// Token index entry for a code from one of the model enums, as serialized by serde.
+    fn enum_index<T: Serialize>(param: &'static str, value: &T) -> Option<SearchIndex> {
+        let code = serde_json::to_value(value).ok()?.as_str()?.to_string();
+        return Some(SearchIndex::Token { param, system: None, code });
+    }
+
+    impl StoredResource for Patient {
+        const RESOURCE_TYPE: &'static str = "Patient";
+        const PATIENT_PARAM: Option<&'static str> = None;
+
+        fn search_index(&self) -> Vec<SearchIndex> {
+            let mut index = Vec::new();
+            for name in &self.name {
+                if let Some(text) = &name.text {
+                    index.push(SearchIndex::string("name", text.clone(), name.period.as_ref()));
+                }
+                if let Some(family) = &name.family {
+                    index.push(SearchIndex::string("family", family.clone(), name.period.as_ref()));
+                }
+            }
+            if let Some(gender) = &self.gender {
+                index.push(SearchIndex::Token {
+                    param: "gender",
+                    system: None,
+                    code: gender.code().to_string(),
+                });
+            }
+            if let Some(birth_date) = &self.birth_date {
+                index.push(SearchIndex::date("birthdate", birth_date));
+            }
+            for identifier in &self.identifier {
+                if let Some(value) = &identifier.value {
+                    index.push(SearchIndex::Token {
+                        param: "identifier",
+                        system: identifier.system.clone(),
+                        code: value.clone(),
+                    });
+                }
+            }
+            return index;
+        }
+
+        fn references(&self) -> Vec<(&'static str, &Reference)> {
+            let mut references = Vec::new();
+            for identifier in &self.identifier {
+                references.extend(identifier.assigner.iter().map(|r| ("identifier-assigner", &**r)));
+            }
+            for contact in &self.contact {
+                references.extend(contact.organization.iter().map(|r| ("contact-organization", r)));
+            }
+            references.extend(self.general_practitioner.iter().map(|r| ("general-practitioner", r)));
+            references.extend(self.managing_organization.iter().map(|r| ("organization", r)));
+            references.extend(self.link.iter().map(|l| ("link", &l.other)));
+            return references;
+        }
+
+        fn meta(&self) -> Option<&Meta> {
+            return self.meta.as_ref();
+        }
+    }
+
+    impl StoredResource for Observation {
+        const RESOURCE_TYPE: &'static str = "Observation";
+        const PATIENT_PARAM: Option<&'static str> = Some("subject");
+
+        fn search_index(&self) -> Vec<SearchIndex> {
+            let mut index = Vec::new();
+            index.extend(codeable_concept_index("code", &self.code));
+            for category in &self.category {
+                index.extend(codeable_concept_index("category", category));
+            }
+            index.extend(enum_index("status", &self.status));
+            for date in [&self.effective_date_time, &self.effective_instant].into_iter()
+                                                                              .flatten() {
+                index.push(SearchIndex::date("date", date));
+            }
+            index.extend(self.effective_period.iter().map(|p| SearchIndex::period("date", p)));
+            return index;
+        }
+
+        fn references(&self) -> Vec<(&'static str, &Reference)> {
+            let mut references = Vec::new();
+            references.extend(self.subject.iter().map(|r| ("subject", r)));
+            references.extend(self.encounter.iter().map(|r| ("encounter", r)));
+            references.extend(self.performer.iter().map(|r| ("performer", r)));
+            return references;
+        }
+
+        fn meta(&self) -> Option<&Meta> {
+            return self.meta.as_ref();
+        }
+    }
+
+    impl StoredResource for Encounter {
+        const RESOURCE_TYPE: &'static str = "Encounter";
+        const PATIENT_PARAM: Option<&'static str> = Some("subject");
+
+        fn search_index(&self) -> Vec<SearchIndex> {
+            let mut index = Vec::new();
+            index.extend(enum_index("status", &self.status));
+            if let Some(code) = &self.class.code {
+                index.push(SearchIndex::Token {
+                    param: "class",
+                    system: self.class.system.clone(),
+                    code: code.clone(),
+                });
+            }
+            for encounter_type in &self.encounter_type {
+                index.extend(codeable_concept_index("type", encounter_type));
+            }
+            index.extend(self.period.iter().map(|p| SearchIndex::period("date", p)));
+            return index;
+        }
+
+        fn references(&self) -> Vec<(&'static str, &Reference)> {
+            let mut references = Vec::new();
+            references.extend(self.subject.iter().map(|r| ("subject", r)));
+            references.extend(self.participant
+                                  .iter()
+                                  .filter_map(|p| p.individual.as_ref())
+                                  .map(|r| ("participant", r)));
+            references.extend(self.service_provider.iter().map(|r| ("service-provider", r)));
+            return references;
+        }
+
+        fn meta(&self) -> Option<&Meta> {
+            return self.meta.as_ref();
+        }
+    }
+
+    impl StoredResource for Provenance {
+        const RESOURCE_TYPE: &'static str = "Provenance";
+        const PATIENT_PARAM: Option<&'static str> = Some("target");
+
+        fn search_index(&self) -> Vec<SearchIndex> {
+            let mut index = vec![SearchIndex::date("recorded", &self.recorded)];
+            if let Some(activity) = &self.activity {
+                index.extend(codeable_concept_index("activity", activity));
+            }
+            return index;
+        }
+
+        fn references(&self) -> Vec<(&'static str, &Reference)> {
+            let mut references = Vec::new();
+            references.extend(self.target.iter().map(|r| ("target", r)));
+            references.extend(self.agent.iter().map(|a| ("agent", &a.who)));
+            references.extend(self.entity.iter().map(|e| ("entity", &e.what)));
+            return references;
+        }
+
+        fn meta(&self) -> Option<&Meta> {
+            return self.meta.as_ref();
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::model::model::Gender::Female;
+        use crate::model::model::SearchOperator::Or;
+        use crate::model::model::{HumanName, Link, LinkType};
+        use speculoos::assert_that;
+        use speculoos::prelude::{BooleanAssertions, OptionAssertions, VecAssertions};
+
+        fn reference(reference: &str) -> Reference {
+            return Reference {
+                id: None,
+                extension: Vec::new(),
+                reference: Some(reference.to_string()),
+                ref_type: None,
+                identifier: None,
+                display: None,
+            };
+        }
+
+        #[test]
+        fn test_reference_index() {
+            assert_that!(SearchIndex::reference("subject", &reference("Patient/123")))
+                .is_equal_to(Some(SearchIndex::Reference {
+                    param: "subject",
+                    target_type: "Patient".to_string(),
+                    target_id: "123".to_string(),
+                }));
+            assert_that!(SearchIndex::reference("target", &reference("Patient/123/_history/2")))
+                .is_equal_to(Some(SearchIndex::Reference {
+                    param: "target",
+                    target_type: "Patient".to_string(),
+                    target_id: "123".to_string(),
+                }));
+            assert_that!(SearchIndex::reference("subject", &reference("gp"))).is_none();
+            assert_that!(SearchIndex::reference("subject", &reference("http://x/Patient/1")))
+                .is_none();
+        }
+
+        #[test]
+        fn test_string_index_ignores_partial_periods() {
+            let index = SearchIndex::string("name", "A".to_string(), Some(&Period {
+                start: Some("2015".to_string()),
+                end: Some("2016-01-01T00:00:00+01:00".to_string()),
+            }));
+            assert_that!(index).is_equal_to(SearchIndex::String {
+                param: "name",
+                value: "A".to_string(),
+                period_start: None,
+                period_end: Some("2016-01-01T00:00:00+01:00".to_string()),
+            });
+        }
+
+        #[test]
+        fn test_patient_search_index() {
+            let mut patient: Patient = serde_json::from_str("{}").unwrap();
+            patient.name = vec![HumanName {
+                id: None,
+                extension: Vec::new(),
+                human_name_use: None,
+                text: Some("A Meier".to_string()),
+                family: None,
+                given: Vec::new(),
+                prefix: Vec::new(),
+                suffix: Vec::new(),
+                period: None,
+            }];
+            patient.gender = Some(Female);
+            patient.birth_date = Some("1992".to_string());
+            patient.link = vec![Link { other: reference("Patient/1"), link_type: LinkType::Seealso }];
+
+            let index = patient.search_index();
+
+            assert_that!(index).has_length(3);
+            assert_that!(patient.references()).has_length(1);
+            assert_that!(index[1]).is_equal_to(SearchIndex::Token {
+                param: "gender",
+                system: None,
+                code: "FEMALE".to_string(),
+            });
+        }
+
+        #[test]
+        fn test_patient_search_criteria() {
+            let search: ResourceSearch = serde_json::from_str::<PatientSearch>(
+                r#"{"name": "Meier", "birthdateUntil": "1993", "operator": "OR"}"#
+            ).map(ResourceSearch::from).unwrap();
+
+            assert_that!(search.criteria).is_equal_to(vec![
+                SearchCriterion::String { param: "name", value: "Meier".to_string() },
+                SearchCriterion::Date {
+                    param: "birthdate",
+                    from: None,
+                    until: Some("1993".to_string()),
+                },
+            ]);
+            assert_that!(matches!(search.operator, Or)).is_true();
+            assert_that!(search.count).is_equal_to(30);
+        }
+    }
+}
@@ -7,12 +7,19 @@ use crate::model::model::{
     Communication,
     Contact,
     ContactPoint,
+    Encounter,
+    EncounterParticipant,
     Extension,
     HumanName,
     Identifier,
     Meta,
     Narrative,
+    Observation,
     Patient,
+    Provenance,
+    ProvenanceAgent,
+    ProvenanceEntity,
+    Quantity,
     Reference,
     Resource,
 };
@@ -30,7 +37,7 @@ impl SetId for Patient {
                   db: &'a Db,
     ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
         return async move {
-            if self.id == None {
+            if self.id.is_none() {
                 self.id = Some(db.get_id().await?)
             }
             if let Some(meta) = &mut self.meta {
@@ -83,12 +90,149 @@ impl SetId for Patient {
     }
 }
 
+impl SetId for Observation {
+    fn set_id<'a>(&'a mut self,
+                  db: &'a Db,
+    ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
+        return async move {
+            if self.id.is_none() {
+                self.id = Some(db.get_id().await?)
+            }
+            if let Some(meta) = &mut self.meta {
+                meta.set_id(db).await?;
+            }
+            if let Some(text) = &mut self.text {
+                text.set_id(db).await?;
+            }
+            for e in &mut self.contained {
+                e.set_id(db).await?;
+            }
+            for e in &mut self.extension {
+                e.set_id(db).await?;
+            }
+            for e in &mut self.modifier_extension {
+                e.set_id(db).await?;
+            }
+            for e in &mut self.identifier {
+                e.set_id(db).await?;
+            }
+            for e in &mut self.category {
+                e.set_id(db).await?;
+            }
+            self.code.set_id(db).await?;
+            if let Some(subject) = &mut self.subject {
+                subject.set_id(db).await?;
+            }
+            if let Some(encounter) = &mut self.encounter {
+                encounter.set_id(db).await?;
+            }
+            for e in &mut self.performer {
+                e.set_id(db).await?;
+            }
+            if let Some(value_quantity) = &mut self.value_quantity {
+                value_quantity.set_id(db).await?;
+            }
+            if let Some(value_codeable_concept) = &mut self.value_codeable_concept {
+                value_codeable_concept.set_id(db).await?;
+            }
+            for e in &mut self.interpretation {
+                e.set_id(db).await?;
+            }
+            return Ok(());
+        }.boxed();
+    }
+}
+
+impl SetId for Encounter {
+    fn set_id<'a>(&'a mut self,
+                  db: &'a Db,
+    ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
+        return async move {
+            if self.id.is_none() {
+                self.id = Some(db.get_id().await?)
+            }
+            if let Some(meta) = &mut self.meta {
+                meta.set_id(db).await?;
+            }
+            if let Some(text) = &mut self.text {
+                text.set_id(db).await?;
+            }
+            for e in &mut self.contained {
+                e.set_id(db).await?;
+            }
+            for e in &mut self.extension {
+                e.set_id(db).await?;
+            }
+            for e in &mut self.modifier_extension {
+                e.set_id(db).await?;
+            }
+            for e in &mut self.identifier {
+                e.set_id(db).await?;
+            }
+            self.class.set_id(db).await?;
+            for e in &mut self.encounter_type {
+                e.set_id(db).await?;
+            }
+            if let Some(subject) = &mut self.subject {
+                subject.set_id(db).await?;
+            }
+            for e in &mut self.participant {
+                e.set_id(db).await?;
+            }
+            for e in &mut self.reason_code {
+                e.set_id(db).await?;
+            }
+            if let Some(service_provider) = &mut self.service_provider {
+                service_provider.set_id(db).await?;
+            }
+            return Ok(());
+        }.boxed();
+    }
+}
+
+impl SetId for Provenance {
+    fn set_id<'a>(&'a mut self,
+                  db: &'a Db,
+    ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
+        return async move {
+            if self.id.is_none() {
+                self.id = Some(db.get_id().await?)
+            }
+            if let Some(meta) = &mut self.meta {
+                meta.set_id(db).await?;
+            }
+            if let Some(text) = &mut self.text {
+                text.set_id(db).await?;
+            }
+            for e in &mut self.extension {
+                e.set_id(db).await?;
+            }
+            for e in &mut self.target {
+                e.set_id(db).await?;
+            }
+            for e in &mut self.reason {
+                e.set_id(db).await?;
+            }
+            if let Some(activity) = &mut self.activity {
+                activity.set_id(db).await?;
+            }
+            for e in &mut self.agent {
+                e.set_id(db).await?;
+            }
+            for e in &mut self.entity {
+                e.set_id(db).await?;
+            }
+            return Ok(());
+        }.boxed();
+    }
+}
+
 impl SetId for Meta {
     fn set_id<'a>(&'a mut self,
                   db: &'a Db,
     ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
         return async move {
-            if self.id == None {
+            if self.id.is_none() {
                 self.id = Some(db.get_id().await?)
             }
             for e in &mut self.extension {
@@ -110,7 +254,7 @@ impl SetId for Narrative {
                   db: &'a Db,
     ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
         return async move {
-            if self.id == None {
+            if self.id.is_none() {
                 self.id = Some(db.get_id().await?)
             }
             for e in &mut self.extension {
@@ -126,7 +270,7 @@ impl SetId for Resource {
                   db: &'a Db,
     ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
         return async move {
-            if self.id == None {
+            if self.id.is_none() {
                 self.id = Some(db.get_id().await?)
             }
             if let Some(meta) = &mut self.meta {
@@ -142,7 +286,7 @@ impl SetId for HumanName {
                   db: &'a Db,
     ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
         return async move {
-            if self.id == None {
+            if self.id.is_none() {
                 self.id = Some(db.get_id().await?)
             }
             for e in &mut self.extension {
@@ -158,7 +302,7 @@ impl SetId for ContactPoint {
                   db: &'a Db,
     ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
         return async move {
-            if self.id == None {
+            if self.id.is_none() {
                 self.id = Some(db.get_id().await?)
             }
             for e in &mut self.extension {
@@ -174,7 +318,7 @@ impl SetId for Address {
                   db: &'a Db,
     ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
         return async move {
-            if self.id == None {
+            if self.id.is_none() {
                 self.id = Some(db.get_id().await?)
             }
             for e in &mut self.extension {
@@ -190,7 +334,7 @@ impl SetId for Attachment {
                   db: &'a Db,
     ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
         return async move {
-            if self.id == None {
+            if self.id.is_none() {
                 self.id = Some(db.get_id().await?)
             }
             for e in &mut self.extension {
@@ -206,7 +350,7 @@ impl SetId for Contact {
                   db: &'a Db,
     ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
         return async move {
-            if self.id == None {
+            if self.id.is_none() {
                 self.id = Some(db.get_id().await?)
             }
             for e in &mut self.extension {
@@ -240,7 +384,7 @@ impl SetId for Communication {
                   db: &'a Db,
     ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
         return async move {
-            if self.id == None {
+            if self.id.is_none() {
                 self.id = Some(db.get_id().await?)
             }
             for e in &mut self.extension {
@@ -254,12 +398,71 @@ impl SetId for Communication {
     }
 }
 
+impl SetId for EncounterParticipant {
+    fn set_id<'a>(&'a mut self,
+                  db: &'a Db,
+    ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
+        return async move {
+            if self.id.is_none() {
+                self.id = Some(db.get_id().await?)
+            }
+            for e in &mut self.extension {
+                e.set_id(db).await?;
+            }
+            for e in &mut self.modifier_extension {
+                e.set_id(db).await?;
+            }
+            for e in &mut self.participant_type {
+                e.set_id(db).await?;
+            }
+            if let Some(individual) = &mut self.individual {
+                individual.set_id(db).await?;
+            }
+            return Ok(());
+        }.boxed();
+    }
+}
+
+impl SetId for ProvenanceAgent {
+    fn set_id<'a>(&'a mut self,
+                  db: &'a Db,
+    ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
+        return async move {
+            if self.id.is_none() {
+                self.id = Some(db.get_id().await?)
+            }
+            if let Some(agent_type) = &mut self.agent_type {
+                agent_type.set_id(db).await?;
+            }
+            self.who.set_id(db).await?;
+            if let Some(on_behalf_of) = &mut self.on_behalf_of {
+                on_behalf_of.set_id(db).await?;
+            }
+            return Ok(());
+        }.boxed();
+    }
+}
+
+impl SetId for ProvenanceEntity {
+    fn set_id<'a>(&'a mut self,
+                  db: &'a Db,
+    ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
+        return async move {
+            if self.id.is_none() {
+                self.id = Some(db.get_id().await?)
+            }
+            self.what.set_id(db).await?;
+            return Ok(());
+        }.boxed();
+    }
+}
+
 impl SetId for Extension {
     fn set_id<'a>(&'a mut self,
                   db: &'a Db,
     ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
         return async move {
-            if self.id == None {
+            if self.id.is_none() {
                 self.id = Some(db.get_id().await?)
             }
             for e in &mut self.extension {
@@ -275,7 +478,7 @@ impl SetId for Coding {
                   db: &'a Db,
     ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
         return async move {
-            if self.id == None {
+            if self.id.is_none() {
                 self.id = Some(db.get_id().await?)
             }
             for e in &mut self.extension {
@@ -291,7 +494,7 @@ impl SetId for CodeableConcept {
                   db: &'a Db,
     ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
         return async move {
-            if self.id == None {
+            if self.id.is_none() {
                 self.id = Some(db.get_id().await?)
             }
             for e in &mut self.extension {
@@ -310,7 +513,7 @@ impl SetId for Identifier {
                   db: &'a Db,
     ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
         return async move {
-            if self.id == None {
+            if self.id.is_none() {
                 self.id = Some(db.get_id().await?)
             }
             for e in &mut self.extension {
@@ -332,7 +535,7 @@ impl SetId for Reference {
                   db: &'a Db,
     ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
         return async move {
-            if self.id == None {
+            if self.id.is_none() {
                 self.id = Some(db.get_id().await?)
             }
             for e in &mut self.extension {
@@ -345,3 +548,19 @@ impl SetId for Reference {
         }.boxed();
     }
 }
+
+impl SetId for Quantity {
+    fn set_id<'a>(&'a mut self,
+                  db: &'a Db,
+    ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
+        return async move {
+            if self.id.is_none() {
+                self.id = Some(db.get_id().await?)
+            }
+            for e in &mut self.extension {
+                e.set_id(db).await?;
+            }
+            return Ok(());
+        }.boxed();
+    }
+}
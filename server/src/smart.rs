@@ -0,0 +1,256 @@
+pub mod smart {
+    use crate::resource::resource::{compartment_criterion, in_compartment, SearchCriterion, StoredResource};
+    use std::str::FromStr;
+
+    /// FHIR interactions as named by SMART v2 scopes.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Interaction {
+        Create,
+        Read,
+        Update,
+        Delete,
+        Search,
+    }
+
+    impl Interaction {
+        /// All interactions, in the order of the SMART v2 permission letters `cruds`.
+        const ALL: [Interaction; 5] = [
+            Interaction::Create,
+            Interaction::Read,
+            Interaction::Update,
+            Interaction::Delete,
+            Interaction::Search,
+        ];
+
+        /// Name of the interaction in the audit trail.
+        pub fn code(&self) -> &'static str {
+            return match self {
+                Interaction::Create => "create",
+                Interaction::Read => "read",
+                Interaction::Update => "update",
+                Interaction::Delete => "delete",
+                Interaction::Search => "search",
+            };
+        }
+
+        pub fn from_code(code: &str) -> Option<Self> {
+            return Interaction::ALL.into_iter().find(|i| i.code() == code);
+        }
+
+        fn letter(&self) -> char {
+            return match self {
+                Interaction::Create => 'c',
+                Interaction::Read => 'r',
+                Interaction::Update => 'u',
+                Interaction::Delete => 'd',
+                Interaction::Search => 's',
+            };
+        }
+    }
+
+    /// Whose resources a scope is about.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Context {
+        /// The patient the token was issued for.
+        Patient,
+        /// Everything the user may see. There are no user permissions on this server, so
+        /// this is the same as [Context::System].
+        User,
+        System,
+    }
+
+    /// A SMART on FHIR resource scope, e.g. `patient/Observation.read` (v1) or
+    /// `user/*.cruds` (v2).
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Scope {
+        context: Context,
+        /// The resource type, or `*` for all types.
+        resource_type: String,
+        interactions: Vec<Interaction>,
+    }
+
+    impl FromStr for Scope {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let (context, rest) = s.split_once('/').ok_or("Not a resource scope")?;
+            let context = match context {
+                "patient" => Context::Patient,
+                "user" => Context::User,
+                "system" => Context::System,
+                c => return Err(format!("Unknown context {}", c)),
+            };
+            let (resource_type, permissions) = rest.split_once('.').ok_or("No permissions")?;
+            let valid_type = resource_type == "*"
+                || (resource_type.starts_with(|c: char| c.is_ascii_uppercase())
+                && resource_type.chars().all(|c| c.is_ascii_alphanumeric()));
+            if !valid_type {
+                return Err(format!("Invalid resource type {}", resource_type));
+            }
+            let interactions = match permissions {
+                // v1
+                "read" => vec![Interaction::Read, Interaction::Search],
+                "write" => vec![Interaction::Create, Interaction::Update, Interaction::Delete],
+                "*" => Interaction::ALL.to_vec(),
+                // v2, constraints like `?category=...` aren't supported, so they grant nothing
+                _ => {
+                    let mut letters = permissions.chars().peekable();
+                    let interactions: Vec<Interaction> = Interaction::ALL
+                        .into_iter()
+                        .filter(|i| letters.next_if_eq(&i.letter()).is_some())
+                        .collect();
+                    if interactions.is_empty() || letters.next().is_some() {
+                        return Err(format!("Invalid permissions {}", permissions));
+                    }
+                    interactions
+                }
+            };
+            return Ok(Self { context, resource_type: resource_type.to_string(), interactions });
+        }
+    }
+
+    impl Scope {
+        fn grants(&self, resource_type: &str, interaction: Interaction) -> bool {
+            return (self.resource_type == "*" || self.resource_type == resource_type)
+                && self.interactions.contains(&interaction);
+        }
+    }
+
+    /// Which resources of a type a request may touch, as granted by the scopes of the caller.
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub enum Access {
+        All,
+        /// Only resources in the compartment of the patient with this ID.
+        Patient(String),
+    }
+
+    impl Access {
+        /// Access the scopes grant for the interaction on the resource type. Patient scopes
+        /// only grant access to the compartment of the patient in context, and nothing without
+        /// one. Scopes that aren't SMART resource scopes are ignored. None if nothing is granted.
+        pub fn granted(scopes: &[String],
+                       patient: Option<&str>,
+                       resource_type: &str,
+                       interaction: Interaction) -> Option<Self> {
+            let granting: Vec<Scope> = scopes.iter()
+                                             .filter_map(|s| s.parse::<Scope>().ok())
+                                             .filter(|s| s.grants(resource_type, interaction))
+                                             .collect();
+            if granting.iter().any(|s| s.context != Context::Patient) {
+                return Some(Access::All);
+            }
+            if granting.iter().any(|s| s.context == Context::Patient) {
+                return patient.map(|p| Access::Patient(p.to_string()));
+            }
+            return None;
+        }
+
+        /// Criterion restricting searches to what may be accessed, None if everything may.
+        pub fn compartment<R: StoredResource>(&self) -> Option<SearchCriterion> {
+            return match self {
+                Access::All => None,
+                Access::Patient(patient_id) => Some(compartment_criterion::<R>(patient_id)),
+            };
+        }
+
+        /// Whether the resource with the ID may be accessed.
+        pub fn allows<R: StoredResource>(&self, id: Option<&str>, resource: &R) -> bool {
+            return match self {
+                Access::All => true,
+                Access::Patient(patient_id) => in_compartment(id, resource, patient_id),
+            };
+        }
+
+        /// Short form for cache keys and logs.
+        pub fn code(&self) -> String {
+            return match self {
+                Access::All => "all".to_string(),
+                Access::Patient(patient_id) => format!("patient:{}", patient_id),
+            };
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::model::model::{Observation, Patient};
+        use speculoos::assert_that;
+        use speculoos::prelude::ResultAssertions;
+
+        fn scopes(scopes: &[&str]) -> Vec<String> {
+            return scopes.iter().map(|s| s.to_string()).collect();
+        }
+
+        #[test]
+        fn test_parse_scopes() {
+            assert_that!("patient/Patient.read".parse::<Scope>()).is_ok().is_equal_to(Scope {
+                context: Context::Patient,
+                resource_type: "Patient".to_string(),
+                interactions: vec![Interaction::Read, Interaction::Search],
+            });
+            assert_that!("system/*.rs".parse::<Scope>()).is_ok().is_equal_to(Scope {
+                context: Context::System,
+                resource_type: "*".to_string(),
+                interactions: vec![Interaction::Read, Interaction::Search],
+            });
+            assert_that!("user/Observation.cud".parse::<Scope>().map(|s| s.interactions))
+                .is_ok()
+                .is_equal_to(vec![Interaction::Create, Interaction::Update, Interaction::Delete]);
+            assert_that!("user/*.*".parse::<Scope>().map(|s| s.interactions))
+                .is_ok()
+                .is_equal_to(Interaction::ALL.to_vec());
+
+            // letters out of order, repeated or unknown
+            assert_that!("user/Patient.sr".parse::<Scope>()).is_err();
+            assert_that!("user/Patient.rr".parse::<Scope>()).is_err();
+            assert_that!("user/Patient.rx".parse::<Scope>()).is_err();
+            assert_that!("patient/Observation.rs?category=laboratory".parse::<Scope>()).is_err();
+            assert_that!("openid".parse::<Scope>()).is_err();
+            assert_that!("launch/patient".parse::<Scope>()).is_err();
+        }
+
+        #[test]
+        fn test_granted_access() {
+            let patient = scopes(&["openid", "patient/Patient.read", "patient/Observation.rs"]);
+            assert_that!(Access::granted(&patient, Some("p1"), "Patient", Interaction::Read))
+                .is_equal_to(Some(Access::Patient("p1".to_string())));
+            assert_that!(Access::granted(&patient, Some("p1"), "Patient", Interaction::Update))
+                .is_equal_to(None);
+            assert_that!(Access::granted(&patient, Some("p1"), "Encounter", Interaction::Read))
+                .is_equal_to(None);
+            // patient scopes need a patient in context
+            assert_that!(Access::granted(&patient, None, "Patient", Interaction::Read))
+                .is_equal_to(None);
+
+            let user = scopes(&["patient/*.read", "user/Patient.write"]);
+            assert_that!(Access::granted(&user, Some("p1"), "Patient", Interaction::Update))
+                .is_equal_to(Some(Access::All));
+            assert_that!(Access::granted(&user, Some("p1"), "Patient", Interaction::Read))
+                .is_equal_to(Some(Access::Patient("p1".to_string())));
+            assert_that!(Access::granted(&scopes(&["system/*.rs"]), None, "Encounter", Interaction::Search))
+                .is_equal_to(Some(Access::All));
+        }
+
+        #[test]
+        fn test_compartment() {
+            let access = Access::Patient("p1".to_string());
+            let mut observation: Observation = serde_json::from_value(serde_json::json!({
+                "status": "FINAL",
+                "code": {"text": "Weight"},
+                "subject": {"reference": "Patient/p1"},
+            })).unwrap();
+            let patient: Patient = serde_json::from_str("{}").unwrap();
+
+            assert_that!(access.allows(Some("o1"), &observation)).is_equal_to(true);
+            assert_that!(access.allows(Some("p1"), &patient)).is_equal_to(true);
+            assert_that!(access.allows(Some("p2"), &patient)).is_equal_to(false);
+            assert_that!(access.allows(None, &patient)).is_equal_to(false);
+            assert_that!(Access::All.allows(Some("p2"), &patient)).is_equal_to(true);
+
+            observation.subject.as_mut().unwrap().reference = Some("Patient/p2".to_string());
+            assert_that!(access.allows(Some("o1"), &observation)).is_equal_to(false);
+            assert_that!(access.compartment::<Patient>())
+                .is_equal_to(Some(SearchCriterion::Id { id: "p1".to_string() }));
+        }
+    }
+}
@@ -0,0 +1,594 @@
+pub mod tls {
+    use crate::auth::auth::Identity;
+    use axum::extract::ConnectInfo;
+    use axum::Router;
+    use axum_core::body::Body;
+    use http::Request;
+    use hyper::body::Incoming;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper_util::rt::{TokioIo, TokioTimer};
+    use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+    use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+    use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+    use rustls::sign::CertifiedKey;
+    use rustls::{
+        ClientConfig,
+        DigitallySignedStruct,
+        RootCertStore,
+        ServerConfig,
+        SignatureScheme,
+    };
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use std::net::SocketAddr;
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex, RwLock};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+    use tokio_postgres_rustls::MakeRustlsConnect;
+    use tokio_rustls::TlsAcceptor;
+    use tower::ServiceExt;
+    use tracing::{error, info};
+    use x509_parser::parse_x509_certificate;
+
+    /// Default time between checks whether the certificate files changed.
+    const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+    /// Clients that don't finish the handshake in time are dropped, so that they can't hold
+    /// connections open.
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// The identity of a system client that authenticated with its TLS client certificate.
+    /// Added to the requests of its connection, see [crate::auth::auth::Auth::auth_middleware].
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct ClientCertificate(pub Identity);
+
+    /// All certificates in a PEM file.
+    fn read_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+        let pem = fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let certs = CertificateDer::pem_slice_iter(&pem)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid certificate in {}: {}", path.display(), e))?;
+        if certs.is_empty() {
+            return Err(format!("No certificate in {}", path.display()));
+        }
+        return Ok(certs);
+    }
+
+    fn root_store(path: &Path) -> Result<RootCertStore, String> {
+        let mut roots = RootCertStore::empty();
+        for cert in read_certificates(path)? {
+            roots.add(cert).map_err(|e| format!("Invalid CA in {}: {}", path.display(), e))?;
+        }
+        return Ok(roots);
+    }
+
+    /// The certificate chain and private key in the PEM files.
+    fn certified_key(cert: &[u8], key: &[u8]) -> Result<CertifiedKey, String> {
+        let certs = CertificateDer::pem_slice_iter(cert)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid certificate: {}", e))?;
+        if certs.is_empty() {
+            return Err("No certificate found".to_string());
+        }
+        let key = PrivateKeyDer::from_pem_slice(key)
+            .map_err(|e| format!("Invalid private key: {}", e))?;
+        let signing_key = rustls::crypto::ring::sign::any_supported_type(&key)
+            .map_err(|e| format!("Unsupported private key: {}", e))?;
+        let certified = CertifiedKey::new(certs, signing_key);
+        certified.keys_match().map_err(|e| format!("Key doesn't match the certificate: {}", e))?;
+        return Ok(certified);
+    }
+
+    /// Serves the certificate in the files and swaps it when they change, so that renewed
+    /// certificates are picked up without a restart.
+    #[derive(Debug)]
+    pub struct ReloadingCertificate {
+        cert_path: PathBuf,
+        key_path: PathBuf,
+        current: RwLock<Arc<CertifiedKey>>,
+        /// Contents of the files the current certificate was read from.
+        loaded: Mutex<(Vec<u8>, Vec<u8>)>,
+    }
+
+    impl ReloadingCertificate {
+        pub fn load(cert_path: &Path, key_path: &Path) -> Result<Self, String> {
+            let cert = fs::read(cert_path)
+                .map_err(|e| format!("Could not read {}: {}", cert_path.display(), e))?;
+            let key = fs::read(key_path)
+                .map_err(|e| format!("Could not read {}: {}", key_path.display(), e))?;
+            let certified = certified_key(&cert, &key)?;
+            return Ok(Self {
+                cert_path: cert_path.to_path_buf(),
+                key_path: key_path.to_path_buf(),
+                current: RwLock::new(Arc::new(certified)),
+                loaded: Mutex::new((cert, key)),
+            });
+        }
+
+        /// Reads the files again and swaps the certificate if they changed. If the new files
+        /// can't be used, e.g. because only one of them was replaced yet, the old certificate
+        /// is kept.
+        pub fn reload(&self) -> bool {
+            let (Ok(cert), Ok(key)) = (fs::read(&self.cert_path), fs::read(&self.key_path)) else {
+                error!(cert = ?self.cert_path,
+                       key = ?self.key_path,
+                       "Could not read TLS certificate");
+                return false;
+            };
+            let mut loaded = self.loaded.lock().unwrap();
+            if loaded.0 == cert && loaded.1 == key {
+                return false;
+            }
+            return match certified_key(&cert, &key) {
+                Ok(certified) => {
+                    *self.current.write().unwrap() = Arc::new(certified);
+                    *loaded = (cert, key);
+                    info!(cert = ?self.cert_path, "Reloaded TLS certificate");
+                    true
+                }
+                Err(e) => {
+                    error!(?e, "Could not reload TLS certificate, keeping the old one");
+                    false
+                }
+            };
+        }
+    }
+
+    impl ResolvesServerCert for ReloadingCertificate {
+        fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+            return Some(self.current.read().unwrap().clone());
+        }
+    }
+
+    /// `lab-system=system/Observation.cruds system/Patient.rs,billing=system/*.rs`, the scopes
+    /// of system clients by the common name of their certificate.
+    fn parse_clients(s: &str) -> Result<HashMap<String, Identity>, String> {
+        return s.split(',')
+                .filter(|c| !c.trim().is_empty())
+                .map(|c| {
+                    let (name, scopes) = c.split_once('=')
+                                          .filter(|(name, _)| !name.trim().is_empty())
+                                          .ok_or(format!("Invalid client: {}", c))?;
+                    let name = name.trim().to_string();
+                    return Ok((name.clone(), Identity {
+                        subject: name.clone(),
+                        client_id: Some(name),
+                        scopes: scopes.split_whitespace().map(str::to_string).collect(),
+                        patient: None,
+                        roles: Vec::new(),
+                    }));
+                })
+                .collect();
+    }
+
+    /// HTTPS for the listener, optionally with client certificates.
+    #[derive(Clone)]
+    pub struct Tls {
+        certificate: Arc<ReloadingCertificate>,
+        config: Arc<ServerConfig>,
+        /// Identities of system clients by the common name of their certificate.
+        clients: Arc<HashMap<String, Identity>>,
+        reload_interval: Duration,
+    }
+
+    impl Tls {
+        /// Client certificates are asked for if there is a CA for them, but not required, so
+        /// that other clients can still use tokens.
+        pub fn new(certificate: ReloadingCertificate,
+                   client_ca: Option<&Path>,
+                   clients: HashMap<String, Identity>,
+        ) -> Result<Self, String> {
+            let certificate = Arc::new(certificate);
+            let builder = ServerConfig::builder();
+            let builder = match client_ca {
+                Some(path) => {
+                    let verifier = WebPkiClientVerifier::builder(Arc::new(root_store(path)?))
+                        .allow_unauthenticated()
+                        .build()
+                        .map_err(|e| format!("Invalid client CA: {}", e))?;
+                    builder.with_client_cert_verifier(verifier)
+                }
+                None => builder.with_no_client_auth(),
+            };
+            let mut config = builder.with_cert_resolver(certificate.clone());
+            config.alpn_protocols = vec![b"http/1.1".to_vec()];
+            return Ok(Self {
+                certificate,
+                config: Arc::new(config),
+                clients: Arc::new(clients),
+                reload_interval: RELOAD_INTERVAL,
+            });
+        }
+
+        /// HTTPS with the PEM files `FHIR_TLS_CERT` and `FHIR_TLS_KEY`, checked for changes
+        /// every `FHIR_TLS_RELOAD_SECONDS`. Client certificates issued by the CAs in
+        /// `FHIR_TLS_CLIENT_CA` authenticate the clients in `FHIR_TLS_CLIENTS`. None without a
+        /// certificate, the server then speaks plain HTTP.
+        pub fn from_env() -> Option<Self> {
+            let path = |name: &str| env::var_os(name).map(PathBuf::from);
+            let (cert, key) = match (path("FHIR_TLS_CERT"), path("FHIR_TLS_KEY")) {
+                (Some(cert), Some(key)) => (cert, key),
+                (None, None) => return None,
+                _ => {
+                    error!("FHIR_TLS_CERT and FHIR_TLS_KEY have to be set together");
+                    panic!("FHIR_TLS_CERT and FHIR_TLS_KEY have to be set together");
+                }
+            };
+            let clients = match env::var_os("FHIR_TLS_CLIENTS") {
+                None => HashMap::new(),
+                Some(val) => match parse_clients(&val.into_string().unwrap()) {
+                    Ok(clients) => clients,
+                    Err(e) => {
+                        error!(?e, "Invalid FHIR_TLS_CLIENTS");
+                        panic!("Invalid FHIR_TLS_CLIENTS");
+                    }
+                },
+            };
+            let tls = ReloadingCertificate::load(&cert, &key)
+                .and_then(|certificate| {
+                    Tls::new(certificate, path("FHIR_TLS_CLIENT_CA").as_deref(), clients)
+                });
+            let mut tls = match tls {
+                Ok(tls) => tls,
+                Err(e) => {
+                    error!(?e, "Invalid TLS configuration");
+                    panic!("Invalid TLS configuration");
+                }
+            };
+            if let Some(seconds) = env::var_os("FHIR_TLS_RELOAD_SECONDS") {
+                tls.reload_interval = match seconds.into_string().unwrap().parse() {
+                    Ok(seconds) => Duration::from_secs(seconds),
+                    Err(e) => {
+                        error!(?e, "Invalid FHIR_TLS_RELOAD_SECONDS");
+                        panic!("Invalid FHIR_TLS_RELOAD_SECONDS");
+                    }
+                };
+            }
+            return Some(tls);
+        }
+
+        /// The system client of a verified certificate, None for certificates of unknown
+        /// clients, which then have to use tokens like everybody else.
+        fn identify(&self, certs: Option<&[CertificateDer<'_>]>) -> Option<ClientCertificate> {
+            let (_, cert) = parse_x509_certificate(certs?.first()?.as_ref()).ok()?;
+            let name = cert.subject().iter_common_name().next()?.as_str().ok()?;
+            let identity = self.clients.get(name);
+            if identity.is_none() {
+                info!(name, "Client certificate of an unknown client");
+            }
+            return identity.cloned().map(ClientCertificate);
+        }
+
+        /// Serves the app over HTTPS, reloading the certificate in the background. The remote
+        /// address and the [ClientCertificate] are added to the requests.
+        pub async fn serve(self, listener: TcpListener, app: Router) {
+            let certificate = self.certificate.clone();
+            let mut interval = tokio::time::interval(self.reload_interval);
+            tokio::spawn(async move {
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    certificate.reload();
+                }
+            });
+            let acceptor = TlsAcceptor::from(self.config.clone());
+            loop {
+                let (stream, remote) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        error!(?e, "Could not accept connection");
+                        continue;
+                    }
+                };
+                let acceptor = acceptor.clone();
+                let tls = self.clone();
+                let app = app.clone();
+                tokio::spawn(async move {
+                    let stream = match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => stream,
+                        Ok(Err(e)) => {
+                            info!(?e, %remote, "TLS handshake failed");
+                            return;
+                        }
+                        Err(_) => {
+                            info!(%remote, "TLS handshake timed out");
+                            return;
+                        }
+                    };
+                    let client = tls.identify(stream.get_ref().1.peer_certificates());
+                    let service = service_fn(move |req: Request<Incoming>| {
+                        let mut req = req.map(Body::new);
+                        req.extensions_mut().insert(ConnectInfo::<SocketAddr>(remote));
+                        if let Some(client) = &client {
+                            req.extensions_mut().insert(client.clone());
+                        }
+                        return app.clone().oneshot(req);
+                    });
+                    if let Err(e) = http1::Builder::new().timer(TokioTimer::new())
+                                                         .serve_connection(TokioIo::new(stream),
+                                                                           service)
+                                                         .await {
+                        info!(?e, %remote, "Connection failed");
+                    }
+                });
+            }
+        }
+    }
+
+    /// How the server connects to Postgres.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum DbTls {
+        /// Plain TCP.
+        Disable,
+        /// TLS without checking the certificate of the server, which only protects against
+        /// eavesdropping.
+        Require,
+        /// TLS with the certificate checked against the CAs in the file, or those of the
+        /// system, and the host name.
+        VerifyFull(Option<PathBuf>),
+    }
+
+    impl FromStr for DbTls {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            return match s.trim() {
+                "disable" => Ok(DbTls::Disable),
+                "require" => Ok(DbTls::Require),
+                "verify-full" => Ok(DbTls::VerifyFull(None)),
+                _ => Err(format!("Unknown TLS mode, expected disable, require or verify-full: {}",
+                                 s)),
+            };
+        }
+    }
+
+    impl DbTls {
+        /// `FHIR_DB_TLS`, `disable` by default. `verify-full` checks the certificate against the
+        /// CAs in `FHIR_DB_CA_FILE`, or those of the system.
+        pub fn from_env() -> Self {
+            let tls = match env::var_os("FHIR_DB_TLS") {
+                None => DbTls::Disable,
+                Some(val) => match DbTls::from_str(&val.into_string().unwrap()) {
+                    Ok(tls) => tls,
+                    Err(e) => {
+                        error!(?e, "Invalid FHIR_DB_TLS");
+                        panic!("Invalid FHIR_DB_TLS");
+                    }
+                },
+            };
+            return match tls {
+                DbTls::VerifyFull(_) => DbTls::VerifyFull(env::var_os("FHIR_DB_CA_FILE")
+                    .map(PathBuf::from)),
+                tls => tls,
+            };
+        }
+
+        /// None for plain TCP.
+        pub fn connector(&self) -> Result<Option<MakeRustlsConnect>, String> {
+            let config = match self {
+                DbTls::Disable => return Ok(None),
+                DbTls::Require => {
+                    let provider = Arc::new(rustls::crypto::ring::default_provider());
+                    ClientConfig::builder()
+                        .dangerous()
+                        .with_custom_certificate_verifier(Arc::new(AnyCertificate(provider)))
+                        .with_no_client_auth()
+                }
+                DbTls::VerifyFull(Some(ca)) => {
+                    ClientConfig::builder().with_root_certificates(root_store(ca)?)
+                                           .with_no_client_auth()
+                }
+                DbTls::VerifyFull(None) => {
+                    let mut roots = RootCertStore::empty();
+                    let native = rustls_native_certs::load_native_certs();
+                    for e in native.errors {
+                        error!(?e, "Could not load a system CA");
+                    }
+                    roots.add_parsable_certificates(native.certs);
+                    ClientConfig::builder().with_root_certificates(roots).with_no_client_auth()
+                }
+            };
+            return Ok(Some(MakeRustlsConnect::new(config)));
+        }
+    }
+
+    /// Accepts any certificate of the server, but still checks that it signed the handshake.
+    #[derive(Debug)]
+    struct AnyCertificate(Arc<CryptoProvider>);
+
+    impl ServerCertVerifier for AnyCertificate {
+        fn verify_server_cert(&self,
+                              _end_entity: &CertificateDer<'_>,
+                              _intermediates: &[CertificateDer<'_>],
+                              _server_name: &ServerName<'_>,
+                              _ocsp_response: &[u8],
+                              _now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            return Ok(ServerCertVerified::assertion());
+        }
+
+        fn verify_tls12_signature(&self,
+                                  message: &[u8],
+                                  cert: &CertificateDer<'_>,
+                                  dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            return verify_tls12_signature(message,
+                                          cert,
+                                          dss,
+                                          &self.0.signature_verification_algorithms);
+        }
+
+        fn verify_tls13_signature(&self,
+                                  message: &[u8],
+                                  cert: &CertificateDer<'_>,
+                                  dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            return verify_tls13_signature(message,
+                                          cert,
+                                          dss,
+                                          &self.0.signature_verification_algorithms);
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            return self.0.signature_verification_algorithms.supported_schemes();
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use axum::routing::get;
+        use axum::Extension;
+        use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, Issuer, KeyPair};
+        use rustls::pki_types::ServerName;
+        use speculoos::assert_that;
+        use speculoos::prelude::ResultAssertions;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpStream;
+        use tokio_rustls::TlsConnector;
+        use uuid::Uuid;
+
+        /// A CA, and certificates with their keys issued by it, as PEM.
+        struct TestCa {
+            issuer: Issuer<'static, KeyPair>,
+            pem: String,
+        }
+
+        impl TestCa {
+            fn new(name: &str) -> Self {
+                let mut params = CertificateParams::new(Vec::new()).unwrap();
+                params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+                params.distinguished_name.push(DnType::CommonName, name);
+                let key = KeyPair::generate().unwrap();
+                let pem = params.self_signed(&key).unwrap().pem();
+                return Self { issuer: Issuer::new(params, key), pem };
+            }
+
+            /// Certificate and key for the host name, or of a client with the common name.
+            fn issue(&self, host: &str, common_name: &str) -> (String, String) {
+                let mut params = CertificateParams::new(vec![host.to_string()]).unwrap();
+                params.distinguished_name.push(DnType::CommonName, common_name);
+                let key = KeyPair::generate().unwrap();
+                let cert = params.signed_by(&key, &self.issuer).unwrap();
+                return (cert.pem(), key.serialize_pem());
+            }
+        }
+
+        fn write(dir: &Path, name: &str, pem: &str) -> PathBuf {
+            let path = dir.join(name);
+            fs::write(&path, pem).unwrap();
+            return path;
+        }
+
+        fn temp_dir() -> PathBuf {
+            let dir = env::temp_dir().join(format!("fhir-tls-{}", Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            return dir;
+        }
+
+        #[test]
+        fn test_parse() {
+            let clients = parse_clients("lab-system=system/Observation.cruds system/Patient.rs, \
+                                         billing=system/*.rs").unwrap();
+            assert_that!(clients["lab-system"].scopes.len()).is_equal_to(2);
+            assert_that!(clients["billing"].client_id.as_deref()).is_equal_to(Some("billing"));
+            assert_that!(parse_clients("=system/*.rs")).is_err();
+            assert_that!(parse_clients("billing")).is_err();
+
+            assert_that!(DbTls::from_str("verify-full"))
+                .is_ok()
+                .is_equal_to(DbTls::VerifyFull(None));
+            assert_that!(DbTls::from_str("prefer")).is_err();
+            assert_that!(DbTls::Require.connector().is_ok_and(|c| c.is_some())).is_equal_to(true);
+        }
+
+        #[test]
+        fn test_reload() {
+            let ca = TestCa::new("Test CA");
+            let dir = temp_dir();
+            let (cert, key) = ca.issue("localhost", "first");
+            let cert_path = write(&dir, "cert.pem", &cert);
+            let key_path = write(&dir, "key.pem", &key);
+            let certificate = ReloadingCertificate::load(&cert_path, &key_path).unwrap();
+            let served = || certificate.current.read().unwrap().cert[0].clone();
+            let first = served();
+            assert_that!(certificate.reload()).is_equal_to(false);
+
+            // only the certificate was replaced yet, it doesn't match the key
+            let (cert, key) = ca.issue("localhost", "second");
+            write(&dir, "cert.pem", &cert);
+            assert_that!(certificate.reload()).is_equal_to(false);
+            assert_that!(served()).is_equal_to(first.clone());
+
+            write(&dir, "key.pem", &key);
+            assert_that!(certificate.reload()).is_equal_to(true);
+            assert_that!(served()).is_not_equal_to(first);
+            fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_client_certificates() {
+            let server_ca = TestCa::new("Server CA");
+            let client_ca = TestCa::new("Client CA");
+            let dir = temp_dir();
+            let (cert, key) = server_ca.issue("localhost", "localhost");
+            let certificate = ReloadingCertificate::load(&write(&dir, "cert.pem", &cert),
+                                                         &write(&dir, "key.pem", &key)).unwrap();
+            let tls = Tls::new(certificate,
+                               Some(&write(&dir, "client-ca.pem", &client_ca.pem)),
+                               parse_clients("lab-system=system/Observation.cruds").unwrap())
+                .unwrap();
+            let app = Router::new().route("/whoami", get(
+                async |client: Option<Extension<ClientCertificate>>| match client {
+                    Some(Extension(ClientCertificate(identity))) => identity.subject,
+                    None => "anonymous".to_string(),
+                }));
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(tls.serve(listener, app));
+
+            let whoami = async |client: Option<(String, String)>| -> Result<String, String> {
+                let mut roots = RootCertStore::empty();
+                roots.add(CertificateDer::from_pem_slice(server_ca.pem.as_bytes()).unwrap())
+                     .unwrap();
+                let builder = ClientConfig::builder().with_root_certificates(roots);
+                let config = match client {
+                    Some((cert, key)) => builder.with_client_auth_cert(
+                        vec![CertificateDer::from_pem_slice(cert.as_bytes()).unwrap()],
+                        PrivateKeyDer::from_pem_slice(key.as_bytes()).unwrap()).unwrap(),
+                    None => builder.with_no_client_auth(),
+                };
+                let stream = TcpStream::connect(addr).await.unwrap();
+                let mut stream = TlsConnector::from(Arc::new(config))
+                    .connect(ServerName::try_from("localhost").unwrap(), stream)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let request = "GET /whoami HTTP/1.1\r\nHost: localhost\r\n\
+                               Connection: close\r\n\r\n";
+                stream.write_all(request.as_bytes())
+                      .await
+                      .map_err(|e| e.to_string())?;
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.map_err(|e| e.to_string())?;
+                return Ok(response);
+            };
+
+            let lab = whoami(Some(client_ca.issue("lab.example.org", "lab-system"))).await;
+            assert_that!(lab.unwrap().ends_with("lab-system")).is_equal_to(true);
+            let unknown = whoami(Some(client_ca.issue("other.example.org", "other"))).await;
+            assert_that!(unknown.unwrap().ends_with("anonymous")).is_equal_to(true);
+            let anonymous = whoami(None).await;
+            assert_that!(anonymous.unwrap().ends_with("anonymous")).is_equal_to(true);
+            // certificates of other CAs are rejected in the handshake
+            let forged = whoami(Some(server_ca.issue("lab.example.org", "lab-system"))).await;
+            assert_that!(forged.is_ok_and(|r| r.ends_with("lab-system"))).is_equal_to(false);
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+}